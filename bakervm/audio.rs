@@ -0,0 +1,478 @@
+//! The audio unit, which mixes the channels controlled by the audio
+//! instructions
+
+use core::{Waveform, CHANNEL_COUNT};
+use core::error::*;
+use core::typedef::*;
+use std::collections::BTreeMap;
+use std::io::Write;
+
+/// The rate the audio unit is rendered at in samples per second
+pub const SAMPLE_RATE: u32 = 44_100;
+
+/// The number of frames per second, that time advances by when rendering
+/// without a sound card
+pub const FRAME_RATE: u32 = 60;
+
+/// The factor the sum of the channels is scaled by, so a few loud channels
+/// don't clip right away
+const MIX_GAIN: f32 = 0.5;
+
+/// The highest frequency a channel plays at. Above it, a waveform would skip
+/// whole periods with every sample.
+pub const MAX_FREQUENCY: f32 = SAMPLE_RATE as f32;
+
+/// The state of the linear feedback shift register of a fresh noise channel
+const NOISE_SEED: u16 = 1;
+
+/// The number of shifts after which the noise register repeats itself
+const NOISE_PERIOD: u32 = 32_767;
+
+/// The commands the VM sends to the audio unit
+#[derive(Debug, Clone, PartialEq)]
+pub enum AudioCommand {
+    /// Silences all channels and replaces the samples by the ones of a new
+    /// program, which are keyed by their asset index
+    Reset(BTreeMap<Address, Sample>),
+    /// Changes what a channel plays
+    Voice(Address, Waveform),
+    /// Starts a note on a channel. Samples are played at their own rate if
+    /// the frequency is 0.
+    Tone {
+        channel: Address,
+        frequency: f32,
+        volume: f32,
+    },
+    Envelope { channel: Address, envelope: Envelope },
+    /// Releases the note of a channel
+    NoteOff(Address),
+    /// Marks that a frame was flushed
+    Frame,
+}
+
+/// The volume envelope of a channel. The times are in seconds.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Envelope {
+    pub attack: f32,
+    pub decay: f32,
+    /// The level held after the decay until the note is released
+    pub sustain: f32,
+    pub release: f32,
+}
+
+impl Default for Envelope {
+    fn default() -> Envelope {
+        Envelope {
+            attack: 0.0,
+            decay: 0.0,
+            sustain: 1.0,
+            release: 0.0,
+        }
+    }
+}
+
+/// A sound played once by a channel
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sample {
+    /// The native rate of the sample in samples per second
+    pub rate: f32,
+    /// Unsigned 8 bit samples, where 128 is silence
+    pub data: Vec<Byte>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Stage {
+    Off,
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+}
+
+#[derive(Debug, Clone)]
+struct Channel {
+    waveform: Waveform,
+    frequency: f32,
+    volume: f32,
+    envelope: Envelope,
+    stage: Stage,
+    /// The current level of the envelope between 0 and 1
+    level: f32,
+    /// The position within the current period of the oscillator
+    phase: f32,
+    noise: u16,
+    /// The position within the played sample
+    position: f32,
+}
+
+impl Channel {
+    fn new(channel: Address) -> Channel {
+        Channel {
+            waveform: Waveform::default_for(channel),
+            frequency: 0.0,
+            volume: 0.0,
+            envelope: Envelope::default(),
+            stage: Stage::Off,
+            level: 0.0,
+            phase: 0.0,
+            noise: NOISE_SEED,
+            position: 0.0,
+        }
+    }
+
+    /// Advances the envelope by one sample
+    fn advance_envelope(&mut self, step: f32) {
+        let envelope = self.envelope;
+
+        match self.stage {
+            Stage::Off => self.level = 0.0,
+            Stage::Attack => {
+                self.level = if envelope.attack > 0.0 {
+                    self.level + step / envelope.attack
+                } else {
+                    1.0
+                };
+
+                if self.level >= 1.0 {
+                    self.level = 1.0;
+                    self.stage = Stage::Decay;
+                }
+            }
+            Stage::Decay => {
+                self.level = if envelope.decay > 0.0 {
+                    self.level - step * (1.0 - envelope.sustain) / envelope.decay
+                } else {
+                    envelope.sustain
+                };
+
+                if self.level <= envelope.sustain {
+                    self.level = envelope.sustain;
+                    self.stage = Stage::Sustain;
+                }
+            }
+            Stage::Sustain => self.level = envelope.sustain,
+            Stage::Release => {
+                self.level = if envelope.release > 0.0 {
+                    self.level - step / envelope.release
+                } else {
+                    0.0
+                };
+
+                if self.level <= 0.0 {
+                    self.level = 0.0;
+                    self.stage = Stage::Off;
+                }
+            }
+        }
+    }
+
+    /// Renders the next sample of the channel
+    fn next(&mut self, samples: &BTreeMap<Address, Sample>, sample_rate: f32) -> f32 {
+        if self.stage == Stage::Off {
+            return 0.0;
+        }
+
+        self.advance_envelope(1.0 / sample_rate);
+
+        let step = self.frequency / sample_rate;
+
+        let output = match self.waveform {
+            Waveform::Square => {
+                let output = if self.phase < 0.5 { 1.0 } else { -1.0 };
+                self.phase = (self.phase + step).fract();
+                output
+            }
+            Waveform::Triangle => {
+                let output = 4.0 * (self.phase - 0.5).abs() - 1.0;
+                self.phase = (self.phase + step).fract();
+                output
+            }
+            Waveform::Noise => {
+                let output = if self.noise & 1 == 1 { 1.0 } else { -1.0 };
+
+                self.phase += step;
+
+                // The register is shifted once per period, but repeats itself
+                // after a full cycle
+                let shifts = (self.phase as u32).min(NOISE_PERIOD);
+                self.phase = self.phase.fract();
+
+                for _ in 0..shifts {
+                    let bit = (self.noise ^ self.noise >> 1) & 1;
+                    self.noise = self.noise >> 1 | bit << 14;
+                }
+
+                output
+            }
+            Waveform::Sample(asset) => {
+                let position = self.position as usize;
+                let sample = samples
+                    .get(&asset)
+                    .and_then(|sample| sample.data.get(position).map(|&byte| (sample, byte)));
+
+                match sample {
+                    Some((sample, byte)) => {
+                        let rate = if self.frequency > 0.0 {
+                            self.frequency
+                        } else {
+                            sample.rate
+                        };
+
+                        self.position += rate / sample_rate;
+
+                        (f32::from(byte) - 128.0) / 128.0
+                    }
+                    None => {
+                        // Samples are played once
+                        self.stage = Stage::Off;
+                        0.0
+                    }
+                }
+            }
+        };
+
+        output * self.volume * self.level
+    }
+}
+
+/// Mixes the channels into a mono signal
+#[derive(Debug)]
+pub struct AudioUnit {
+    sample_rate: f32,
+    channels: Vec<Channel>,
+    samples: BTreeMap<Address, Sample>,
+}
+
+impl AudioUnit {
+    pub fn new(sample_rate: u32) -> AudioUnit {
+        AudioUnit {
+            sample_rate: sample_rate as f32,
+            channels: (0..CHANNEL_COUNT).map(Channel::new).collect(),
+            samples: BTreeMap::new(),
+        }
+    }
+
+    /// Applies a command of the VM. Commands for channels that don't exist
+    /// are ignored.
+    pub fn apply(&mut self, command: &AudioCommand) {
+        match *command {
+            AudioCommand::Reset(ref samples) => {
+                self.channels = (0..CHANNEL_COUNT).map(Channel::new).collect();
+                self.samples = samples.clone();
+            }
+            AudioCommand::Voice(channel, waveform) => {
+                if let Some(channel) = self.channels.get_mut(channel) {
+                    channel.waveform = waveform;
+                }
+            }
+            AudioCommand::Tone {
+                channel,
+                frequency,
+                volume,
+            } => if let Some(channel) = self.channels.get_mut(channel) {
+                channel.frequency = if frequency.is_finite() {
+                    frequency.clamp(0.0, MAX_FREQUENCY)
+                } else {
+                    0.0
+                };
+                channel.volume = volume;
+                channel.stage = Stage::Attack;
+                channel.level = 0.0;
+                channel.position = 0.0;
+            },
+            AudioCommand::Envelope { channel, envelope } => {
+                if let Some(channel) = self.channels.get_mut(channel) {
+                    channel.envelope = envelope;
+                }
+            }
+            AudioCommand::NoteOff(channel) => {
+                if let Some(channel) = self.channels.get_mut(channel) {
+                    if channel.stage != Stage::Off {
+                        channel.stage = Stage::Release;
+                    }
+                }
+            }
+            AudioCommand::Frame => {}
+        }
+    }
+
+    /// Renders the next samples of the mixed channels
+    pub fn render(&mut self, output: &mut [f32]) {
+        for value in output.iter_mut() {
+            let mut sum = 0.0;
+
+            for channel in &mut self.channels {
+                sum += channel.next(&self.samples, self.sample_rate);
+            }
+
+            *value = (sum * MIX_GAIN).clamp(-1.0, 1.0);
+        }
+    }
+}
+
+/// Writes mono samples between -1 and 1 as a 16 bit WAV file
+pub fn write_wav<W: Write>(writer: &mut W, sample_rate: u32, samples: &[f32]) -> Result<()> {
+    let data_len = samples.len() as u32 * 2;
+
+    let mut bytes = Vec::with_capacity(44 + data_len as usize);
+    bytes.extend_from_slice(b"RIFF");
+    bytes.extend_from_slice(&(36 + data_len).to_le_bytes());
+    bytes.extend_from_slice(b"WAVEfmt ");
+    bytes.extend_from_slice(&16u32.to_le_bytes());
+    // Uncompressed, mono
+    bytes.extend_from_slice(&1u16.to_le_bytes());
+    bytes.extend_from_slice(&1u16.to_le_bytes());
+    bytes.extend_from_slice(&sample_rate.to_le_bytes());
+    bytes.extend_from_slice(&(sample_rate * 2).to_le_bytes());
+    bytes.extend_from_slice(&2u16.to_le_bytes());
+    bytes.extend_from_slice(&16u16.to_le_bytes());
+    bytes.extend_from_slice(b"data");
+    bytes.extend_from_slice(&data_len.to_le_bytes());
+
+    for &sample in samples {
+        let value = (sample.clamp(-1.0, 1.0) * f32::from(i16::MAX)) as i16;
+        bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    writer.write_all(&bytes).chain_err(|| "unable to write WAV file")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(unit: &mut AudioUnit, len: usize) -> Vec<f32> {
+        let mut output = vec![0.0; len];
+        unit.render(&mut output);
+        output
+    }
+
+    #[test]
+    fn waveforms() {
+        let mut unit = AudioUnit::new(100);
+        assert_eq!(render(&mut unit, 2), vec![0.0, 0.0]);
+
+        unit.apply(&AudioCommand::Tone {
+            channel: 0,
+            frequency: 25.0,
+            volume: 1.0,
+        });
+        assert_eq!(render(&mut unit, 4), vec![0.5, 0.5, -0.5, -0.5]);
+
+        unit.apply(&AudioCommand::Voice(0, Waveform::Triangle));
+        assert_eq!(render(&mut unit, 4), vec![0.5, 0.0, -0.5, 0.0]);
+
+        // Louder channels are clipped
+        unit.apply(&AudioCommand::Voice(0, Waveform::Square));
+        unit.apply(&AudioCommand::Tone {
+            channel: 1,
+            frequency: 0.0,
+            volume: 1.0,
+        });
+        unit.apply(&AudioCommand::Tone {
+            channel: 3,
+            frequency: 0.0,
+            volume: 1.0,
+        });
+        assert_eq!(render(&mut unit, 1), vec![1.0]);
+
+        // The noise channel stays put without a frequency
+        unit.apply(&AudioCommand::NoteOff(0));
+        unit.apply(&AudioCommand::NoteOff(1));
+        assert_eq!(render(&mut unit, 2), vec![0.5, 0.5]);
+
+        unit.apply(&AudioCommand::Reset(BTreeMap::new()));
+        assert_eq!(render(&mut unit, 1), vec![0.0]);
+    }
+
+    #[test]
+    fn huge_frequencies() {
+        let mut unit = AudioUnit::new(100);
+
+        for &frequency in &[1e20, f32::INFINITY, f32::NAN] {
+            for channel in 0..CHANNEL_COUNT {
+                unit.apply(&AudioCommand::Tone {
+                    channel,
+                    frequency,
+                    volume: 1.0,
+                });
+            }
+
+            assert!(render(&mut unit, 100).iter().all(|sample| sample.is_finite()));
+        }
+    }
+
+    #[test]
+    fn envelope() {
+        let mut unit = AudioUnit::new(100);
+
+        unit.apply(&AudioCommand::Envelope {
+            channel: 0,
+            envelope: Envelope {
+                attack: 0.02,
+                decay: 0.02,
+                sustain: 0.5,
+                release: 0.04,
+            },
+        });
+
+        // A low tone, so the square wave stays high
+        unit.apply(&AudioCommand::Tone {
+            channel: 0,
+            frequency: 1.0,
+            volume: 1.0,
+        });
+
+        let levels: Vec<f32> = render(&mut unit, 6).iter().map(|&value| value / MIX_GAIN).collect();
+        assert_eq!(levels, vec![0.5, 1.0, 0.75, 0.5, 0.5, 0.5]);
+
+        unit.apply(&AudioCommand::NoteOff(0));
+
+        let levels: Vec<f32> = render(&mut unit, 3).iter().map(|&value| value / MIX_GAIN).collect();
+        assert_eq!(levels, vec![0.25, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn samples() {
+        let mut unit = AudioUnit::new(100);
+
+        let mut samples = BTreeMap::new();
+        samples.insert(
+            3,
+            Sample {
+                rate: 50.0,
+                data: vec![128, 192, 0],
+            },
+        );
+
+        unit.apply(&AudioCommand::Reset(samples));
+        unit.apply(&AudioCommand::Voice(0, Waveform::Sample(3)));
+        unit.apply(&AudioCommand::Tone {
+            channel: 0,
+            frequency: 0.0,
+            volume: 1.0,
+        });
+
+        // Played at half the rate of the unit, then the channel stops
+        assert_eq!(render(&mut unit, 7), vec![0.0, 0.0, 0.25, 0.25, -0.5, -0.5, 0.0]);
+
+        // The frequency sets the playback rate
+        unit.apply(&AudioCommand::Tone {
+            channel: 0,
+            frequency: 100.0,
+            volume: 1.0,
+        });
+        assert_eq!(render(&mut unit, 4), vec![0.0, 0.25, -0.5, 0.0]);
+    }
+
+    #[test]
+    fn wav() {
+        let mut wav = Vec::new();
+        write_wav(&mut wav, 8000, &[0.0, 1.0, -2.0]).unwrap();
+
+        assert_eq!(wav.len(), 50);
+        assert_eq!(&wav[..4], b"RIFF");
+        assert_eq!(&wav[24..28], &8000u32.to_le_bytes());
+        assert_eq!(&wav[44..], &[0, 0, 0xff, 0x7f, 0x01, 0x80]);
+    }
+}
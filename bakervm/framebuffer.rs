@@ -0,0 +1,605 @@
+//! The framebuffer of the VM, holding either colors or indices into a palette
+
+use core::{Asset, BlitOptions, ColorMode, DisplayResolution, Value, LAYER_COUNT};
+use core::error::*;
+use core::typedef::*;
+use graphics;
+
+/// The colors of the 16 color palette, which also start the 256 color palette
+const BASE_PALETTE: [Color; 16] = [
+    (0x00, 0x00, 0x00),
+    (0x00, 0x00, 0xaa),
+    (0x00, 0xaa, 0x00),
+    (0x00, 0xaa, 0xaa),
+    (0xaa, 0x00, 0x00),
+    (0xaa, 0x00, 0xaa),
+    (0xaa, 0x55, 0x00),
+    (0xaa, 0xaa, 0xaa),
+    (0x55, 0x55, 0x55),
+    (0x55, 0x55, 0xff),
+    (0x55, 0xff, 0x55),
+    (0x55, 0xff, 0xff),
+    (0xff, 0x55, 0x55),
+    (0xff, 0x55, 0xff),
+    (0xff, 0xff, 0x55),
+    (0xff, 0xff, 0xff),
+];
+
+/// The intensities of the color cube of the 256 color palette
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+/// What something is drawn with
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Ink {
+    Color(Color),
+    /// An entry of the palette
+    Index(Address),
+}
+
+impl Ink {
+    /// Returns the ink of a value, which must be a color or a palette index
+    pub fn from_value(value: &Value) -> Option<Ink> {
+        match *value {
+            Value::Color(r, g, b) => Some(Ink::Color((r, g, b))),
+            Value::Address(index) => Some(Ink::Index(index)),
+            _ => None,
+        }
+    }
+
+    /// Returns the ink of an RGBA pixel of an asset. Fully transparent pixels
+    /// have no ink.
+    pub fn from_rgba(pixel: &[Byte]) -> Option<Ink> {
+        if pixel[3] == 0 {
+            None
+        } else {
+            Some(Ink::Color((pixel[0], pixel[1], pixel[2])))
+        }
+    }
+}
+
+/// A pixel of the framebuffer
+pub trait Pixel: Copy + Default + PartialEq {
+    /// Converts the ink into a pixel. Returns `None` if the ink can't be used
+    /// with the given palette.
+    fn from_ink(ink: Ink, palette: &[Color]) -> Option<Self>;
+}
+
+impl Pixel for Color {
+    fn from_ink(ink: Ink, _: &[Color]) -> Option<Self> {
+        match ink {
+            Ink::Color(color) => Some(color),
+            Ink::Index(..) => None,
+        }
+    }
+}
+
+impl Pixel for Byte {
+    fn from_ink(ink: Ink, palette: &[Color]) -> Option<Self> {
+        match ink {
+            Ink::Color(color) => Some(nearest_entry(palette, color)),
+            Ink::Index(index) if index < palette.len() => Some(index as Byte),
+            Ink::Index(..) => None,
+        }
+    }
+}
+
+/// A layer of the framebuffer, covering the whole display
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Layer<P> {
+    pixels: Vec<P>,
+    /// The position of the top left corner of the display inside the layer.
+    /// The layer wraps around at its edges.
+    scroll: (Integer, Integer),
+    /// Pixels of this color show the layers below. The key of the bottom
+    /// layer is ignored.
+    key: P,
+}
+
+impl<P: Pixel> Layer<P> {
+    /// Creates a layer of the given length, which is transparent at first
+    fn new(len: usize) -> Layer<P> {
+        Layer {
+            pixels: vec![P::default(); len],
+            scroll: (0, 0),
+            key: P::default(),
+        }
+    }
+
+    /// Returns the pixel of the layer shown at the given position of the
+    /// display
+    fn shown_at(&self, resolution: &DisplayResolution, x: usize, y: usize) -> P {
+        let wrap = |position: usize, scroll: Integer, len: usize| {
+            (position as i128 + scroll as i128).rem_euclid(len as i128) as usize
+        };
+
+        let x = wrap(x, self.scroll.0, resolution.width);
+        let y = wrap(y, self.scroll.1, resolution.height);
+
+        self.pixels
+            .get(y * resolution.width + x)
+            .cloned()
+            .unwrap_or(self.key)
+    }
+}
+
+/// The layers of the display. The bottom layer always exists, the others
+/// are created when they are used first.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum Framebuffer {
+    Direct(Vec<Layer<Color>>),
+    Indexed {
+        layers: Vec<Layer<Byte>>,
+        palette: Vec<Color>,
+    },
+}
+
+/// Evaluates `$body` with the given layer and the palette of the
+/// framebuffer, creating the layer if needed. The body is compiled for both
+/// kinds of pixels.
+macro_rules! with_layer {
+    ($framebuffer:expr, $index:expr, |$layer:ident, $palette:ident| $body:expr) => {
+        match *$framebuffer {
+            Framebuffer::Direct(ref mut layers) => {
+                let $palette: &[Color] = &[];
+                let $layer = layer_mut(layers, $index)?;
+                $body
+            }
+            Framebuffer::Indexed {
+                ref mut layers,
+                palette: ref $palette,
+            } => {
+                let $layer = layer_mut(layers, $index)?;
+                $body
+            }
+        }
+    };
+}
+
+impl Default for Framebuffer {
+    fn default() -> Self {
+        Framebuffer::Direct(Vec::new())
+    }
+}
+
+impl Framebuffer {
+    /// Creates a black framebuffer of the given length. Indexed framebuffers
+    /// start with the default palette.
+    pub fn new(color_mode: ColorMode, len: usize) -> Framebuffer {
+        if let Some(size) = color_mode.palette_size() {
+            Framebuffer::Indexed {
+                layers: vec![Layer::new(len)],
+                palette: default_palette(size),
+            }
+        } else {
+            Framebuffer::Direct(vec![Layer::new(len)])
+        }
+    }
+
+    /// Returns the color of the bottom layer at the given index. The colors
+    /// shown on the display are the ones of the composited framebuffer.
+    pub fn color(&self, index: usize) -> Option<Color> {
+        match *self {
+            Framebuffer::Direct(ref layers) => {
+                layers.first().and_then(|layer| layer.pixels.get(index).cloned())
+            }
+            Framebuffer::Indexed {
+                ref layers,
+                ref palette,
+            } => layers
+                .first()
+                .and_then(|layer| layer.pixels.get(index))
+                .and_then(|&entry| palette.get(entry as usize).cloned()),
+        }
+    }
+
+    /// Returns the pixel of the layer at the given index as a value. Indexed
+    /// pixels are returned as addresses.
+    pub fn get(&mut self, layer: Address, index: usize) -> Result<Option<Value>> {
+        Ok(match *self {
+            Framebuffer::Direct(ref mut layers) => layer_mut(layers, layer)?
+                .pixels
+                .get(index)
+                .map(|&(r, g, b)| Value::Color(r, g, b)),
+            Framebuffer::Indexed { ref mut layers, .. } => layer_mut(layers, layer)?
+                .pixels
+                .get(index)
+                .map(|&entry| Value::Address(entry as Address)),
+        })
+    }
+
+    /// Sets the pixel of the layer at the given index. Indices outside of the
+    /// framebuffer are ignored.
+    pub fn set(&mut self, layer: Address, index: usize, ink: Ink) -> Result<()> {
+        with_layer!(self, layer, |layer, palette| {
+            let pixel = to_pixel(ink, palette)?;
+
+            if let Some(target) = layer.pixels.get_mut(index) {
+                *target = pixel;
+            }
+        });
+
+        Ok(())
+    }
+
+    pub fn clear(&mut self, layer: Address, ink: Ink) -> Result<()> {
+        with_layer!(self, layer, |layer, palette| {
+            let pixel = to_pixel(ink, palette)?;
+
+            for target in layer.pixels.iter_mut() {
+                *target = pixel;
+            }
+        });
+
+        Ok(())
+    }
+
+    /// Fills the layer with its transparency key
+    pub fn clear_transparent(&mut self, layer: Address) -> Result<()> {
+        with_layer!(self, layer, |layer, _palette| {
+            let key = layer.key;
+
+            for target in layer.pixels.iter_mut() {
+                *target = key;
+            }
+        });
+
+        Ok(())
+    }
+
+    pub fn fill_rect(
+        &mut self, layer: Address, resolution: &DisplayResolution, from: (Integer, Integer),
+        to: (Integer, Integer), ink: Ink
+    ) -> Result<()> {
+        with_layer!(self, layer, |layer, palette| {
+            let pixel = to_pixel(ink, palette)?;
+            graphics::fill_rect(&mut layer.pixels, resolution, from, to, pixel);
+        });
+
+        Ok(())
+    }
+
+    pub fn line(
+        &mut self, layer: Address, resolution: &DisplayResolution, from: (Integer, Integer),
+        to: (Integer, Integer), ink: Ink
+    ) -> Result<()> {
+        with_layer!(self, layer, |layer, palette| {
+            let pixel = to_pixel(ink, palette)?;
+            graphics::line(&mut layer.pixels, resolution, from, to, pixel);
+        });
+
+        Ok(())
+    }
+
+    pub fn draw_text(
+        &mut self, layer: Address, resolution: &DisplayResolution, position: (Integer, Integer),
+        font: &Asset, text: &[char], ink: Ink
+    ) -> Result<()> {
+        with_layer!(self, layer, |layer, palette| {
+            let pixel = to_pixel(ink, palette)?;
+            graphics::draw_text(&mut layer.pixels, resolution, position, font, text, pixel);
+        });
+
+        Ok(())
+    }
+
+    /// Draws a sprite using the given options. Pixels of the sprite, that
+    /// can't be drawn in the color mode of the framebuffer, are skipped.
+    pub fn blit<F>(
+        &mut self, layer: Address, resolution: &DisplayResolution, position: (Integer, Integer),
+        size: (usize, usize), options: &BlitOptions, pixel: F
+    ) -> Result<()>
+    where
+        F: Fn(usize, usize) -> Option<Ink>,
+    {
+        let color_key = options.color_key.map(Ink::Color);
+        let flip = (options.flip_x, options.flip_y);
+
+        with_layer!(self, layer, |layer, palette| {
+            graphics::blit(&mut layer.pixels, resolution, position, size, flip, |x, y| {
+                pixel(x, y)
+                    .filter(|&ink| Some(ink) != color_key)
+                    .and_then(|ink| Pixel::from_ink(ink, palette))
+            });
+        });
+
+        Ok(())
+    }
+
+    /// Moves the top left corner of the display to the given position inside
+    /// of the layer
+    pub fn scroll(&mut self, layer: Address, position: (Integer, Integer)) -> Result<()> {
+        with_layer!(self, layer, |layer, _palette| layer.scroll = position);
+
+        Ok(())
+    }
+
+    /// Sets the color, that makes pixels of the layer transparent
+    pub fn set_key(&mut self, layer: Address, ink: Ink) -> Result<()> {
+        with_layer!(self, layer, |layer, palette| layer.key = to_pixel(ink, palette)?);
+
+        Ok(())
+    }
+
+    /// Composites the layers into a framebuffer with a single layer, as shown
+    /// on the display. Higher layers cover the lower ones.
+    pub fn composite(&self, resolution: &DisplayResolution) -> Framebuffer {
+        match *self {
+            Framebuffer::Direct(ref layers) => {
+                Framebuffer::Direct(vec![composite_layers(layers, resolution)])
+            }
+            Framebuffer::Indexed {
+                ref layers,
+                ref palette,
+            } => Framebuffer::Indexed {
+                layers: vec![composite_layers(layers, resolution)],
+                palette: palette.clone(),
+            },
+        }
+    }
+
+    /// Returns for every pixel of the display, whether a layer shows a pixel
+    /// there, that is not its transparency key. This includes the key of the
+    /// bottom layer.
+    pub fn solid_pixels(&self, resolution: &DisplayResolution) -> Vec<bool> {
+        match *self {
+            Framebuffer::Direct(ref layers) => solid_pixels(layers, resolution),
+            Framebuffer::Indexed { ref layers, .. } => solid_pixels(layers, resolution),
+        }
+    }
+
+    /// Changes the color of a palette entry
+    pub fn set_palette(&mut self, index: Address, color: Color) -> Result<()> {
+        let palette = self.palette_mut()?;
+        let len = palette.len();
+
+        if let Some(entry) = palette.get_mut(index) {
+            *entry = color;
+            Ok(())
+        } else {
+            bail!("palette index {} is outside of the palette of {} colors", index, len);
+        }
+    }
+
+    /// Moves the colors of the palette entries from `first` to `last` one
+    /// entry up. The color of `last` moves to `first`.
+    pub fn rotate_palette(&mut self, first: Address, last: Address) -> Result<()> {
+        let palette = self.palette_mut()?;
+
+        ensure!(
+            first <= last && last < palette.len(),
+            "unable to rotate the palette entries {} to {} of a palette of {} colors",
+            first,
+            last,
+            palette.len()
+        );
+
+        palette[first..last + 1].rotate_right(1);
+
+        Ok(())
+    }
+
+    fn palette_mut(&mut self) -> Result<&mut Vec<Color>> {
+        if let Framebuffer::Indexed { ref mut palette, .. } = *self {
+            Ok(palette)
+        } else {
+            bail!("the palette is only available in the indexed color modes");
+        }
+    }
+}
+
+/// Returns the palette an indexed framebuffer of the given size starts with.
+/// The 256 color palette continues the 16 color palette with a 6x6x6 color
+/// cube and a ramp of grays.
+pub fn default_palette(size: usize) -> Vec<Color> {
+    let mut palette = BASE_PALETTE.to_vec();
+
+    for &r in &CUBE_LEVELS {
+        for &g in &CUBE_LEVELS {
+            for &b in &CUBE_LEVELS {
+                palette.push((r, g, b));
+            }
+        }
+    }
+
+    for gray in 0..24 {
+        let level = 8 + gray * 10;
+        palette.push((level, level, level));
+    }
+
+    palette.truncate(size);
+    palette
+}
+
+/// Converts the ink into a pixel of the framebuffer
+fn to_pixel<P: Pixel>(ink: Ink, palette: &[Color]) -> Result<P> {
+    if let Some(pixel) = P::from_ink(ink, palette) {
+        return Ok(pixel);
+    }
+
+    ensure!(
+        !palette.is_empty(),
+        "unable to draw with a palette index in the direct color mode"
+    );
+
+    bail!("{:?} is outside of the palette of {} colors", ink, palette.len());
+}
+
+/// Returns the layer with the given index, creating it and the layers below
+/// if they don't exist yet
+fn layer_mut<P: Pixel>(layers: &mut Vec<Layer<P>>, index: Address) -> Result<&mut Layer<P>> {
+    ensure!(
+        index < LAYER_COUNT,
+        "layer {} is outside of the {} layers of the framebuffer",
+        index,
+        LAYER_COUNT
+    );
+
+    let len = layers.first().map_or(0, |layer| layer.pixels.len());
+
+    while layers.len() <= index {
+        layers.push(Layer::new(len));
+    }
+
+    Ok(&mut layers[index])
+}
+
+/// Composites the given layers, starting with the bottom one
+fn composite_layers<P: Pixel>(layers: &[Layer<P>], resolution: &DisplayResolution) -> Layer<P> {
+    let mut composited = Layer::new(resolution.width * resolution.height);
+
+    for (index, layer) in layers.iter().enumerate() {
+        for y in 0..resolution.height {
+            for x in 0..resolution.width {
+                let pixel = layer.shown_at(resolution, x, y);
+
+                if index == 0 || pixel != layer.key {
+                    composited.pixels[y * resolution.width + x] = pixel;
+                }
+            }
+        }
+    }
+
+    composited
+}
+
+fn solid_pixels<P: Pixel>(layers: &[Layer<P>], resolution: &DisplayResolution) -> Vec<bool> {
+    let mut solid = vec![false; resolution.width * resolution.height];
+
+    for layer in layers {
+        for y in 0..resolution.height {
+            for x in 0..resolution.width {
+                if layer.shown_at(resolution, x, y) != layer.key {
+                    solid[y * resolution.width + x] = true;
+                }
+            }
+        }
+    }
+
+    solid
+}
+
+/// Returns the index of the palette entry closest to the given color
+fn nearest_entry(palette: &[Color], (r, g, b): Color) -> Byte {
+    let distance = |&(entry_r, entry_g, entry_b): &Color| {
+        let difference = |a: u8, b: u8| (a as i32 - b as i32).pow(2);
+
+        difference(r, entry_r) + difference(g, entry_g) + difference(b, entry_b)
+    };
+
+    palette
+        .iter()
+        .enumerate()
+        .min_by_key(|&(_, entry)| distance(entry))
+        .map_or(0, |(index, _)| index as Byte)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_palettes() {
+        assert_eq!(default_palette(16).len(), 16);
+
+        let palette = default_palette(256);
+
+        assert_eq!(palette.len(), 256);
+        assert_eq!(palette[16], (0, 0, 0));
+        assert_eq!(palette[231], (255, 255, 255));
+        assert_eq!(palette[255], (238, 238, 238));
+    }
+
+    #[test]
+    fn indexed() {
+        let mut framebuffer = Framebuffer::new(ColorMode::Indexed16, 4);
+
+        framebuffer.set(0, 0, Ink::Index(4)).unwrap();
+        framebuffer.set(0, 1, Ink::Color((250, 250, 250))).unwrap();
+        framebuffer.set(0, 9, Ink::Index(4)).unwrap();
+
+        assert!(framebuffer.set(0, 0, Ink::Index(16)).is_err());
+
+        assert_eq!(framebuffer.get(0, 0).unwrap(), Some(Value::Address(4)));
+        assert_eq!(framebuffer.get(0, 1).unwrap(), Some(Value::Address(15)));
+        assert_eq!(framebuffer.color(0), Some((0xaa, 0, 0)));
+
+        framebuffer.set_palette(4, (1, 2, 3)).unwrap();
+        assert_eq!(framebuffer.color(0), Some((1, 2, 3)));
+
+        framebuffer.rotate_palette(3, 5).unwrap();
+        assert_eq!(framebuffer.color(0), Some((0x00, 0xaa, 0xaa)));
+        assert!(framebuffer.rotate_palette(5, 16).is_err());
+
+        // Sprites are drawn with the closest palette entries
+        let resolution = DisplayResolution {
+            width: 2,
+            height: 2,
+        };
+
+        let options = BlitOptions {
+            color_key: Some((0, 0, 0)),
+            ..BlitOptions::default()
+        };
+
+        framebuffer
+            .blit(0, &resolution, (0, 0), (2, 1), &options, |x, _| match x {
+                0 => Some(Ink::Color((0, 0, 0))),
+                _ => Some(Ink::Color((0, 0, 200))),
+            })
+            .unwrap();
+
+        assert_eq!(framebuffer.get(0, 0).unwrap(), Some(Value::Address(4)));
+        assert_eq!(framebuffer.get(0, 1).unwrap(), Some(Value::Address(1)));
+    }
+
+    #[test]
+    fn direct() {
+        let mut framebuffer = Framebuffer::new(ColorMode::Direct, 2);
+
+        framebuffer.clear(0, Ink::Color((1, 2, 3))).unwrap();
+
+        assert_eq!(framebuffer.get(0, 1).unwrap(), Some(Value::Color(1, 2, 3)));
+        assert!(framebuffer.clear(0, Ink::Index(0)).is_err());
+        assert!(framebuffer.set_palette(0, (0, 0, 0)).is_err());
+    }
+
+    #[test]
+    fn layers() {
+        let resolution = DisplayResolution {
+            width: 3,
+            height: 1,
+        };
+
+        let mut framebuffer = Framebuffer::new(ColorMode::Direct, 3);
+
+        framebuffer.clear(0, Ink::Color((1, 1, 1))).unwrap();
+        framebuffer.set(2, 0, Ink::Color((2, 2, 2))).unwrap();
+        framebuffer.set(2, 1, Ink::Color((3, 3, 3))).unwrap();
+
+        // New layers are transparent, so only the set pixels cover the bottom
+        let composited = framebuffer.composite(&resolution);
+        assert_eq!(composited.color(0), Some((2, 2, 2)));
+        assert_eq!(composited.color(1), Some((3, 3, 3)));
+        assert_eq!(composited.color(2), Some((1, 1, 1)));
+
+        // Layers wrap around while scrolling
+        framebuffer.scroll(2, (-1, 5)).unwrap();
+        let composited = framebuffer.composite(&resolution);
+        assert_eq!(composited.color(0), Some((1, 1, 1)));
+        assert_eq!(composited.color(1), Some((2, 2, 2)));
+        assert_eq!(composited.color(2), Some((3, 3, 3)));
+
+        framebuffer.set_key(2, Ink::Color((2, 2, 2))).unwrap();
+        framebuffer.set(2, 2, Ink::Color((0, 0, 0))).unwrap();
+        let composited = framebuffer.composite(&resolution);
+        assert_eq!(composited.color(0), Some((0, 0, 0)));
+        assert_eq!(composited.color(1), Some((1, 1, 1)));
+
+        assert!(framebuffer.set(LAYER_COUNT, 0, Ink::Color((0, 0, 0))).is_err());
+
+        // Only pixels, that differ from the key of their layer, are solid
+        let mut framebuffer = Framebuffer::new(ColorMode::Direct, 3);
+        framebuffer.set(1, 2, Ink::Color((5, 5, 5))).unwrap();
+        assert_eq!(framebuffer.solid_pixels(&resolution), vec![false, false, true]);
+    }
+}
@@ -0,0 +1,362 @@
+//! Native drawing routines working on the pixels of the framebuffer. The
+//! pixels are either colors or palette indices.
+
+use core::{self, Asset, DisplayResolution};
+use core::typedef::*;
+use std::cmp;
+
+/// Copies a sprite of the given size to the frame, with its top left corner
+/// at the given position. Pixels outside of the display are clipped. `pixel`
+/// returns the pixel of the sprite at a position or `None` if the pixel is
+/// transparent.
+pub fn blit<P, F>(
+    frame: &mut [P], resolution: &DisplayResolution, position: (Integer, Integer),
+    size: (usize, usize), flip: (bool, bool), pixel: F
+) where
+    F: Fn(usize, usize) -> Option<P>,
+{
+    for_each_pixel(resolution, position, size, flip, |index, x, y| {
+        if let Some(pixel) = pixel(x, y) {
+            if let Some(target) = frame.get_mut(index) {
+                *target = pixel;
+            }
+        }
+    });
+}
+
+/// Calls `f` for every pixel of a sprite of the given size, that lies inside
+/// the display, with the index of the pixel in the frame and its position
+/// inside of the sprite
+pub fn for_each_pixel<F>(
+    resolution: &DisplayResolution, (x, y): (Integer, Integer), (width, height): (usize, usize),
+    (flip_x, flip_y): (bool, bool), mut f: F
+) where
+    F: FnMut(usize, usize, usize),
+{
+    let (start_x, end_x) = clip(x, width, resolution.width);
+    let (start_y, end_y) = clip(y, height, resolution.height);
+
+    for sprite_y in start_y..end_y {
+        let source_y = if flip_y { height - 1 - sprite_y } else { sprite_y };
+        let frame_y = (y as i128 + sprite_y as i128) as usize;
+
+        for sprite_x in start_x..end_x {
+            let source_x = if flip_x { width - 1 - sprite_x } else { sprite_x };
+            let frame_x = (x as i128 + sprite_x as i128) as usize;
+
+            f(frame_y * resolution.width + frame_x, source_x, source_y);
+        }
+    }
+}
+
+/// Draws the given text using the glyphs of a font, with the top left corner
+/// of the first glyph at the given position. Glyphs are separated by a single
+/// pixel and a newline continues the text in the next row.
+pub fn draw_text<P: Copy>(
+    frame: &mut [P], resolution: &DisplayResolution, (x, y): (Integer, Integer), font: &Asset,
+    text: &[char], color: P
+) {
+    let (width, height) = (font.width, font.height);
+    let glyph_len = width * height;
+
+    let (mut cursor_x, mut cursor_y) = (x, y);
+
+    for &character in text {
+        if character == '\n' {
+            cursor_x = x;
+            cursor_y = cursor_y.saturating_add(height as Integer + 1);
+            continue;
+        }
+
+        let offset = core::glyph_index(character) * glyph_len;
+
+        if let Some(glyph) = font.data.get(offset..offset + glyph_len) {
+            blit(
+                frame,
+                resolution,
+                (cursor_x, cursor_y),
+                (width, height),
+                (false, false),
+                |x, y| if glyph[y * width + x] != 0 { Some(color) } else { None },
+            );
+        }
+
+        cursor_x = cursor_x.saturating_add(width as Integer + 1);
+    }
+}
+
+/// Fills the rectangle between the two given corners, including the corners
+/// themselves, clipped to the display
+pub fn fill_rect<P: Copy>(
+    frame: &mut [P], resolution: &DisplayResolution, (x0, y0): (Integer, Integer),
+    (x1, y1): (Integer, Integer), color: P
+) {
+    let (left, right) = (cmp::min(x0, x1), cmp::max(x0, x1));
+    let (top, bottom) = (cmp::min(y0, y1), cmp::max(y0, y1));
+
+    let (start_x, end_x) = clip(left, span_len(left, right), resolution.width);
+    let (start_y, end_y) = clip(top, span_len(top, bottom), resolution.height);
+
+    for y in start_y..end_y {
+        let row = (top as i128 + y as i128) as usize * resolution.width;
+        let start = row + (left as i128 + start_x as i128) as usize;
+        let end = row + (left as i128 + end_x as i128) as usize;
+
+        if let Some(pixels) = frame.get_mut(start..end) {
+            for pixel in pixels {
+                *pixel = color;
+            }
+        }
+    }
+}
+
+/// Draws a line between the two given points, including both end points.
+/// Lines reaching far outside of the display are clipped before drawing.
+pub fn line<P: Copy>(
+    frame: &mut [P], resolution: &DisplayResolution, from: (Integer, Integer),
+    to: (Integer, Integer), color: P
+) {
+    // Straight lines are clipped exactly as rectangles
+    if from.0 == to.0 || from.1 == to.1 {
+        return fill_rect(frame, resolution, from, to, color);
+    }
+
+    let ((mut x, mut y), (x1, y1)) = match clip_line(resolution, from, to) {
+        Some(points) => points,
+        None => return,
+    };
+
+    // Bresenham's line algorithm
+    let dx = (x1 - x).abs();
+    let dy = -(y1 - y).abs();
+    let step_x = if x < x1 { 1 } else { -1 };
+    let step_y = if y < y1 { 1 } else { -1 };
+    let mut error = dx + dy;
+
+    loop {
+        if x >= 0 && y >= 0 && (x as usize) < resolution.width && (y as usize) < resolution.height {
+            if let Some(pixel) = frame.get_mut(y as usize * resolution.width + x as usize) {
+                *pixel = color;
+            }
+        }
+
+        if x == x1 && y == y1 {
+            break;
+        }
+
+        let double_error = 2 * error;
+
+        if double_error >= dy {
+            error += dy;
+            x += step_x;
+        }
+
+        if double_error <= dx {
+            error += dx;
+            y += step_y;
+        }
+    }
+}
+
+/// Clips the line between the given points to the display using the
+/// Liang-Barsky algorithm. Lines inside of the display are left untouched.
+/// Returns `None` if no part of the line is visible.
+fn clip_line(
+    resolution: &DisplayResolution, (x0, y0): (Integer, Integer), (x1, y1): (Integer, Integer)
+) -> Option<((Integer, Integer), (Integer, Integer))> {
+    let max_x = resolution.width as Integer - 1;
+    let max_y = resolution.height as Integer - 1;
+
+    let inside = |x: Integer, y: Integer| x >= 0 && y >= 0 && x <= max_x && y <= max_y;
+
+    if inside(x0, y0) && inside(x1, y1) {
+        return Some(((x0, y0), (x1, y1)));
+    }
+
+    let (x0, y0) = (x0 as f64, y0 as f64);
+    let (dx, dy) = (x1 as f64 - x0, y1 as f64 - y0);
+    let (mut enter, mut exit) = (0.0f64, 1.0f64);
+
+    let edges = [
+        (-dx, x0),
+        (dx, max_x as f64 - x0),
+        (-dy, y0),
+        (dy, max_y as f64 - y0),
+    ];
+
+    for &(p, q) in &edges {
+        if p == 0.0 {
+            if q < 0.0 {
+                return None;
+            }
+        } else {
+            let t = q / p;
+
+            if p < 0.0 {
+                enter = enter.max(t);
+            } else {
+                exit = exit.min(t);
+            }
+        }
+    }
+
+    if enter > exit {
+        return None;
+    }
+
+    // Huge coordinates lose precision as floats, so the clipped points are
+    // kept inside of the display
+    let point = |t: f64| {
+        (
+            (x0 + t * dx).round().max(0.0).min(max_x as f64) as Integer,
+            (y0 + t * dy).round().max(0.0).min(max_y as f64) as Integer,
+        )
+    };
+
+    Some((point(enter), point(exit)))
+}
+
+/// Returns the number of pixels between two coordinates, including both
+fn span_len(start: Integer, end: Integer) -> usize {
+    (end as i128 - start as i128 + 1).min(usize::MAX as i128) as usize
+}
+
+/// Returns the range of a sprite of the given length at the given position
+/// that lies inside a display of the given length
+fn clip(position: Integer, len: usize, display_len: usize) -> (usize, usize) {
+    let position = position as i128;
+    let start = cmp::max(0, -position);
+    let end = cmp::min(len as i128, display_len as i128 - position);
+
+    if end <= start {
+        (0, 0)
+    } else {
+        (start as usize, end as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: Color = (255, 0, 0);
+    const GREEN: Color = (0, 255, 0);
+
+    fn draw(position: (Integer, Integer), flip: (bool, bool)) -> Vec<Color> {
+        let resolution = DisplayResolution {
+            width: 3,
+            height: 2,
+        };
+
+        let mut frame = vec![(0, 0, 0); 6];
+
+        // A 2x2 sprite with a red and a green pixel in the top row and a
+        // transparent bottom row
+        blit(
+            &mut frame,
+            &resolution,
+            position,
+            (2, 2),
+            flip,
+            |x, y| match (x, y) {
+                (0, 0) => Some(RED),
+                (1, 0) => Some(GREEN),
+                _ => None,
+            },
+        );
+
+        frame
+    }
+
+    #[test]
+    fn blit_and_clip() {
+        let flip = (false, false);
+
+        assert_eq!(
+            draw((0, 0), flip),
+            vec![RED, GREEN, (0, 0, 0), (0, 0, 0), (0, 0, 0), (0, 0, 0)]
+        );
+        assert_eq!(draw((2, 1), flip)[5], RED);
+        assert_eq!(draw((-1, 0), flip)[0], GREEN);
+        assert!(draw((0, -1), flip).iter().all(|&color| color == (0, 0, 0)));
+        assert!(draw((3, 0), flip).iter().all(|&color| color == (0, 0, 0)));
+        assert!(draw((-100, 100), flip).iter().all(|&color| color == (0, 0, 0)));
+    }
+
+    #[test]
+    fn rect() {
+        let resolution = DisplayResolution {
+            width: 3,
+            height: 2,
+        };
+
+        let mut frame = vec![(0, 0, 0); 6];
+
+        fill_rect(&mut frame, &resolution, (2, 5), (1, -5), RED);
+        assert_eq!(frame, vec![(0, 0, 0), RED, RED, (0, 0, 0), RED, RED]);
+
+        fill_rect(&mut frame, &resolution, (Integer::MIN, 0), (Integer::MAX, 0), GREEN);
+        assert_eq!(&frame[0..3], &[GREEN; 3]);
+
+        fill_rect(&mut frame, &resolution, (3, 0), (10, 10), (0, 0, 0));
+        assert_eq!(frame[5], RED);
+    }
+
+    #[test]
+    fn lines() {
+        let resolution = DisplayResolution {
+            width: 4,
+            height: 4,
+        };
+
+        let mut frame = vec![(0, 0, 0); 16];
+
+        line(&mut frame, &resolution, (3, 3), (0, 0), RED);
+
+        for i in 0..4 {
+            assert_eq!(frame[i * 5], RED);
+        }
+
+        assert_eq!(frame.iter().filter(|&&color| color == RED).count(), 4);
+
+        // A line reaching far outside of the display is clipped
+        line(&mut frame, &resolution, (Integer::MIN, 1), (Integer::MAX, 1), GREEN);
+        assert_eq!(&frame[4..8], &[GREEN; 4]);
+
+        line(&mut frame, &resolution, (-1 << 40, -1 << 40), (1 << 40, 1 << 40), GREEN);
+        assert_eq!(frame[15], GREEN);
+
+        let before = frame.clone();
+        line(&mut frame, &resolution, (-10, -1), (10, -1), GREEN);
+        assert_eq!(frame, before);
+    }
+
+    #[test]
+    fn text() {
+        let resolution = DisplayResolution {
+            width: 12,
+            height: 16,
+        };
+
+        let mut frame = vec![(0, 0, 0); 12 * 16];
+        let font = core::builtin_font();
+
+        draw_text(&mut frame, &resolution, (0, 0), font, &['T', 'T', '\n', 'I'], RED);
+
+        // The top rows of both 'T's, separated by a single pixel
+        let top: Vec<_> = frame[0..12].iter().map(|&color| color == RED).collect();
+        assert_eq!(
+            top,
+            vec![true, true, true, true, true, false, true, true, true, true, true, false]
+        );
+
+        // The top row of the 'I' in the next line
+        assert_eq!(&frame[8 * 12..8 * 12 + 5], &[(0, 0, 0), RED, RED, RED, (0, 0, 0)]);
+    }
+
+    #[test]
+    fn flip() {
+        assert_eq!(&draw((0, 0), (true, true))[3..5], &[GREEN, RED]);
+        assert_eq!(&draw((0, 0), (false, true))[3..5], &[RED, GREEN]);
+    }
+}
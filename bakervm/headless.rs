@@ -0,0 +1,56 @@
+//! Running images without a display or a sound card
+
+use audio::{self, AudioCommand, AudioUnit, FRAME_RATE, SAMPLE_RATE};
+use core::error::*;
+use framebuffer::Framebuffer;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+use std::sync::{Arc, Barrier};
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::time::Duration;
+
+/// The interval in which the shown frames are discarded while waiting for
+/// audio commands
+const FRAME_POLL_INTERVAL_MS: u64 = 10;
+
+/// Runs the VM without a display, rendering its audio as if every flushed
+/// frame took 1/60 of a second. Stops after the given number of frames or
+/// when the VM halts, then writes the audio to the given WAV file.
+pub fn start(
+    frame_receiver: Receiver<Framebuffer>, audio_receiver: Receiver<AudioCommand>, barrier: Arc<Barrier>,
+    wav: Option<&Path>, frame_limit: Option<usize>
+) -> Result<()> {
+    barrier.wait();
+
+    let mut unit = AudioUnit::new(SAMPLE_RATE);
+    let frame_len = (SAMPLE_RATE / FRAME_RATE) as usize;
+    let mut output = Vec::new();
+    let mut frames = 0;
+
+    while Some(frames) != frame_limit {
+        // Nobody looks at the frames, but the VM waits for them to be taken
+        while frame_receiver.try_recv().is_ok() {}
+
+        match audio_receiver.recv_timeout(Duration::from_millis(FRAME_POLL_INTERVAL_MS)) {
+            Ok(AudioCommand::Frame) => {
+                let start = output.len();
+                output.resize(start + frame_len, 0.0);
+                unit.render(&mut output[start..]);
+
+                frames += 1;
+            }
+            Ok(command) => unit.apply(&command),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+    }
+
+    if let Some(path) = wav {
+        let file = File::create(path).chain_err(|| format!("unable to create {:?}", path))?;
+
+        audio::write_wav(&mut BufWriter::new(file), SAMPLE_RATE, &output)?;
+    }
+
+    Ok(())
+}
@@ -1,23 +1,50 @@
-use core::Config;
-use core::Event;
+use audio::{AudioCommand, AudioUnit, SAMPLE_RATE};
+use core::{Axis, Button, Config, Event};
 use core::error::*;
+use framebuffer::Framebuffer;
 use core::typedef::*;
 use sdl2;
+use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
+use sdl2::controller::{Axis as SDL2Axis, Button as SDL2Button, GameController};
 use sdl2::event::Event as SDL2Event;
 use sdl2::event::EventType as SDL2EventType;
+use sdl2::mouse::MouseWheelDirection;
 use sdl2::pixels::Color;
+use std::collections::BTreeMap;
 use std::sync::{Arc, Barrier};
 use std::sync::mpsc::{Receiver, Sender, TryRecvError};
 use std::thread;
 use std::time::Duration;
 
 pub fn start(
-    frame_receiver: Receiver<Frame>, event_sender: Sender<Event>, config: Config,
-    barrier: Arc<Barrier>
+    frame_receiver: Receiver<Framebuffer>, event_sender: Sender<Event>, status_receiver: Receiver<String>,
+    audio_receiver: Receiver<AudioCommand>, config: Config, barrier: Arc<Barrier>
 ) -> Result<()> {
     let sdl_context = sdl2::init()?;
     let video_subsystem = sdl_context.video()?;
 
+    // Images still run without a sound card
+    let _audio_device = match open_audio(&sdl_context, audio_receiver) {
+        Ok(device) => Some(device),
+        Err(ref e) => {
+            println!("warning: {}", e);
+            None
+        }
+    };
+
+    // Images still run without controller support
+    let controller_subsystem = match sdl_context.game_controller() {
+        Ok(subsystem) => Some(subsystem),
+        Err(ref e) => {
+            println!("warning: unable to initialize game controllers: {}", e);
+            None
+        }
+    };
+
+    // The opened controllers by their instance id. Controllers are closed when
+    // they're dropped.
+    let mut controllers: BTreeMap<i32, GameController> = BTreeMap::new();
+
     let hide_cursor = config.display.hide_cursor;
 
 
@@ -58,8 +85,6 @@ pub fn start(
     event_pump.disable_event(SDL2EventType::AppDidEnterForeground);
     event_pump.disable_event(SDL2EventType::Window);
     event_pump.disable_event(SDL2EventType::TextEditing);
-    event_pump.disable_event(SDL2EventType::TextInput);
-    event_pump.disable_event(SDL2EventType::MouseWheel);
     event_pump.disable_event(SDL2EventType::JoyAxisMotion);
     event_pump.disable_event(SDL2EventType::JoyBallMotion);
     event_pump.disable_event(SDL2EventType::JoyHatMotion);
@@ -67,11 +92,6 @@ pub fn start(
     event_pump.disable_event(SDL2EventType::JoyButtonUp);
     event_pump.disable_event(SDL2EventType::JoyDeviceAdded);
     event_pump.disable_event(SDL2EventType::JoyDeviceRemoved);
-    event_pump.disable_event(SDL2EventType::ControllerAxisMotion);
-    event_pump.disable_event(SDL2EventType::ControllerButtonDown);
-    event_pump.disable_event(SDL2EventType::ControllerButtonUp);
-    event_pump.disable_event(SDL2EventType::ControllerDeviceAdded);
-    event_pump.disable_event(SDL2EventType::ControllerDeviceRemoved);
     event_pump.disable_event(SDL2EventType::ControllerDeviceRemapped);
     event_pump.disable_event(SDL2EventType::FingerDown);
     event_pump.disable_event(SDL2EventType::FingerUp);
@@ -148,6 +168,84 @@ pub fn start(
                                 break 'main;
                             }
                         }
+                        SDL2Event::ControllerDeviceAdded { which, .. } => {
+                            if let Some(ref subsystem) = controller_subsystem {
+                                match subsystem.open(which) {
+                                    Ok(controller) => {
+                                        controllers.insert(controller.instance_id(), controller);
+                                    }
+                                    Err(e) => println!("warning: unable to open game controller: {}", e),
+                                }
+                            }
+                        }
+                        SDL2Event::ControllerDeviceRemoved { which, .. } => {
+                            controllers.remove(&which);
+                        }
+                        SDL2Event::ControllerButtonDown { button, .. } => {
+                            let button = map_button(button);
+
+                            // Mapped buttons also press a key, so keyboard-only
+                            // images can be played with a controller
+                            let res = event_sender
+                                .send(Event::ButtonDown(button))
+                                .and_then(|_| match config.button_map.get(&button) {
+                                    Some(&key) => event_sender.send(Event::KeyDown(key)),
+                                    None => Ok(()),
+                                });
+
+                            if res.is_err() {
+                                break 'main;
+                            }
+                        }
+                        SDL2Event::ControllerButtonUp { button, .. } => {
+                            let button = map_button(button);
+
+                            let res = event_sender
+                                .send(Event::ButtonUp(button))
+                                .and_then(|_| match config.button_map.get(&button) {
+                                    Some(&key) => event_sender.send(Event::KeyUp(key)),
+                                    None => Ok(()),
+                                });
+
+                            if res.is_err() {
+                                break 'main;
+                            }
+                        }
+                        SDL2Event::ControllerAxisMotion { axis, value, .. } => {
+                            let value = (value as Float / i16::MAX as Float).clamp(-1.0, 1.0);
+                            let res = event_sender.send(Event::AxisMotion {
+                                axis: map_axis(axis),
+                                value,
+                            });
+
+                            if res.is_err() {
+                                break 'main;
+                            }
+                        }
+                        SDL2Event::MouseWheel { x, y, direction, .. } => {
+                            // Natural scrolling flips the reported direction
+                            let sign = if direction == MouseWheelDirection::Flipped {
+                                -1
+                            } else {
+                                1
+                            };
+
+                            let res = event_sender.send(Event::MouseWheel {
+                                dx: sign * x as Integer,
+                                dy: sign * y as Integer,
+                            });
+
+                            if res.is_err() {
+                                break 'main;
+                            }
+                        }
+                        SDL2Event::TextInput { text, .. } => {
+                            for c in text.chars() {
+                                if event_sender.send(Event::TextInput(c)).is_err() {
+                                    break 'main;
+                                }
+                            }
+                        }
                         SDL2Event::MouseMotion { x, y, .. } => {
                             let res = event_sender.send(
                                 Event::MouseMove {
@@ -172,6 +270,20 @@ pub fn start(
             last_event = new_event;
         }
 
+        // Show status messages like reload errors in the title bar
+        while let Ok(status) = status_receiver.try_recv() {
+            let title = if status.is_empty() {
+                config.title.clone()
+            } else {
+                format!("{} - {}", config.title, status)
+            };
+
+            canvas
+                .window_mut()
+                .set_title(&title)
+                .chain_err(|| "unable to set window title")?;
+        }
+
         // Receive a frame
         let maybe_frame = frame_receiver.try_recv();
         if let Ok(frame) = maybe_frame {
@@ -179,7 +291,7 @@ pub fn start(
             let mut index = 0;
             for y_coord in 0..config.display.resolution.height {
                 for x_coord in 0..config.display.resolution.width {
-                    if let Some(&(r, g, b)) = frame.get(index) {
+                    if let Some((r, g, b)) = frame.color(index) {
                         canvas.set_draw_color(Color::RGB(r, g, b));
                         canvas.draw_point((x_coord as i32, y_coord as i32))?;
                         index += 1;
@@ -199,3 +311,73 @@ pub fn start(
 
     Ok(())
 }
+
+fn map_button(button: SDL2Button) -> Button {
+    match button {
+        SDL2Button::A => Button::A,
+        SDL2Button::B => Button::B,
+        SDL2Button::X => Button::X,
+        SDL2Button::Y => Button::Y,
+        SDL2Button::Back => Button::Back,
+        SDL2Button::Guide => Button::Guide,
+        SDL2Button::Start => Button::Start,
+        SDL2Button::LeftStick => Button::LeftStick,
+        SDL2Button::RightStick => Button::RightStick,
+        SDL2Button::LeftShoulder => Button::LeftShoulder,
+        SDL2Button::RightShoulder => Button::RightShoulder,
+        SDL2Button::DPadUp => Button::DPadUp,
+        SDL2Button::DPadDown => Button::DPadDown,
+        SDL2Button::DPadLeft => Button::DPadLeft,
+        SDL2Button::DPadRight => Button::DPadRight,
+    }
+}
+
+fn map_axis(axis: SDL2Axis) -> Axis {
+    match axis {
+        SDL2Axis::LeftX => Axis::LeftX,
+        SDL2Axis::LeftY => Axis::LeftY,
+        SDL2Axis::RightX => Axis::RightX,
+        SDL2Axis::RightY => Axis::RightY,
+        SDL2Axis::TriggerLeft => Axis::TriggerLeft,
+        SDL2Axis::TriggerRight => Axis::TriggerRight,
+    }
+}
+
+/// Plays the audio unit, applying the commands of the VM as they arrive
+struct Speaker {
+    unit: AudioUnit,
+    receiver: Receiver<AudioCommand>,
+}
+
+impl AudioCallback for Speaker {
+    type Channel = f32;
+
+    fn callback(&mut self, output: &mut [f32]) {
+        while let Ok(command) = self.receiver.try_recv() {
+            self.unit.apply(&command);
+        }
+
+        self.unit.render(output);
+    }
+}
+
+fn open_audio(sdl_context: &sdl2::Sdl, receiver: Receiver<AudioCommand>) -> Result<AudioDevice<Speaker>> {
+    let audio_subsystem = sdl_context.audio()?;
+
+    let spec = AudioSpecDesired {
+        freq: Some(SAMPLE_RATE as i32),
+        channels: Some(1),
+        samples: None,
+    };
+
+    let device = audio_subsystem
+        .open_playback(None, &spec, |spec| Speaker {
+            unit: AudioUnit::new(spec.freq as u32),
+            receiver,
+        })
+        .map_err(|e| format!("unable to open audio device: {}", e))?;
+
+    device.resume();
+
+    Ok(device)
+}
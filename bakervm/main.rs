@@ -2,7 +2,6 @@ extern crate core;
 #[macro_use]
 extern crate error_chain;
 extern crate rand;
-extern crate rmp_serde;
 extern crate sdl2;
 extern crate serde;
 #[macro_use]
@@ -11,17 +10,23 @@ extern crate serde_derive;
 extern crate structopt;
 
 mod vm;
+mod audio;
+mod framebuffer;
+mod graphics;
+mod headless;
 mod io;
+mod sprites;
+mod tilemap;
+mod watch;
 
-use core::Program;
+use core::{Button, Program};
 use core::error::*;
 use core::typedef::*;
-use rmp_serde::Deserializer;
-use serde::Deserialize;
-use std::fs::File;
-use std::io::Read;
+use sdl2::keyboard::Keycode;
 use std::path::PathBuf;
+use std::str::FromStr;
 use std::sync::{mpsc, Arc, Barrier};
+use std::sync::atomic::Ordering;
 use structopt::StructOpt;
 
 fn main() {
@@ -48,31 +53,80 @@ struct Opt {
     input: Option<PathBuf>,
     #[structopt(help = "Sets the scale for the display. If not specified, the default scale set by the image will be used.")]
     scale: Option<f64>,
+    #[structopt(short = "w", long = "watch",
+                help = "Reloads the image whenever the image file changes")]
+    watch: bool,
+    #[structopt(long = "keep-state",
+                help = "Keeps the value-index and the stack when reloading the image. Resets the whole VM otherwise.")]
+    keep_state: bool,
+    #[structopt(long = "verify",
+                help = "Verifies images before running them and rejects images that would fail while running")]
+    verify: bool,
+    #[structopt(long = "headless", conflicts_with = "watch",
+                help = "Runs the image without a display or a sound card. Input is never received, so the image halts once it waits for events.")]
+    headless: bool,
+    #[structopt(long = "wav", parse(from_os_str), requires = "headless",
+                help = "Writes the audio of a headless run to a WAV file")]
+    wav: Option<PathBuf>,
+    #[structopt(long = "frames", requires = "headless",
+                help = "Stops a headless run after the given number of flushed frames")]
+    frames: Option<usize>,
+    #[structopt(long = "map-button", value_name = "BUTTON=KEY", number_of_values = 1,
+                help = "Maps a controller button to a key code or an SDL key name. Overrides the mapping of the image")]
+    button_mappings: Vec<ButtonMapping>,
 }
 
-fn run() -> Result<()> {
-    let opt = Opt::from_args();
+/// A mapping from a controller button to the key it presses
+#[derive(Debug)]
+struct ButtonMapping {
+    button: Button,
+    key: Address,
+}
+
+impl FromStr for ButtonMapping {
+    type Err = &'static str;
 
-    let program: Program = if let Some(input) = opt.input {
-        let mut file = File::open(input).chain_err(|| "unable to open file")?;
-        let mut buf: ImageData = ImageData::new();
-        file.read_to_end(&mut buf)
-            .chain_err(|| "unable to read from file")?;
+    fn from_str(s: &str) -> ::std::result::Result<Self, Self::Err> {
+        let mut split = s.splitn(2, '=');
 
-        let mut de = Deserializer::new(&buf[..]);
+        let button = split.next().unwrap_or_default().trim().parse()?;
+        let key = split
+            .next()
+            .ok_or("unable to parse button mapping. Mappings must look like BUTTON=KEY")?
+            .trim();
 
-        Deserialize::deserialize(&mut de).chain_err(|| "unable to decode image file")?
+        let key = if let Ok(key_code) = key.parse() {
+            key_code
+        } else if let Some(key_code) = Keycode::from_name(key) {
+            key_code as Address
+        } else {
+            return Err("unknown key. Keys must be key codes or SDL key names like Space or W");
+        };
+
+        Ok(ButtonMapping { button, key })
+    }
+}
+
+fn run() -> Result<()> {
+    let opt = Opt::from_args();
+
+    let program: Program = if let Some(ref input) = opt.input {
+        watch::read_image(input)?
     } else {
         let program_data = include_bytes!("stock.img");
 
-        let mut de = Deserializer::new(&program_data[..]);
-
-        Deserialize::deserialize(&mut de).chain_err(|| "unable to decode image file")?
+        Program::decode(&program_data[..])?
     };
 
     let mut config = program.config.clone();
 
-    config.display.default_scale = opt.scale.unwrap_or(core::DEFAULT_SCALE);
+    if let Some(scale) = opt.scale {
+        config.display.default_scale = scale;
+    }
+
+    for mapping in &opt.button_mappings {
+        config.button_map.insert(mapping.button, mapping.key);
+    }
 
     if config.display.default_scale < 1.0 {
         bail!("Display scale can't be less than 1");
@@ -81,11 +135,66 @@ fn run() -> Result<()> {
     let (vm_sender, outer_receiver) = mpsc::sync_channel(1);
     let (outer_sender, vm_receiver) = mpsc::channel();
 
-    let barrier = Arc::new(Barrier::new(2));
+    let (status_sender, status_receiver) = mpsc::channel();
+    let (audio_sender, audio_receiver) = mpsc::channel();
+
+    let reload = if opt.watch {
+        let input = if let Some(input) = opt.input {
+            input
+        } else {
+            bail!("the stock image can't be watched. Specify an image file to watch");
+        };
+
+        let (program_sender, program_receiver) = mpsc::channel();
+
+        watch::start(input, program_sender, status_sender.clone());
 
-    let vm_handle = vm::start(program, vm_sender, vm_receiver, barrier.clone());
+        Some(vm::Reload {
+            receiver: program_receiver,
+            keep_state: opt.keep_state,
+            status_sender,
+        })
+    } else {
+        None
+    };
+
+    let barrier = Arc::new(Barrier::new(2));
 
-    io::start(outer_receiver, outer_sender, config, barrier.clone())?;
+    let (vm_handle, halt_flag) = vm::start(
+        program,
+        vm_sender,
+        vm_receiver,
+        barrier.clone(),
+        reload,
+        Some(audio_sender),
+        opt.verify,
+    );
+
+    if opt.headless {
+        // Without events, the VM halts as soon as it waits for one
+        drop(outer_sender);
+
+        headless::start(
+            outer_receiver,
+            audio_receiver,
+            barrier.clone(),
+            opt.wav.as_deref(),
+            opt.frames,
+        )?;
+
+        // The VM only notices the missing receiver when it flushes again,
+        // which an image that loops without flushing never does
+        halt_flag.store(true, Ordering::SeqCst);
+    } else {
+        io::start(
+            outer_receiver,
+            outer_sender,
+            status_receiver,
+            audio_receiver,
+            config,
+            barrier.clone(),
+        )?;
+    }
 
     if let Err(err) = vm_handle.join() {
         bail!("unable to join: {:?}", err);
@@ -0,0 +1,300 @@
+//! The sprite table, which is drawn on top of the layers on every flush
+
+use core::{Asset, AssetKind, BlitOptions, DisplayResolution, SPRITE_COUNT};
+use core::error::*;
+use core::typedef::*;
+use framebuffer::{Framebuffer, Ink};
+use graphics;
+
+/// An entry of the sprite table
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+struct Sprite {
+    /// The index of the sprite asset
+    asset: Address,
+    position: (Integer, Integer),
+    /// Sprites with a higher z-order are drawn on top of the others. Of the
+    /// sprites with the same z-order, the ones in lower slots are on top.
+    z: Integer,
+    flip: (bool, bool),
+    visible: bool,
+}
+
+/// The collisions of a sprite during the last flush
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+struct Collisions {
+    /// A bit for every slot of a sprite, that was touched
+    sprites: u64,
+    /// Whether a solid pixel of the layers was touched
+    background: bool,
+}
+
+/// The sprites drawn on top of the layers and their collisions during the
+/// last flush
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct SpriteTable {
+    sprites: Vec<Sprite>,
+    collisions: Vec<Collisions>,
+}
+
+impl SpriteTable {
+    /// Shows the sprite asset in the given slot at the given position
+    pub fn show(
+        &mut self, assets: &[Asset], slot: Address, asset: Address, position: (Integer, Integer)
+    ) -> Result<()> {
+        sprite_asset(assets, asset)?;
+
+        let sprite = self.sprite_mut(slot)?;
+        sprite.asset = asset;
+        sprite.position = position;
+        sprite.visible = true;
+
+        Ok(())
+    }
+
+    /// Sets the z-order and the flip flags of the sprite in the given slot
+    pub fn set_attributes(&mut self, slot: Address, z: Integer, flip: (bool, bool)) -> Result<()> {
+        let sprite = self.sprite_mut(slot)?;
+        sprite.z = z;
+        sprite.flip = flip;
+
+        Ok(())
+    }
+
+    pub fn hide(&mut self, slot: Address) -> Result<()> {
+        self.sprite_mut(slot)?.visible = false;
+
+        Ok(())
+    }
+
+    /// Returns the lowest slot of the sprites, that the sprite in the given
+    /// slot touched during the last flush, plus 1. Returns 0 if it touched no
+    /// sprites.
+    pub fn sprite_hit(&self, slot: Address) -> Result<Address> {
+        let sprites = self.collisions(slot)?.sprites;
+
+        if sprites == 0 {
+            Ok(0)
+        } else {
+            Ok(sprites.trailing_zeros() as Address + 1)
+        }
+    }
+
+    /// Returns whether the sprites in the two given slots touched each other
+    /// during the last flush
+    pub fn sprites_touched(&self, slot: Address, other: Address) -> Result<bool> {
+        check_slot(other)?;
+
+        Ok(self.collisions(slot)?.sprites & 1 << other != 0)
+    }
+
+    /// Returns whether the sprite in the given slot touched a solid pixel of
+    /// the layers during the last flush
+    pub fn background_hit(&self, slot: Address) -> Result<bool> {
+        Ok(self.collisions(slot)?.background)
+    }
+
+    /// Draws the visible sprites to the composited frame and records their
+    /// collisions with each other and with the solid pixels of the layers.
+    /// Only pixels on the display collide.
+    pub fn render(
+        &mut self, layers: &Framebuffer, frame: &mut Framebuffer, assets: &[Asset],
+        resolution: &DisplayResolution
+    ) -> Result<()> {
+        let mut collisions = vec![Collisions::default(); SPRITE_COUNT];
+
+        let mut slots: Vec<usize> = (0..self.sprites.len())
+            .rev()
+            .filter(|&slot| self.sprites[slot].visible)
+            .collect();
+
+        // The sort is stable, so lower slots are still drawn last
+        slots.sort_by_key(|&slot| self.sprites[slot].z);
+
+        let solid = if slots.is_empty() {
+            Vec::new()
+        } else {
+            layers.solid_pixels(resolution)
+        };
+
+        // The slots of the sprites drawn to every pixel of the display as bits
+        let mut owners = vec![0u64; solid.len()];
+
+        for slot in slots {
+            let sprite = &self.sprites[slot];
+            let asset = sprite_asset(assets, sprite.asset)?;
+            let size = (asset.width, asset.height);
+            let pixel = |x: usize, y: usize| Ink::from_rgba(&asset.data[(y * asset.width + x) * 4..]);
+
+            let hits = &mut collisions[slot];
+
+            graphics::for_each_pixel(resolution, sprite.position, size, sprite.flip, |index, x, y| {
+                if pixel(x, y).is_none() {
+                    return;
+                }
+
+                if let Some(owner) = owners.get_mut(index) {
+                    hits.sprites |= *owner;
+                    *owner |= 1 << slot;
+                }
+
+                if solid.get(index) == Some(&true) {
+                    hits.background = true;
+                }
+            });
+
+            let options = BlitOptions {
+                flip_x: sprite.flip.0,
+                flip_y: sprite.flip.1,
+                color_key: None,
+            };
+
+            frame.blit(0, resolution, sprite.position, size, &options, pixel)?;
+        }
+
+        // Sprites drawn first don't know about the sprites drawn on top of them
+        for slot in 0..SPRITE_COUNT {
+            for other in 0..SPRITE_COUNT {
+                if collisions[slot].sprites & 1 << other != 0 {
+                    collisions[other].sprites |= 1 << slot;
+                }
+            }
+        }
+
+        self.collisions = collisions;
+
+        Ok(())
+    }
+
+    fn sprite_mut(&mut self, slot: Address) -> Result<&mut Sprite> {
+        check_slot(slot)?;
+
+        if self.sprites.len() <= slot {
+            self.sprites.resize(slot + 1, Sprite::default());
+        }
+
+        Ok(&mut self.sprites[slot])
+    }
+
+    fn collisions(&self, slot: Address) -> Result<Collisions> {
+        check_slot(slot)?;
+
+        Ok(self.collisions.get(slot).cloned().unwrap_or_default())
+    }
+}
+
+fn check_slot(slot: Address) -> Result<()> {
+    ensure!(
+        slot < SPRITE_COUNT,
+        "sprite {} is outside of the {} entries of the sprite table",
+        slot,
+        SPRITE_COUNT
+    );
+
+    Ok(())
+}
+
+/// Returns the sprite asset with the given index
+fn sprite_asset(assets: &[Asset], index: Address) -> Result<&Asset> {
+    if let Some(asset) = assets.get(index) {
+        ensure!(
+            asset.kind == AssetKind::Sprite && asset.expected_len() == Some(asset.data.len()),
+            "asset {:?} is not a valid sprite",
+            asset.name
+        );
+
+        Ok(asset)
+    } else {
+        bail!("no asset found at index {}", index);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::ColorMode;
+
+    #[test]
+    fn render_and_collide() {
+        let resolution = DisplayResolution {
+            width: 4,
+            height: 1,
+        };
+
+        // A red 2x1 sprite with a transparent right pixel and a green 2x1 one
+        let assets = vec![
+            Asset::sprite("red".into(), 2, 1, vec![255, 0, 0, 255, 0, 0, 0, 0]),
+            Asset::sprite("green".into(), 2, 1, vec![0, 255, 0, 255, 0, 255, 0, 255]),
+            Asset::data("level".into(), vec![1]),
+        ];
+
+        let mut layers = Framebuffer::new(ColorMode::Direct, 4);
+        layers.set(1, 3, Ink::Color((0, 0, 255))).unwrap();
+
+        let mut table = SpriteTable::default();
+        table.show(&assets, 0, 0, (1, 0)).unwrap();
+        table.show(&assets, 5, 1, (0, 0)).unwrap();
+        table.show(&assets, 7, 1, (2, 0)).unwrap();
+
+        let mut frame = layers.composite(&resolution);
+        table.render(&layers, &mut frame, &assets, &resolution).unwrap();
+
+        // Both green sprites are drawn below the red one, whose transparent
+        // pixel shows the green below
+        let colors: Vec<_> = (0..4).map(|x| frame.color(x).unwrap()).collect();
+        assert_eq!(colors, vec![(0, 255, 0), (255, 0, 0), (0, 255, 0), (0, 255, 0)]);
+
+        // The sprites are sorted by their z-order first
+        table.set_attributes(5, 1, (false, false)).unwrap();
+        let mut frame = layers.composite(&resolution);
+        table.render(&layers, &mut frame, &assets, &resolution).unwrap();
+        assert_eq!(frame.color(1), Some((0, 255, 0)));
+
+        // Transparent pixels don't collide, so the red sprite only touches
+        // the sprite in slot 5
+        assert_eq!(table.sprite_hit(0).unwrap(), 6);
+        assert_eq!(table.sprite_hit(5).unwrap(), 1);
+        assert_eq!(table.sprite_hit(7).unwrap(), 0);
+        assert!(table.sprites_touched(0, 5).unwrap());
+        assert!(table.sprites_touched(5, 0).unwrap());
+        assert!(!table.sprites_touched(0, 7).unwrap());
+        assert!(table.background_hit(7).unwrap());
+        assert!(!table.background_hit(0).unwrap());
+
+        table.hide(7).unwrap();
+        let mut frame = layers.composite(&resolution);
+        table.render(&layers, &mut frame, &assets, &resolution).unwrap();
+        assert_eq!(frame.color(2), Some((0, 0, 0)));
+        assert!(!table.background_hit(7).unwrap());
+
+        assert!(table.show(&assets, 1, 2, (0, 0)).is_err());
+        assert!(table.hide(SPRITE_COUNT).is_err());
+        assert!(table.sprite_hit(SPRITE_COUNT).is_err());
+        assert!(table.sprites_touched(0, SPRITE_COUNT).is_err());
+    }
+
+    #[test]
+    fn flipped_collisions() {
+        let resolution = DisplayResolution {
+            width: 3,
+            height: 1,
+        };
+
+        // A 2x1 sprite, whose left pixel is transparent
+        let assets = vec![Asset::sprite("half".into(), 2, 1, vec![0, 0, 0, 0, 255, 255, 255, 255])];
+
+        let layers = Framebuffer::new(ColorMode::Direct, 3);
+        let mut table = SpriteTable::default();
+        table.show(&assets, 0, 0, (0, 0)).unwrap();
+        table.show(&assets, 1, 0, (1, 0)).unwrap();
+
+        let mut frame = layers.composite(&resolution);
+        table.render(&layers, &mut frame, &assets, &resolution).unwrap();
+        assert_eq!(table.sprite_hit(0).unwrap(), 0);
+
+        // Flipping the second sprite moves its opaque pixel onto the first one
+        table.set_attributes(1, 0, (true, false)).unwrap();
+        table.render(&layers, &mut frame, &assets, &resolution).unwrap();
+        assert_eq!(table.sprite_hit(0).unwrap(), 2);
+        assert_eq!(table.sprite_hit(1).unwrap(), 1);
+    }
+}
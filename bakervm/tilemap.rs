@@ -0,0 +1,228 @@
+//! The tile engine, which renders tilemaps into the layers of the framebuffer
+
+use core::{Asset, AssetKind, BlitOptions, DisplayResolution};
+use core::error::*;
+use core::typedef::*;
+use framebuffer::{Framebuffer, Ink};
+
+/// A tilemap bound to a layer of the framebuffer
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Tilemap {
+    /// The index of the tileset asset the tiles are drawn from
+    tileset: Address,
+    width: usize,
+    height: usize,
+    /// The tiles row by row. 0 is an empty tile, all others are the index of
+    /// a tile plus 1.
+    tiles: Vec<Address>,
+    /// The position of the top left corner of the display inside of the map
+    /// in pixels
+    pub scroll: (Integer, Integer),
+}
+
+impl Tilemap {
+    /// Creates a tilemap of the given size, checking its tiles against the
+    /// tileset
+    pub fn new(
+        assets: &[Asset], tileset: Address, width: usize, height: usize, tiles: Vec<Address>
+    ) -> Result<Tilemap> {
+        ensure!(
+            Some(tiles.len()) == width.checked_mul(height),
+            "a tilemap of size {}x{} can't hold {} tiles",
+            width,
+            height,
+            tiles.len()
+        );
+
+        let tile_count = tileset_asset(assets, tileset)?.1;
+
+        for &tile in &tiles {
+            check_tile(tile, tile_count)?;
+        }
+
+        Ok(Tilemap {
+            tileset,
+            width,
+            height,
+            tiles,
+            scroll: (0, 0),
+        })
+    }
+
+    /// Returns the tile at the given position. Positions outside of the map
+    /// hold empty tiles.
+    pub fn get(&self, (x, y): (Integer, Integer)) -> Address {
+        self.index_of(x, y).map_or(0, |index| self.tiles[index])
+    }
+
+    /// Sets the tile at the given position. Positions outside of the map are
+    /// ignored.
+    pub fn set(&mut self, assets: &[Asset], (x, y): (Integer, Integer), tile: Address) -> Result<()> {
+        check_tile(tile, tileset_asset(assets, self.tileset)?.1)?;
+
+        if let Some(index) = self.index_of(x, y) {
+            self.tiles[index] = tile;
+        }
+
+        Ok(())
+    }
+
+    /// Draws the tiles visible on the display to the given layer. The rest of
+    /// the layer is transparent.
+    pub fn render(
+        &self, framebuffer: &mut Framebuffer, layer: Address, assets: &[Asset],
+        resolution: &DisplayResolution
+    ) -> Result<()> {
+        let (tileset, tile_count) = tileset_asset(assets, self.tileset)?;
+        let (tile_width, tile_height) = (tileset.width, tileset.height);
+        let tile_len = tile_width * tile_height * 4;
+
+        framebuffer.clear_transparent(layer)?;
+
+        let columns = visible_tiles(self.scroll.0, resolution.width, tile_width, self.width);
+        let rows = visible_tiles(self.scroll.1, resolution.height, tile_height, self.height);
+
+        for row in rows {
+            for column in columns.clone() {
+                let tile = self.tiles[row * self.width + column];
+
+                if tile == 0 || tile > tile_count {
+                    continue;
+                }
+
+                let pixels = &tileset.data[(tile - 1) * tile_len..tile * tile_len];
+
+                let position = (
+                    tile_position(column, tile_width, self.scroll.0),
+                    tile_position(row, tile_height, self.scroll.1),
+                );
+
+                framebuffer.blit(
+                    layer,
+                    resolution,
+                    position,
+                    (tile_width, tile_height),
+                    &BlitOptions::default(),
+                    |x, y| Ink::from_rgba(&pixels[(y * tile_width + x) * 4..]),
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn index_of(&self, x: Integer, y: Integer) -> Option<usize> {
+        if x < 0 || y < 0 || x as u64 >= self.width as u64 || y as u64 >= self.height as u64 {
+            None
+        } else {
+            Some(y as usize * self.width + x as usize)
+        }
+    }
+}
+
+/// Returns the tileset asset with the given index and the number of its tiles
+fn tileset_asset(assets: &[Asset], index: Address) -> Result<(&Asset, usize)> {
+    if let Some(asset) = assets.get(index) {
+        ensure!(
+            asset.kind == AssetKind::Tileset && asset.expected_len() == Some(asset.data.len()),
+            "asset {:?} is not a valid tileset",
+            asset.name
+        );
+
+        Ok((asset, asset.data.len() / (asset.width * asset.height * 4)))
+    } else {
+        bail!("no asset found at index {}", index);
+    }
+}
+
+fn check_tile(tile: Address, tile_count: usize) -> Result<()> {
+    ensure!(
+        tile <= tile_count,
+        "tile {} is outside of the tileset of {} tiles",
+        tile,
+        tile_count
+    );
+
+    Ok(())
+}
+
+/// Returns the range of the tiles of a map that are visible on a display of
+/// the given length, scrolled to the given position
+fn visible_tiles(
+    scroll: Integer, display_len: usize, tile_len: usize, map_len: usize
+) -> ::std::ops::Range<usize> {
+    let first = (scroll as i128).div_euclid(tile_len as i128);
+    let last = (scroll as i128 + display_len as i128 - 1).div_euclid(tile_len as i128);
+
+    let start = first.max(0).min(map_len as i128) as usize;
+    let end = (last + 1).max(0).min(map_len as i128) as usize;
+
+    start..end.max(start)
+}
+
+/// Returns the position of a tile on the display
+fn tile_position(tile: usize, tile_len: usize, scroll: Integer) -> Integer {
+    (tile as i128 * tile_len as i128 - scroll as i128) as Integer
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::ColorMode;
+
+    #[test]
+    fn render() {
+        let red = [255, 0, 0, 255];
+        let clear = [0, 0, 0, 0];
+
+        // Two 2x1 tiles. The second one has a transparent right pixel.
+        let mut data = Vec::new();
+        data.extend_from_slice(&red);
+        data.extend_from_slice(&red);
+        data.extend_from_slice(&red);
+        data.extend_from_slice(&clear);
+
+        let assets = vec![Asset::tileset("tiles".into(), 2, 1, data)];
+
+        let resolution = DisplayResolution {
+            width: 3,
+            height: 1,
+        };
+
+        let mut framebuffer = Framebuffer::new(ColorMode::Direct, 3);
+        framebuffer.clear(1, Ink::Color((0, 0, 255))).unwrap();
+
+        let mut tilemap = Tilemap::new(&assets, 0, 3, 1, vec![1, 0, 2]).unwrap();
+        tilemap.scroll = (1, 0);
+        tilemap.render(&mut framebuffer, 1, &assets, &resolution).unwrap();
+
+        let composited = framebuffer.composite(&resolution);
+        let colors: Vec<_> = (0..3).map(|x| composited.color(x).unwrap()).collect();
+
+        assert_eq!(colors, vec![(255, 0, 0), (0, 0, 0), (0, 0, 0)]);
+
+        tilemap.set(&assets, (1, 0), 1).unwrap();
+        assert_eq!(tilemap.get((1, 0)), 1);
+        assert_eq!(tilemap.get((-1, 0)), 0);
+        assert!(tilemap.set(&assets, (1, 0), 3).is_err());
+        assert!(Tilemap::new(&assets, 0, 2, 1, vec![1]).is_err());
+
+        tilemap.scroll = (-2, 0);
+        tilemap.render(&mut framebuffer, 1, &assets, &resolution).unwrap();
+
+        let composited = framebuffer.composite(&resolution);
+        let colors: Vec<_> = (0..3).map(|x| composited.color(x).unwrap()).collect();
+
+        assert_eq!(colors, vec![(0, 0, 0), (0, 0, 0), (255, 0, 0)]);
+    }
+
+    #[test]
+    fn visible_range() {
+        assert_eq!(visible_tiles(0, 160, 16, 100), 0..10);
+        assert_eq!(visible_tiles(8, 160, 16, 100), 0..11);
+        assert_eq!(visible_tiles(-20, 160, 16, 100), 0..9);
+        assert_eq!(visible_tiles(1590, 160, 16, 100), 99..100);
+        assert_eq!(visible_tiles(Integer::MIN, 160, 16, 100), 0..0);
+        assert_eq!(visible_tiles(Integer::MAX, 160, 16, 100), 100..100);
+    }
+}
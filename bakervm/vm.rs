@@ -1,18 +1,40 @@
-use core::{Config, Event, Instruction, Program, Signal, Target, Type, Value};
+use core::{self, Asset, AssetKind, BlitOptions, BlitSource, Config, Event, Instruction, Program, Signal, Target,
+           TilemapSource, Type, Value, Waveform, AXIS_COUNT, CHANNEL_COUNT, FIRST_AXIS_SLOT, LAYER_COUNT};
 use core::error::*;
 use core::typedef::*;
 use std::collections::{BTreeMap, BTreeSet, LinkedList};
 use std::env;
+use std::mem;
 use std::sync::{Arc, Barrier};
-use std::sync::mpsc::{Receiver, SyncSender, TrySendError};
+use std::sync::atomic::{self, AtomicBool};
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender, SyncSender, TrySendError};
 use std::thread::{self, JoinHandle};
-
-pub fn start(program: Program, sender: SyncSender<Frame>, receiver: Receiver<Event>, barrier: Arc<Barrier>)
-    -> JoinHandle<()> {
-    thread::spawn(
+use std::time::Duration;
+use audio::{AudioCommand, Envelope, Sample, MAX_FREQUENCY};
+use framebuffer::{Framebuffer, Ink};
+use sprites::SpriteTable;
+use tilemap::Tilemap;
+
+/// Runs the program on a new thread. Setting the returned flag halts the VM.
+pub fn start(
+    program: Program, sender: SyncSender<Framebuffer>, receiver: Receiver<Event>, barrier: Arc<Barrier>,
+    reload: Option<Reload>, audio: Option<Sender<AudioCommand>>, verify: bool
+) -> (JoinHandle<()>, Arc<AtomicBool>) {
+    let halt_flag = Arc::new(AtomicBool::new(false));
+    let vm_halt_flag = halt_flag.clone();
+
+    let handle = thread::spawn(
         move || {
             barrier.wait();
-            if let Err(ref e) = VM::default().exec(program, sender, receiver) {
+
+            let mut vm = VM {
+                verify,
+                audio,
+                halt_flag: Some(vm_halt_flag),
+                ..VM::default()
+            };
+
+            if let Err(ref e) = vm.exec(program, sender, receiver, reload) {
                 println!("error: {}", e);
 
                 for e in e.iter().skip(1) {
@@ -28,7 +50,9 @@ pub fn start(program: Program, sender: SyncSender<Frame>, receiver: Receiver<Eve
                 ::std::process::exit(1);
             }
         },
-    )
+    );
+
+    (handle, halt_flag)
 }
 
 /// Since rusts `std:::cmp::Ordering` doesn't implement serialization, we have
@@ -40,6 +64,33 @@ enum Ordering {
     Equal,
 }
 
+/// The interval in which a paused VM checks for new images
+const RELOAD_POLL_INTERVAL_MS: u64 = 100;
+
+/// The channels used for reloading the image while the VM is running
+pub struct Reload {
+    /// Receives the new programs to load
+    pub receiver: Receiver<Program>,
+    /// Keeps the value-index and the stack when loading a new program
+    pub keep_state: bool,
+    /// Sends status messages shown by the display. An empty message clears
+    /// the status.
+    pub status_sender: Sender<String>,
+}
+
+impl Reload {
+    /// Prints the given error and shows it on the display
+    fn report(&self, error: &Error) {
+        println!("error: {}", error);
+
+        for e in error.iter().skip(1) {
+            println!("caused by: {}", e);
+        }
+
+        let _ = self.status_sender.send(format!("error: {}", error));
+    }
+}
+
 const NUM_RESERVED_MEM_SLOTS: usize = 20;
 
 const FRAMEBUFFER_CURSOR_INDEX: Target = Target::ValueIndex(0);
@@ -50,12 +101,20 @@ const MOUSE_Y_INDEX: Target = Target::ValueIndex(4);
 const LEFT_MOUSE_INDEX: Target = Target::ValueIndex(5);
 const MIDDLE_MOUSE_INDEX: Target = Target::ValueIndex(6);
 const RIGHT_MOUSE_INDEX: Target = Target::ValueIndex(7);
+const WHEEL_X_INDEX: Target = Target::ValueIndex(14);
+const WHEEL_Y_INDEX: Target = Target::ValueIndex(15);
+const TEXT_LENGTH_INDEX: Target = Target::ValueIndex(16);
+
+/// The number of typed characters, that are kept until the program reads them
+const TEXT_INPUT_CAPACITY: usize = 256;
 
 /// The whole state of the VM
 #[derive(Serialize, Deserialize, Default, Debug)]
 struct VM {
     /// The instructions that are currently executed
     image_data: Vec<Instruction>,
+    /// The binary data sections of the program
+    assets: Vec<Asset>,
     /// The current program counter
     pc: Address,
     base_ptr: Address,
@@ -63,9 +122,19 @@ struct VM {
     value_index: BTreeMap<Address, Value>,
     /// A register containing all currently pressed keys
     key_register: BTreeSet<Address>,
-    framebuffer: Frame,
+    /// A register containing all currently pressed controller buttons
+    button_register: BTreeSet<Address>,
+    /// The characters typed by the user, that the program didn't read yet
+    text_input: LinkedList<char>,
+    framebuffer: Framebuffer,
     framebuffer_invalid: bool,
-    next_frame: Frame,
+    next_frame: Framebuffer,
+    /// The framebuffer layer drawing instructions draw to
+    layer: Address,
+    /// The tilemaps rendered into the framebuffer layers on every flush
+    tilemaps: BTreeMap<Address, Tilemap>,
+    /// The sprites drawn on top of the layers on every flush
+    sprites: SpriteTable,
     /// A register for holding information about a recent comparison
     cmp_register: Option<Ordering>,
     /// A stack to hold the return addresses of function calls
@@ -76,17 +145,85 @@ struct VM {
     config: Config,
     halted: bool,
     paused: bool,
+    /// Set if the program failed while reloading is enabled, so the VM waits
+    /// for a new image instead of exiting
+    failed: bool,
+    /// A new program that arrived while the VM was waiting for events
+    pending_program: Option<Program>,
+    /// Statically verifies programs before loading them
+    verify: bool,
+    /// Sends the commands of the audio instructions to the audio unit
+    #[serde(skip)]
+    audio: Option<Sender<AudioCommand>>,
+    /// Set by other threads to halt the VM, even if it never waits or flushes
+    #[serde(skip)]
+    halt_flag: Option<Arc<AtomicBool>>,
 }
 
 impl VM {
     // # Maintainance functions
 
-    /// Executes the given program
-    pub fn exec(&mut self, program: Program, sender: SyncSender<Frame>, receiver: Receiver<Event>)
-        -> Result<()> {
+    /// Executes the given program. If reloading is enabled, new programs are
+    /// loaded as soon as they arrive and errors are reported without exiting.
+    pub fn exec(
+        &mut self, program: Program, sender: SyncSender<Framebuffer>, receiver: Receiver<Event>,
+        reload: Option<Reload>
+    ) -> Result<()> {
+        self.init(&program)?;
+
+        while !self.halted && !self.halt_requested() {
+            if let Some(ref reload) = reload {
+                let next_program = self.pending_program
+                    .take()
+                    .or_else(|| reload.receiver.try_recv().ok());
+
+                if let Some(next_program) = next_program {
+                    match self.reload(&next_program, reload.keep_state) {
+                        Ok(()) => {
+                            let _ = reload.status_sender.send(String::new());
+                        }
+                        Err(ref e) => reload.report(e),
+                    }
+                }
+            }
+
+            if self.pc < self.image_data.len() && !self.failed {
+                if let Err(e) = self.do_cycle() {
+                    if let Some(ref reload) = reload {
+                        reload.report(&e);
+                        self.failed = true;
+                    } else {
+                        return Err(e);
+                    }
+                }
+            } else if reload.is_some() {
+                // Keep the display open until a new image arrives
+                self.paused = true;
+            } else {
+                break;
+            }
+
+            if self.framebuffer_invalid {
+                let res = sender.try_send(self.next_frame.clone());
+                if let Err(TrySendError::Disconnected(..)) = res {
+                    self.halt();
+                } else if let Ok(()) = res {
+                    self.framebuffer_invalid = false;
+                }
+            }
+
+            self.handle_events(&receiver, &sender, reload.as_ref())?;
+        }
+
+        Ok(())
+    }
+
+    /// Resets the VM and prepares it for executing the given program
+    fn init(&mut self, program: &Program) -> Result<()> {
         self.reset();
-        self.load_program(&program).chain_err(|| "invalid program container")?;
+        self.load_program(program).chain_err(|| "invalid program container")?;
         self.build_framebuffer();
+        self.reset_audio();
 
         self.push(&FRAMEBUFFER_CURSOR_INDEX, Value::Address(0))?;
 
@@ -106,21 +243,70 @@ impl VM {
         self.push(&MIDDLE_MOUSE_INDEX, Value::Boolean(false))?;
         self.push(&RIGHT_MOUSE_INDEX, Value::Boolean(false))?;
 
-        while (self.pc < self.image_data.len()) && !self.halted {
-            self.do_cycle()?;
+        for slot in FIRST_AXIS_SLOT..FIRST_AXIS_SLOT + AXIS_COUNT {
+            self.push(&Target::ValueIndex(slot), Value::Float(0.0))?;
+        }
 
-            if self.framebuffer_invalid {
-                let res = sender.try_send(self.next_frame.clone());
-                if let Err(TrySendError::Disconnected(..)) = res {
-                    self.halt();
-                } else if let Ok(()) = res {
-                    self.framebuffer_invalid = false;
-                }
-            }
+        self.push(&WHEEL_X_INDEX, Value::Integer(0))?;
+        self.push(&WHEEL_Y_INDEX, Value::Integer(0))?;
+        self.push(&TEXT_LENGTH_INDEX, Value::Address(0))?;
 
-            self.handle_events(&receiver, &sender)?;
+        Ok(())
+    }
+
+    /// Replaces the running program with the given one, starting it from the
+    /// beginning. The value-index, the stack and the framebuffer are kept if
+    /// requested. The running program is left untouched if the given one is
+    /// invalid.
+    fn reload(&mut self, program: &Program, keep_state: bool) -> Result<()> {
+        let mut vm = VM {
+            verify: self.verify,
+            ..VM::default()
+        };
+
+        vm.load_program(program)
+            .chain_err(|| "invalid program container")?;
+
+        let resolution = &program.config.display.resolution;
+        let current_resolution = &self.config.display.resolution;
+
+        ensure!(
+            resolution.width == current_resolution.width &&
+                resolution.height == current_resolution.height,
+            "the display resolution of the new image differs. Restart the VM to apply it"
+        );
+
+        ensure!(
+            program.config.display.color_mode == self.config.display.color_mode,
+            "the color mode of the new image differs. Restart the VM to apply it"
+        );
+
+        let value_index = mem::take(&mut self.value_index);
+        let stack = mem::take(&mut self.stack);
+        let framebuffer = mem::take(&mut self.framebuffer);
+        let key_register = mem::take(&mut self.key_register);
+        let button_register = mem::take(&mut self.button_register);
+        let text_input = mem::take(&mut self.text_input);
+
+        self.init(program)?;
+
+        // Keys and buttons that are still held down are not pressed again,
+        // and typed characters are not lost
+        self.key_register = key_register;
+        self.button_register = button_register;
+        self.text_input = text_input;
+
+        if keep_state {
+            self.value_index = value_index;
+            self.stack = stack;
+            self.framebuffer = framebuffer;
         }
 
+        self.update_text_length()?;
+
+        self.next_frame = self.framebuffer.composite(&self.config.display.resolution);
+        self.invalidate_framebuffer();
+
         Ok(())
     }
 
@@ -173,21 +359,170 @@ impl VM {
             Instruction::Halt => self.halt(),
             Instruction::Pause => self.pause(),
             Instruction::Nop => {}
-            Instruction::Sig(signal) => self.sig(&signal),
+            Instruction::Sig(signal) => self.sig(&signal)?,
+
+            Instruction::Read(dest, asset) => self.read(&dest, asset)?,
+            Instruction::Blit(source, options) => self.blit(&source, &options)?,
+
+            Instruction::Clear => self.clear()?,
+            Instruction::Rect => self.rect()?,
+            Instruction::Line => self.line()?,
+            Instruction::HSpan => self.span(true)?,
+            Instruction::VSpan => self.span(false)?,
+
+            Instruction::DrawChar(font) => self.draw_char(font)?,
+            Instruction::DrawText(text, font) => self.draw_text(text, font)?,
+
+            Instruction::SetPalette => self.set_palette()?,
+            Instruction::RotatePalette => self.rotate_palette()?,
+
+            Instruction::Layer(layer) => self.select_layer(layer)?,
+            Instruction::Scroll(layer) => {
+                let position = self.pop_point()?;
+                self.framebuffer.scroll(layer, position)?;
+            }
+            Instruction::LayerKey(layer) => {
+                let ink = self.pop_ink()?;
+                self.framebuffer.set_key(layer, ink)?;
+            }
+
+            Instruction::Tilemap(layer, ref source, tileset) => {
+                self.bind_tilemap(layer, source, tileset)?
+            }
+            Instruction::SetTile(layer) => {
+                let tile = self.pop_tile()?;
+                let position = self.pop_point()?;
+                let tilemap = bound_tilemap(&mut self.tilemaps, layer)?;
+
+                tilemap.set(&self.assets, position, tile)?;
+            }
+            Instruction::GetTile(layer) => {
+                let position = self.pop_point()?;
+                let tile = bound_tilemap(&mut self.tilemaps, layer)?.get(position);
+
+                self.push(&Target::Stack, Value::Address(tile))?;
+            }
+            Instruction::ScrollMap(layer) => {
+                let position = self.pop_point()?;
+                bound_tilemap(&mut self.tilemaps, layer)?.scroll = position;
+            }
+
+            Instruction::Sprite(slot) => {
+                let position = self.pop_point()?;
+
+                let asset = if let Value::Address(asset) = self.pop(&Target::Stack)? {
+                    asset
+                } else {
+                    bail!("a sprite must be an asset like @0");
+                };
+
+                self.sprites.show(&self.assets, slot, asset, position)?;
+            }
+            Instruction::SpriteAttr(slot) => {
+                let flip_y = self.pop_flag()?;
+                let flip_x = self.pop_flag()?;
+                let z = self.pop_position()?;
+
+                self.sprites.set_attributes(slot, z, (flip_x, flip_y))?;
+            }
+            Instruction::HideSprite(slot) => self.sprites.hide(slot)?,
+            Instruction::SpriteHit(slot) => {
+                let hit = self.sprites.sprite_hit(slot)?;
+                self.push(&Target::Stack, Value::Address(hit))?;
+            }
+            Instruction::BgHit(slot) => {
+                let hit = self.sprites.background_hit(slot)?;
+                self.push(&Target::Stack, Value::Boolean(hit))?;
+            }
+            Instruction::SpriteHitPair(slot, other) => {
+                let hit = self.sprites.sprites_touched(slot, other)?;
+                self.push(&Target::Stack, Value::Boolean(hit))?;
+            }
+
+            Instruction::Voice(channel, waveform) => {
+                check_channel(channel)?;
+
+                if let Waveform::Sample(asset) = waveform {
+                    sample_asset(&self.assets, asset)?;
+                }
+
+                self.send_audio(AudioCommand::Voice(channel, waveform));
+            }
+            Instruction::Tone(channel) => {
+                check_channel(channel)?;
+
+                let volume = self.pop_level()?;
+                let frequency = self.pop_number()?;
+                ensure!(
+                    frequency.is_finite() && frequency >= 0.0,
+                    "a frequency must be a finite number of at least 0. Found {}",
+                    frequency
+                );
+
+                self.send_audio(AudioCommand::Tone {
+                    channel,
+                    frequency: frequency.min(Float::from(MAX_FREQUENCY)) as f32,
+                    volume: volume as f32,
+                });
+            }
+            Instruction::Envelope(channel) => {
+                check_channel(channel)?;
+
+                let release = self.pop_number()?;
+                let sustain = self.pop_level()?;
+                let decay = self.pop_number()?;
+                let attack = self.pop_number()?;
+
+                ensure!(
+                    attack >= 0.0 && decay >= 0.0 && release >= 0.0,
+                    "the times of an envelope can't be negative"
+                );
+
+                self.send_audio(AudioCommand::Envelope {
+                    channel,
+                    envelope: Envelope {
+                        attack: attack as f32,
+                        decay: decay as f32,
+                        sustain: sustain as f32,
+                        release: release as f32,
+                    },
+                });
+            }
+            Instruction::NoteOff(channel) => {
+                check_channel(channel)?;
+
+                self.send_audio(AudioCommand::NoteOff(channel));
+            }
         }
 
         Ok(())
     }
 
-    /// Loads the instructions of the given program to the VM's state
+    /// Loads the instructions of the given program to the VM's state. If
+    /// verification is enabled, programs that would fail while running are
+    /// rejected with a report of all problems found.
     fn load_program(&mut self, program: &Program) -> Result<()> {
         let orig_program = Program::default();
         if program.preamble != orig_program.preamble {
             bail!("invalid preamble");
-        } else if program.version != orig_program.version {
-            bail!("invalid version");
+        } else if program.format_version != orig_program.format_version {
+            bail!(
+                "unsupported image format version {}",
+                program.format_version
+            );
         } else {
+            if self.verify {
+                let diagnostics = core::verify(program);
+
+                if !diagnostics.is_empty() {
+                    let report: Vec<String> = diagnostics.iter().map(|d| d.to_string()).collect();
+
+                    bail!("verification failed:\n{}", report.join("\n"));
+                }
+            }
+
             self.image_data = program.instructions.clone();
+            self.assets = program.assets.clone();
             self.config = program.config.clone();
 
             Ok(())
@@ -216,27 +551,48 @@ impl VM {
     }
 
     /// Handles an internal signal
-    fn sig(&mut self, signal: &Signal) {
-        match signal {
-            &Signal::FlushFrame => {
-                self.next_frame = self.framebuffer.clone();
+    fn sig(&mut self, signal: &Signal) -> Result<()> {
+        match *signal {
+            Signal::FlushFrame => {
+                for (&layer, tilemap) in &self.tilemaps {
+                    tilemap.render(
+                        &mut self.framebuffer,
+                        layer,
+                        &self.assets,
+                        &self.config.display.resolution,
+                    )?;
+                }
+
+                self.next_frame = self.framebuffer.composite(&self.config.display.resolution);
+
+                self.sprites.render(
+                    &self.framebuffer,
+                    &mut self.next_frame,
+                    &self.assets,
+                    &self.config.display.resolution,
+                )?;
+
+                self.send_audio(AudioCommand::Frame);
                 self.invalidate_framebuffer();
             }
+            Signal::Unknown(index) => bail!("unknown signal with index {}", index),
         }
+
+        Ok(())
     }
 
     /// Handles incoming events
-    fn handle_events(&mut self, receiver: &Receiver<Event>, sender: &SyncSender<Frame>)
-        -> Result<()> {
+    fn handle_events(
+        &mut self, receiver: &Receiver<Event>, sender: &SyncSender<Framebuffer>, reload: Option<&Reload>
+    ) -> Result<()> {
         let event = if self.paused {
             self.paused = false;
             // We don't know how long this is going to take... better tell I/O what's going
             // on
             self.wait_flush_framebuffer(sender);
-            if let Ok(event) = receiver.recv() {
+            if let Some(event) = self.wait_event(receiver, reload) {
                 event
             } else {
-                self.halt();
                 return Ok(());
             }
         } else {
@@ -247,6 +603,11 @@ impl VM {
             }
         };
 
+        self.handle_event(event)
+    }
+
+    /// Applies an event to the registers and the reserved value-index slots
+    fn handle_event(&mut self, event: Event) -> Result<()> {
         match event {
             Event::Halt => self.halt(),
             Event::KeyDown(key_code) => {
@@ -279,14 +640,92 @@ impl VM {
                 self.push(&MOUSE_X_INDEX, Value::Address(x))?;
                 self.push(&MOUSE_Y_INDEX, Value::Address(y))?;
             }
+            Event::ButtonDown(button) => {
+                self.button_register.insert(button.index());
+            }
+            Event::ButtonUp(button) => {
+                self.button_register.remove(&button.index());
+            }
+            Event::AxisMotion { axis, value } => {
+                self.push(&Target::ValueIndex(axis.slot()), Value::Float(value))?;
+            }
+            Event::MouseWheel { dx, dy } => {
+                self.scroll(&WHEEL_X_INDEX, dx)?;
+                self.scroll(&WHEEL_Y_INDEX, dy)?;
+            }
+            Event::TextInput(c) => {
+                // Characters typed while the queue is full are dropped
+                if self.text_input.len() < TEXT_INPUT_CAPACITY {
+                    self.text_input.push_back(c);
+                    self.update_text_length()?;
+                }
+            }
         }
 
         Ok(())
     }
 
+    /// Adds the given amount to the scroll position in the given reserved
+    /// slot. The program resets the position by writing to the slot.
+    fn scroll(&mut self, slot: &Target, amount: Integer) -> Result<()> {
+        let position = match self.pop(slot) {
+            Ok(Value::Integer(position)) => position,
+            _ => 0,
+        };
+
+        self.push(slot, Value::Integer(position.saturating_add(amount)))
+    }
+
+    /// Writes the number of queued characters to its reserved slot
+    fn update_text_length(&mut self) -> Result<()> {
+        let length = self.text_input.len();
+
+        self.push(&TEXT_LENGTH_INDEX, Value::Address(length))
+    }
+
+    /// Blocks until an event arrives. Returns `None` if a new program arrived
+    /// in the meantime or if the VM was halted.
+    fn wait_event(&mut self, receiver: &Receiver<Event>, reload: Option<&Reload>)
+        -> Option<Event> {
+        let reload = if let Some(reload) = reload {
+            reload
+        } else {
+            let event = receiver.recv().ok();
+
+            if event.is_none() {
+                self.halt();
+            }
+
+            return event;
+        };
+
+        loop {
+            match receiver.recv_timeout(Duration::from_millis(RELOAD_POLL_INTERVAL_MS)) {
+                Ok(event) => return Some(event),
+                Err(RecvTimeoutError::Timeout) => {
+                    if let Ok(program) = reload.receiver.try_recv() {
+                        self.pending_program = Some(program);
+                        return None;
+                    }
+                }
+                Err(RecvTimeoutError::Disconnected) => {
+                    self.halt();
+                    return None;
+                }
+            }
+        }
+    }
+
+    /// Checks if another thread asked the VM to halt
+    fn halt_requested(&self) -> bool {
+        self.halt_flag
+            .as_ref()
+            .is_some_and(|flag| flag.load(atomic::Ordering::SeqCst))
+    }
+
     /// Waits for the channel to be available, then flushes the internal
     /// framebuffer using the given sender
-    fn wait_flush_framebuffer(&mut self, sender: &SyncSender<Frame>) {
+    fn wait_flush_framebuffer(&mut self, sender: &SyncSender<Framebuffer>) {
         if self.framebuffer_invalid {
             let res = sender.send(self.next_frame.clone());
             if let Err(..) = res {
@@ -301,12 +740,17 @@ impl VM {
     fn build_framebuffer(&mut self) {
         let ref resolution = self.config.display.resolution;
         let allocation_space = resolution.width * resolution.height;
-        self.framebuffer = vec![Color::default(); allocation_space];
+        self.framebuffer = Framebuffer::new(self.config.display.color_mode, allocation_space);
     }
 
-    /// Resets the VM to a clean state
+    /// Resets the VM to a clean state, keeping its settings
     fn reset(&mut self) {
-        *self = VM::default();
+        *self = VM {
+            verify: self.verify,
+            audio: self.audio.take(),
+            halt_flag: self.halt_flag.take(),
+            ..VM::default()
+        };
     }
 
     /// Locks the program counter in place
@@ -342,18 +786,10 @@ impl VM {
 
     /// Calculates the internal index
     fn internal_index(&mut self, index: Address) -> Result<Address> {
-        if index < NUM_RESERVED_MEM_SLOTS {
-            Ok(index)
-        } else {
-            let base_index = NUM_RESERVED_MEM_SLOTS + self.base_ptr - 1;
-            let offset = index - NUM_RESERVED_MEM_SLOTS;
-            let internal_index = base_index - offset;
-
-            if internal_index < NUM_RESERVED_MEM_SLOTS {
-                bail!("cannot access value without further allocation");
-            }
-
+        if let Some(internal_index) = value_slot(self.base_ptr, index) {
             Ok(internal_index)
+        } else {
+            bail!("cannot access value without further allocation");
         }
     }
 
@@ -377,17 +813,28 @@ impl VM {
                     bail!("unable to pop value off an empty stack");
                 }
             }
-            &Target::Framebuffer => {
+            &Target::Framebuffer | &Target::FramebufferLayer(..) => {
+                let layer = framebuffer_layer(target);
                 let index = self.get_framebuffer_index()?;
 
-                if let Some(&(r, g, b)) = self.framebuffer.get(index) {
-                    Ok(Value::Color(r, g, b))
+                if let Some(value) = self.framebuffer.get(layer, index)? {
+                    Ok(value)
                 } else {
                     bail!("no value found in framebuffer at index {}", index);
                 }
             }
             &Target::BasePointer => Ok(Value::Address(self.base_ptr)),
             &Target::KeyRegister(key_code) => Ok(Value::Boolean(self.key_register.contains(&key_code),),),
+            &Target::ButtonRegister(button) => Ok(Value::Boolean(self.button_register.contains(&button))),
+            &Target::TextInput => {
+                if let Some(c) = self.text_input.pop_front() {
+                    self.update_text_length()?;
+
+                    Ok(Value::Char(c))
+                } else {
+                    bail!("unable to read from an empty text input queue");
+                }
+            }
         }
     }
 
@@ -540,16 +987,12 @@ impl VM {
                 self.stack.push_front(value);
                 Ok(())
             }
-            &Target::Framebuffer => {
+            &Target::Framebuffer | &Target::FramebufferLayer(..) => {
+                let layer = framebuffer_layer(dest);
                 let index = self.get_framebuffer_index()?;
 
-                if index >= self.framebuffer.len() {
-                    return Ok(());
-                }
-
-                if let Value::Color(r, g, b) = value {
-                    self.framebuffer[index] = (r, g, b);
-                    Ok(())
+                if let Some(ink) = Ink::from_value(&value) {
+                    self.framebuffer.set(layer, index, ink)
                 } else {
                     bail!("unable push a non-color value to the framebuffer");
                 }
@@ -562,7 +1005,17 @@ impl VM {
                     bail!("unable set the base pointer to a non-address value");
                 }
             }
-            &Target::KeyRegister(..) => Ok(()),
+            &Target::KeyRegister(..) | &Target::ButtonRegister(..) => Ok(()),
+            &Target::TextInput => {
+                // Characters are put back in front, so comparisons don't
+                // consume them
+                if let Value::Char(c) = value {
+                    self.text_input.push_front(c);
+                    self.update_text_length()
+                } else {
+                    bail!("unable to push a non-char value to the text input queue");
+                }
+            }
         }
     }
 
@@ -613,15 +1066,523 @@ impl VM {
 
         Ok(())
     }
+
+    /// Reads the byte of the given asset at the offset on top of the stack
+    /// and pushes it to the dest target
+    fn read(&mut self, dest: &Target, asset: Address) -> Result<()> {
+        let offset = if let Value::Address(offset) = self.pop(&Target::Stack)? {
+            offset
+        } else {
+            bail!("the offset to read from must be an address");
+        };
+
+        let byte = if let Some(asset) = self.assets.get(asset) {
+            if let Some(&byte) = asset.data.get(offset) {
+                byte
+            } else {
+                bail!(
+                    "offset {} is outside of asset {:?} of length {}",
+                    offset,
+                    asset.name,
+                    asset.data.len()
+                );
+            }
+        } else {
+            bail!("no asset found at index {}", asset);
+        };
+
+        self.push(dest, Value::Integer(byte as Integer))
+    }
+
+    /// Draws a sprite to the framebuffer at the position on top of the stack.
+    /// The y position is popped first, followed by the x position.
+    fn blit(&mut self, source: &BlitSource, options: &BlitOptions) -> Result<()> {
+        let (x, y) = self.pop_point()?;
+
+        match *source {
+            BlitSource::Asset(index) => {
+                let asset = if let Some(asset) = self.assets.get(index) {
+                    asset
+                } else {
+                    bail!("no asset found at index {}", index);
+                };
+
+                ensure!(
+                    asset.kind == AssetKind::Sprite,
+                    "unable to blit asset {:?} of kind {}",
+                    asset.name,
+                    asset.kind
+                );
+
+                ensure!(
+                    asset.expected_len() == Some(asset.data.len()),
+                    "the data of sprite {:?} does not match its size",
+                    asset.name
+                );
+
+                let width = asset.width;
+
+                self.framebuffer.blit(
+                    self.layer,
+                    &self.config.display.resolution,
+                    (x, y),
+                    (asset.width, asset.height),
+                    options,
+                    |x, y| Ink::from_rgba(&asset.data[(y * width + x) * 4..]),
+                )?;
+            }
+            BlitSource::ValueIndex(index) => {
+                let width = self.size_at(index)?;
+                let height = self.size_at(index.saturating_add(1))?;
+
+                let len = if let Some(len) = width.checked_mul(height) {
+                    len
+                } else {
+                    bail!("the size of the sprite at index {} is too large", index);
+                };
+
+                // The size comes from the program, so the pixels are read while
+                // drawing, which only visits the pixels on the display. The last
+                // pixel must be allocated, so all the others are.
+                if len > 0 {
+                    self.internal_index(index.saturating_add(1).saturating_add(len))?;
+                }
+
+                let base_ptr = self.base_ptr;
+                let value_index = &self.value_index;
+
+                self.framebuffer.blit(
+                    self.layer,
+                    &self.config.display.resolution,
+                    (x, y),
+                    (width, height),
+                    options,
+                    |x, y| {
+                        let pixel = index.saturating_add(2).saturating_add(y * width + x);
+
+                        value_slot(base_ptr, pixel)
+                            .and_then(|slot| value_index.get(&slot))
+                            .and_then(Ink::from_value)
+                    },
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Fills the framebuffer with the color on top of the stack
+    fn clear(&mut self) -> Result<()> {
+        let ink = self.pop_ink()?;
+
+        self.framebuffer.clear(self.layer, ink)
+    }
+
+    /// Fills the rectangle between the two corners on top of the stack, with
+    /// the color below them
+    fn rect(&mut self) -> Result<()> {
+        let to = self.pop_point()?;
+        let from = self.pop_point()?;
+        let ink = self.pop_ink()?;
+
+        self.framebuffer.fill_rect(
+            self.layer,
+            &self.config.display.resolution,
+            from,
+            to,
+            ink,
+        )
+    }
+
+    /// Draws a line between the two points on top of the stack, with the
+    /// color below them
+    fn line(&mut self) -> Result<()> {
+        let to = self.pop_point()?;
+        let from = self.pop_point()?;
+        let ink = self.pop_ink()?;
+
+        self.framebuffer.line(
+            self.layer,
+            &self.config.display.resolution,
+            from,
+            to,
+            ink,
+        )
+    }
+
+    /// Draws a horizontal or vertical span. The length is on top of the stack,
+    /// followed by the start point and the color.
+    fn span(&mut self, horizontal: bool) -> Result<()> {
+        let len = self.pop_position()?;
+        let (x, y) = self.pop_point()?;
+        let ink = self.pop_ink()?;
+
+        if len <= 0 {
+            return Ok(());
+        }
+
+        let to = if horizontal {
+            (x.saturating_add(len - 1), y)
+        } else {
+            (x, y.saturating_add(len - 1))
+        };
+
+        self.framebuffer.fill_rect(
+            self.layer,
+            &self.config.display.resolution,
+            (x, y),
+            to,
+            ink,
+        )
+    }
+
+    /// Draws the char on top of the stack at the position below it, using the
+    /// color below the position
+    fn draw_char(&mut self, font: Option<Address>) -> Result<()> {
+        let character = if let Value::Char(character) = self.pop(&Target::Stack)? {
+            character
+        } else {
+            bail!("unable to draw a non-char value");
+        };
+
+        let position = self.pop_point()?;
+        let ink = self.pop_ink()?;
+
+        self.framebuffer.draw_text(
+            self.layer,
+            &self.config.display.resolution,
+            position,
+            font_asset(&self.assets, font)?,
+            &[character],
+            ink,
+        )
+    }
+
+    /// Draws the chars of the value-index starting at the given index. The
+    /// number of chars is on top of the stack, followed by the position and
+    /// the color.
+    fn draw_text(&mut self, text: Address, font: Option<Address>) -> Result<()> {
+        let len = if let Value::Address(len) = self.pop(&Target::Stack)? {
+            len
+        } else {
+            bail!("the length of a text must be an address");
+        };
+
+        let position = self.pop_point()?;
+        let ink = self.pop_ink()?;
+
+        // The length comes from the program, so nothing is allocated up front.
+        // A huge length fails at the first slot without a char.
+        let mut characters = Vec::new();
+
+        for offset in 0..len {
+            let index = text.saturating_add(offset);
+            let internal_index = self.internal_index(index)?;
+
+            if let Some(&Value::Char(character)) = self.value_index.get(&internal_index) {
+                characters.push(character);
+            } else {
+                bail!("unable to draw the non-char value at index {}", index);
+            }
+        }
+
+        self.framebuffer.draw_text(
+            self.layer,
+            &self.config.display.resolution,
+            position,
+            font_asset(&self.assets, font)?,
+            &characters,
+            ink,
+        )
+    }
+
+    /// Pops a coordinate from the stack, which may be an address, an integer
+    /// or a float
+    fn pop_position(&mut self) -> Result<Integer> {
+        match self.pop(&Target::Stack)? {
+            Value::Address(position) => Ok(position as Integer),
+            Value::Integer(position) => Ok(position),
+            Value::Float(position) => Ok(position as Integer),
+            _ => bail!("a position must be an address, an integer or a float"),
+        }
+    }
+
+    /// Pops a point from the stack. The y coordinate is popped first.
+    fn pop_point(&mut self) -> Result<(Integer, Integer)> {
+        let y = self.pop_position()?;
+        let x = self.pop_position()?;
+
+        Ok((x, y))
+    }
+
+    /// Pops the color to draw with from the stack, which is either a color or
+    /// a palette index
+    fn pop_ink(&mut self) -> Result<Ink> {
+        if let Some(ink) = Ink::from_value(&self.pop(&Target::Stack)?) {
+            Ok(ink)
+        } else {
+            bail!("unable to draw with a value that is neither a color nor a palette index");
+        }
+    }
+
+    /// Sets the palette entry below the top of the stack to the color on top
+    fn set_palette(&mut self) -> Result<()> {
+        let color = if let Value::Color(r, g, b) = self.pop(&Target::Stack)? {
+            (r, g, b)
+        } else {
+            bail!("a palette entry must be set to a color");
+        };
+
+        let index = self.pop_palette_index()?;
+
+        self.framebuffer.set_palette(index, color)
+    }
+
+    /// Rotates the palette entries between the two indices on top of the
+    /// stack. The last index is on top.
+    fn rotate_palette(&mut self) -> Result<()> {
+        let last = self.pop_palette_index()?;
+        let first = self.pop_palette_index()?;
+
+        self.framebuffer.rotate_palette(first, last)
+    }
+
+    /// Selects the framebuffer layer drawing instructions draw to
+    fn select_layer(&mut self, layer: Address) -> Result<()> {
+        check_layer(layer)?;
+
+        self.layer = layer;
+
+        Ok(())
+    }
+
+    /// Binds a tilemap to a layer, which is rendered into the layer on every
+    /// flush. Tilemaps are copied, so changes to their source are not shown.
+    fn bind_tilemap(&mut self, layer: Address, source: &TilemapSource, tileset: Address) -> Result<()> {
+        check_layer(layer)?;
+
+        let (width, height, tiles) = match *source {
+            TilemapSource::Asset(index) => {
+                let asset = if let Some(asset) = self.assets.get(index) {
+                    asset
+                } else {
+                    bail!("no asset found at index {}", index);
+                };
+
+                ensure!(
+                    asset.kind == AssetKind::Tilemap && asset.expected_len() == Some(asset.data.len()),
+                    "asset {:?} is not a valid tilemap",
+                    asset.name
+                );
+
+                (asset.width, asset.height, asset.tiles())
+            }
+            TilemapSource::ValueIndex(index) => {
+                let width = self.size_at(index)?;
+                let height = self.size_at(index.saturating_add(1))?;
+
+                let len = if let Some(len) = width.checked_mul(height) {
+                    len
+                } else {
+                    bail!("the size of the tilemap at index {} is too large", index);
+                };
+
+                // A huge size fails at the first missing tile
+                let mut tiles = Vec::new();
+
+                for tile in 0..len {
+                    tiles.push(self.size_at(index.saturating_add(2).saturating_add(tile))?);
+                }
+
+                (width, height, tiles)
+            }
+        };
+
+        let tilemap = Tilemap::new(&self.assets, tileset, width, height, tiles)?;
+        self.tilemaps.insert(layer, tilemap);
+
+        Ok(())
+    }
+
+    /// Silences the audio unit and hands it the samples of the program
+    fn reset_audio(&self) {
+        let samples = self.assets
+            .iter()
+            .enumerate()
+            .filter(|&(index, _)| sample_asset(&self.assets, index).is_ok())
+            .map(|(index, asset)| {
+                let sample = Sample {
+                    rate: asset.width as f32,
+                    data: asset.data.clone(),
+                };
+
+                (index, sample)
+            })
+            .collect();
+
+        self.send_audio(AudioCommand::Reset(samples));
+    }
+
+    /// Sends a command to the audio unit. Commands are dropped if there is
+    /// no audio unit.
+    fn send_audio(&self, command: AudioCommand) {
+        if let Some(ref audio) = self.audio {
+            let _ = audio.send(command);
+        }
+    }
+
+    /// Pops a parameter of a sound from the stack, which may be an address,
+    /// an integer or a float
+    fn pop_number(&mut self) -> Result<Float> {
+        match self.pop(&Target::Stack)? {
+            Value::Address(number) => Ok(number as Float),
+            Value::Integer(number) => Ok(number as Float),
+            Value::Float(number) => Ok(number),
+            _ => bail!("a parameter of a sound must be an address, an integer or a float"),
+        }
+    }
+
+    /// Pops a volume or a sustain level between 0 and 1 from the stack
+    fn pop_level(&mut self) -> Result<Float> {
+        let level = self.pop_number()?;
+        ensure!((0.0..=1.0).contains(&level), "a level must be between 0 and 1. Found {}", level);
+
+        Ok(level)
+    }
+
+    fn pop_flag(&mut self) -> Result<bool> {
+        if let Value::Boolean(flag) = self.pop(&Target::Stack)? {
+            Ok(flag)
+        } else {
+            bail!("a flip flag must be a boolean");
+        }
+    }
+
+    fn pop_tile(&mut self) -> Result<Address> {
+        if let Value::Address(tile) = self.pop(&Target::Stack)? {
+            Ok(tile)
+        } else {
+            bail!("a tile must be an address");
+        }
+    }
+
+    fn pop_palette_index(&mut self) -> Result<Address> {
+        if let Value::Address(index) = self.pop(&Target::Stack)? {
+            Ok(index)
+        } else {
+            bail!("a palette index must be an address");
+        }
+    }
+
+    /// Returns the address at the given index of the value-index without
+    /// consuming it
+    fn size_at(&mut self, index: Address) -> Result<Address> {
+        let internal_index = self.internal_index(index)?;
+
+        if let Some(&Value::Address(size)) = self.value_index.get(&internal_index) {
+            Ok(size)
+        } else {
+            bail!("the sprite size at index {} must be an address", index);
+        }
+    }
+}
+
+/// Returns the internal index of the given index of the value-index, or `None`
+/// if it isn't allocated
+fn value_slot(base_ptr: Address, index: Address) -> Option<Address> {
+    if index < NUM_RESERVED_MEM_SLOTS {
+        Some(index)
+    } else {
+        let offset = index - NUM_RESERVED_MEM_SLOTS;
+
+        // The base pointer is set by the program, so an inconsistent one must
+        // not overflow the calculation
+        base_ptr
+            .checked_sub(offset + 1)
+            .and_then(|index| index.checked_add(NUM_RESERVED_MEM_SLOTS))
+    }
+}
+
+fn check_layer(layer: Address) -> Result<()> {
+    ensure!(
+        layer < LAYER_COUNT,
+        "layer {} is outside of the {} layers of the framebuffer",
+        layer,
+        LAYER_COUNT
+    );
+
+    Ok(())
+}
+
+fn check_channel(channel: Address) -> Result<()> {
+    ensure!(
+        channel < CHANNEL_COUNT,
+        "channel {} is outside of the {} channels of the audio unit",
+        channel,
+        CHANNEL_COUNT
+    );
+
+    Ok(())
+}
+
+/// Returns the sample asset with the given index
+fn sample_asset(assets: &[Asset], index: Address) -> Result<&Asset> {
+    if let Some(asset) = assets.get(index) {
+        ensure!(
+            asset.kind == AssetKind::Sample && asset.expected_len() == Some(asset.data.len()),
+            "asset {:?} is not a valid sample",
+            asset.name
+        );
+
+        Ok(asset)
+    } else {
+        bail!("no asset found at index {}", index);
+    }
+}
+
+fn bound_tilemap(tilemaps: &mut BTreeMap<Address, Tilemap>, layer: Address) -> Result<&mut Tilemap> {
+    if let Some(tilemap) = tilemaps.get_mut(&layer) {
+        Ok(tilemap)
+    } else {
+        bail!("no tilemap is bound to layer {}", layer);
+    }
+}
+
+/// Returns the framebuffer layer of a framebuffer target
+fn framebuffer_layer(target: &Target) -> Address {
+    match *target {
+        Target::FramebufferLayer(layer) => layer,
+        _ => 0,
+    }
+}
+
+/// Returns the font asset with the given index or the built-in font
+fn font_asset(assets: &[Asset], font: Option<Address>) -> Result<&Asset> {
+    let index = if let Some(index) = font {
+        index
+    } else {
+        return Ok(core::builtin_font());
+    };
+
+    if let Some(asset) = assets.get(index) {
+        ensure!(
+            asset.kind == AssetKind::Font && asset.expected_len() == Some(asset.data.len()),
+            "asset {:?} is not a valid font",
+            asset.name
+        );
+
+        Ok(asset)
+    } else {
+        bail!("no asset found at index {}", index);
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use core::ImageBuilder;
+    use core::{Axis, Button, ColorMode, ImageBuilder};
     use rand;
-    use rmp_serde as rmps;
-    use serde::Deserialize;
+    use std::sync::mpsc;
 
     #[test]
     fn halt() {
@@ -631,6 +1592,23 @@ mod tests {
         assert!(vm.halted);
     }
 
+    #[test]
+    fn halt_flag() {
+        let mut builder = ImageBuilder::new();
+        builder.jmp(0);
+
+        let (sender, _outer_receiver) = mpsc::sync_channel(1);
+        let (_outer_sender, receiver) = mpsc::channel();
+
+        let mut vm = VM {
+            halt_flag: Some(Arc::new(AtomicBool::new(true))),
+            ..VM::default()
+        };
+
+        // The endless loop never flushes, so only the flag stops it
+        vm.exec(builder.gen_program(), sender, receiver, None).unwrap();
+    }
+
     #[test]
     fn swp() {
         for _ in 0..3000 {
@@ -761,9 +1739,7 @@ mod tests {
     fn load_stock_image() {
         let program_data = include_bytes!("stock.img");
 
-        let mut de = rmps::Deserializer::new(&program_data[..]);
-
-        let program = Deserialize::deserialize(&mut de).unwrap();
+        let program = Program::decode(&program_data[..]).unwrap();
 
         let mut vm = VM::default();
 
@@ -772,6 +1748,582 @@ mod tests {
         }
     }
 
+    #[test]
+    fn reload() {
+        let mut builder = ImageBuilder::new();
+        builder.push(Target::Stack, Value::Integer(1));
+        builder.push(Target::ValueIndex(19), Value::Integer(2));
+        let program = builder.gen_program();
+
+        let mut builder = ImageBuilder::new();
+        builder.nop();
+        let new_program = builder.gen_program();
+
+        let mut vm = VM::default();
+        vm.init(&program).unwrap();
+        vm.do_cycle().unwrap();
+        vm.do_cycle().unwrap();
+        vm.handle_event(Event::TextInput('h')).unwrap();
+        vm.handle_event(Event::TextInput('i')).unwrap();
+        vm.pop(&Target::TextInput).unwrap();
+
+        vm.reload(&new_program, true).unwrap();
+
+        assert_eq!(vm.pc, 0);
+        assert_eq!(vm.image_data.len(), 1);
+        assert_eq!(vm.stack.front(), Some(&Value::Integer(1)));
+        assert_eq!(vm.pop(&Target::ValueIndex(19)).unwrap(), Value::Integer(2));
+        assert_eq!(vm.pop(&TEXT_LENGTH_INDEX).unwrap(), Value::Address(1));
+
+        vm.reload(&program, false).unwrap();
+
+        assert!(vm.stack.is_empty());
+        assert!(vm.pop(&Target::ValueIndex(19)).is_err());
+        assert_eq!(vm.pop(&TEXT_LENGTH_INDEX).unwrap(), Value::Address(1));
+        assert_eq!(vm.pop(&Target::TextInput).unwrap(), Value::Char('i'));
+
+        let mut builder = ImageBuilder::new();
+        let mut config = Config::default();
+        config.display.resolution.width *= 2;
+        builder.set_config(config);
+        builder.nop();
+
+        assert!(vm.reload(&builder.gen_program(), false).is_err());
+        assert_eq!(vm.image_data.len(), 2);
+    }
+
+    #[test]
+    fn read() {
+        let mut vm = VM::default();
+        vm.assets.push(Asset::data("level".into(), vec![7, 200]));
+
+        vm.push(&Target::Stack, Value::Address(1)).unwrap();
+        vm.read(&Target::ValueIndex(8), 0).unwrap();
+
+        assert_eq!(vm.pop(&Target::ValueIndex(8)).unwrap(), Value::Integer(200));
+
+        vm.push(&Target::Stack, Value::Address(2)).unwrap();
+        assert!(vm.read(&Target::Stack, 0).is_err());
+
+        vm.push(&Target::Stack, Value::Address(0)).unwrap();
+        assert!(vm.read(&Target::Stack, 1).is_err());
+    }
+
+    #[test]
+    fn blit() {
+        let mut vm = VM::default();
+        vm.build_framebuffer();
+
+        let width = vm.config.display.resolution.width;
+
+        // A 2x1 sprite with an opaque red and a transparent pixel
+        vm.assets.push(Asset::sprite(
+            "player".into(),
+            2,
+            1,
+            vec![255, 0, 0, 255, 0, 255, 0, 0],
+        ));
+        vm.assets.push(Asset::data("level".into(), vec![1]));
+
+        vm.push(&Target::Stack, Value::Address(1)).unwrap();
+        vm.push(&Target::Stack, Value::Integer(2)).unwrap();
+        vm.blit(&BlitSource::Asset(0), &BlitOptions::default()).unwrap();
+
+        assert_eq!(vm.framebuffer.color(2 * width + 1), Some((255, 0, 0)));
+        assert_eq!(vm.framebuffer.color(2 * width + 2), Some((0, 0, 0)));
+
+        // The same sprite in the value-index, drawn mirrored
+        vm.push(&Target::ValueIndex(1), Value::Address(2)).unwrap();
+        vm.push(&Target::ValueIndex(2), Value::Address(1)).unwrap();
+        vm.push(&Target::ValueIndex(3), Value::Color(0, 0, 255)).unwrap();
+
+        let options = BlitOptions {
+            flip_x: true,
+            ..BlitOptions::default()
+        };
+
+        vm.push(&Target::Stack, Value::Integer(-1)).unwrap();
+        vm.push(&Target::Stack, Value::Address(0)).unwrap();
+        vm.blit(&BlitSource::ValueIndex(1), &options).unwrap();
+
+        assert_eq!(vm.framebuffer.color(0), Some((0, 0, 255)));
+
+        vm.push(&Target::Stack, Value::Address(0)).unwrap();
+        vm.push(&Target::Stack, Value::Address(0)).unwrap();
+        assert!(vm.blit(&BlitSource::Asset(1), &options).is_err());
+
+        // Sprites must not be larger than the allocated value-index
+        vm.push(&Target::ValueIndex(1), Value::Address(Address::MAX)).unwrap();
+        vm.push(&Target::ValueIndex(2), Value::Address(1)).unwrap();
+        vm.push(&Target::Stack, Value::Address(0)).unwrap();
+        vm.push(&Target::Stack, Value::Address(0)).unwrap();
+        assert!(vm.blit(&BlitSource::ValueIndex(1), &options).is_err());
+
+        // Only the pixels on the display are read
+        vm.base_ptr = Address::MAX - NUM_RESERVED_MEM_SLOTS;
+        vm.push(&Target::ValueIndex(1), Value::Address(1 << 32)).unwrap();
+        vm.push(&Target::ValueIndex(2), Value::Address(1 << 16)).unwrap();
+        vm.push(&Target::Stack, Value::Address(0)).unwrap();
+        vm.push(&Target::Stack, Value::Address(0)).unwrap();
+        vm.blit(&BlitSource::ValueIndex(1), &options).unwrap();
+    }
+
+    #[test]
+    fn primitives() {
+        let mut vm = VM::default();
+        vm.build_framebuffer();
+
+        let width = vm.config.display.resolution.width;
+        let height = vm.config.display.resolution.height;
+
+        vm.push(&Target::Stack, Value::Color(1, 2, 3)).unwrap();
+        vm.clear().unwrap();
+
+        for index in 0..width * height {
+            assert_eq!(vm.framebuffer.color(index), Some((1, 2, 3)));
+        }
+
+        vm.push(&Target::Stack, Value::Color(255, 0, 0)).unwrap();
+        vm.push(&Target::Stack, Value::Integer(-1)).unwrap();
+        vm.push(&Target::Stack, Value::Address(1)).unwrap();
+        vm.push(&Target::Stack, Value::Integer(3)).unwrap();
+        vm.handle_instruction(Instruction::HSpan).unwrap();
+
+        let row: Vec<_> = (width..width + 3)
+            .map(|index| vm.framebuffer.color(index).unwrap())
+            .collect();
+        assert_eq!(row, vec![(255, 0, 0), (255, 0, 0), (1, 2, 3)]);
+
+        vm.push(&Target::Stack, Value::Color(0, 255, 0)).unwrap();
+        vm.push(&Target::Stack, Value::Float(0.0)).unwrap();
+        vm.push(&Target::Stack, Value::Float(0.0)).unwrap();
+        vm.push(&Target::Stack, Value::Float(2.0)).unwrap();
+        vm.push(&Target::Stack, Value::Float(2.0)).unwrap();
+        vm.handle_instruction(Instruction::Line).unwrap();
+
+        assert_eq!(vm.framebuffer.color(2 * width + 2), Some((0, 255, 0)));
+
+        vm.push(&Target::Stack, Value::Address(0)).unwrap();
+        assert!(vm.clear().is_err());
+    }
+
+    #[test]
+    fn text() {
+        let mut vm = VM::default();
+        vm.build_framebuffer();
+
+        let width = vm.config.display.resolution.width;
+
+        vm.push(&Target::ValueIndex(1), Value::Char('T')).unwrap();
+        vm.push(&Target::ValueIndex(2), Value::Char('I')).unwrap();
+
+        vm.push(&Target::Stack, Value::Color(255, 0, 0)).unwrap();
+        vm.push(&Target::Stack, Value::Address(0)).unwrap();
+        vm.push(&Target::Stack, Value::Address(1)).unwrap();
+        vm.push(&Target::Stack, Value::Address(2)).unwrap();
+        vm.handle_instruction(Instruction::DrawText(1, None)).unwrap();
+
+        // The top rows of 'T' and 'I'
+        assert_eq!(vm.framebuffer.color(width + 4), Some((255, 0, 0)));
+        assert_eq!(vm.framebuffer.color(width + 5), Some((0, 0, 0)));
+        assert_eq!(vm.framebuffer.color(width + 6), Some((0, 0, 0)));
+        assert_eq!(vm.framebuffer.color(width + 7), Some((255, 0, 0)));
+
+        // Lengths of the program fail at the first missing char
+        vm.push(&Target::Stack, Value::Color(255, 0, 0)).unwrap();
+        vm.push(&Target::Stack, Value::Address(0)).unwrap();
+        vm.push(&Target::Stack, Value::Address(1)).unwrap();
+        vm.push(&Target::Stack, Value::Address(Address::MAX)).unwrap();
+        assert!(vm.handle_instruction(Instruction::DrawText(1, None)).is_err());
+
+        vm.push(&Target::Stack, Value::Color(0, 255, 0)).unwrap();
+        vm.push(&Target::Stack, Value::Address(0)).unwrap();
+        vm.push(&Target::Stack, Value::Address(0)).unwrap();
+        vm.push(&Target::Stack, Value::Char('-')).unwrap();
+        vm.handle_instruction(Instruction::DrawChar(None)).unwrap();
+
+        assert_eq!(vm.framebuffer.color(3 * width), Some((0, 255, 0)));
+
+        vm.assets.push(Asset::data("level".into(), vec![1]));
+
+        vm.push(&Target::Stack, Value::Color(0, 255, 0)).unwrap();
+        vm.push(&Target::Stack, Value::Address(0)).unwrap();
+        vm.push(&Target::Stack, Value::Address(0)).unwrap();
+        vm.push(&Target::Stack, Value::Char('-')).unwrap();
+        assert!(vm.handle_instruction(Instruction::DrawChar(Some(0))).is_err());
+    }
+
+    #[test]
+    fn palette() {
+        let mut vm = VM::default();
+        vm.config.display.color_mode = ColorMode::Indexed16;
+        vm.build_framebuffer();
+
+        vm.push(&Target::Stack, Value::Address(0)).unwrap();
+        vm.push(&Target::Framebuffer, Value::Address(4)).unwrap();
+        vm.push(&Target::Stack, Value::Address(0)).unwrap();
+        assert_eq!(vm.pop(&Target::Framebuffer).unwrap(), Value::Address(4));
+
+        vm.push(&Target::Stack, Value::Address(4)).unwrap();
+        vm.push(&Target::Stack, Value::Color(1, 2, 3)).unwrap();
+        vm.handle_instruction(Instruction::SetPalette).unwrap();
+
+        assert_eq!(vm.framebuffer.color(0), Some((1, 2, 3)));
+
+        vm.push(&Target::Stack, Value::Address(3)).unwrap();
+        vm.push(&Target::Stack, Value::Address(4)).unwrap();
+        vm.handle_instruction(Instruction::RotatePalette).unwrap();
+
+        assert_eq!(vm.framebuffer.color(0), Some((0x00, 0xaa, 0xaa)));
+
+        vm.push(&Target::Stack, Value::Address(16)).unwrap();
+        vm.push(&Target::Stack, Value::Color(1, 2, 3)).unwrap();
+        assert!(vm.handle_instruction(Instruction::SetPalette).is_err());
+
+        vm.push(&Target::Stack, Value::Address(0)).unwrap();
+        assert!(vm.push(&Target::Framebuffer, Value::Address(16)).is_err());
+    }
+
+    #[test]
+    fn layers() {
+        let mut vm = VM::default();
+        vm.build_framebuffer();
+
+        let width = vm.config.display.resolution.width;
+
+        vm.push(&Target::Stack, Value::Color(0, 0, 255)).unwrap();
+        vm.clear().unwrap();
+
+        vm.handle_instruction(Instruction::Layer(1)).unwrap();
+        vm.push(&Target::Stack, Value::Color(255, 0, 0)).unwrap();
+        vm.push(&Target::Stack, Value::Address(0)).unwrap();
+        vm.push(&Target::Stack, Value::Address(0)).unwrap();
+        vm.push(&Target::Stack, Value::Address(2)).unwrap();
+        vm.handle_instruction(Instruction::HSpan).unwrap();
+
+        vm.push(&Target::Stack, Value::Integer(1)).unwrap();
+        vm.push(&Target::Stack, Value::Integer(0)).unwrap();
+        vm.handle_instruction(Instruction::Scroll(1)).unwrap();
+
+        vm.push(&Target::ValueIndex(0), Value::Address(width)).unwrap();
+        vm.push(&Target::FramebufferLayer(2), Value::Color(0, 255, 0)).unwrap();
+
+        vm.handle_instruction(Instruction::Sig(Signal::FlushFrame)).unwrap();
+
+        // The bottom layer is untouched and the others are composited on top
+        assert_eq!(vm.framebuffer.color(0), Some((0, 0, 255)));
+        assert_eq!(vm.next_frame.color(0), Some((255, 0, 0)));
+        assert_eq!(vm.next_frame.color(1), Some((0, 0, 255)));
+        assert_eq!(vm.next_frame.color(width), Some((0, 255, 0)));
+
+        assert!(vm.handle_instruction(Instruction::Layer(LAYER_COUNT)).is_err());
+    }
+
+    #[test]
+    fn tilemaps() {
+        let mut vm = VM::default();
+        vm.build_framebuffer();
+
+        // A single red 1x1 tile
+        vm.assets.push(Asset::tileset("tiles".into(), 1, 1, vec![255, 0, 0, 255]));
+
+        // A 2x1 map whose first tile is empty
+        vm.push(&Target::ValueIndex(1), Value::Address(2)).unwrap();
+        vm.push(&Target::ValueIndex(2), Value::Address(1)).unwrap();
+        vm.push(&Target::ValueIndex(3), Value::Address(0)).unwrap();
+        vm.push(&Target::ValueIndex(4), Value::Address(1)).unwrap();
+
+        let bind = Instruction::Tilemap(1, TilemapSource::ValueIndex(1), 0);
+        vm.handle_instruction(bind).unwrap();
+        vm.handle_instruction(Instruction::Sig(Signal::FlushFrame)).unwrap();
+
+        assert_eq!(vm.next_frame.color(0), Some((0, 0, 0)));
+        assert_eq!(vm.next_frame.color(1), Some((255, 0, 0)));
+
+        vm.push(&Target::Stack, Value::Integer(0)).unwrap();
+        vm.push(&Target::Stack, Value::Integer(0)).unwrap();
+        vm.push(&Target::Stack, Value::Address(1)).unwrap();
+        vm.handle_instruction(Instruction::SetTile(1)).unwrap();
+
+        vm.push(&Target::Stack, Value::Integer(0)).unwrap();
+        vm.push(&Target::Stack, Value::Integer(0)).unwrap();
+        vm.handle_instruction(Instruction::GetTile(1)).unwrap();
+        assert_eq!(vm.pop(&Target::Stack).unwrap(), Value::Address(1));
+
+        vm.push(&Target::Stack, Value::Integer(1)).unwrap();
+        vm.push(&Target::Stack, Value::Integer(0)).unwrap();
+        vm.handle_instruction(Instruction::ScrollMap(1)).unwrap();
+        vm.handle_instruction(Instruction::Sig(Signal::FlushFrame)).unwrap();
+
+        assert_eq!(vm.next_frame.color(0), Some((255, 0, 0)));
+        assert_eq!(vm.next_frame.color(1), Some((0, 0, 0)));
+
+        vm.push(&Target::Stack, Value::Integer(0)).unwrap();
+        vm.push(&Target::Stack, Value::Integer(0)).unwrap();
+        assert!(vm.handle_instruction(Instruction::GetTile(2)).is_err());
+
+        // Sizes of the program fail at the first missing tile
+        vm.push(&Target::ValueIndex(1), Value::Address(Address::MAX)).unwrap();
+        let bind = Instruction::Tilemap(1, TilemapSource::ValueIndex(1), 0);
+        assert!(vm.handle_instruction(bind).is_err());
+        vm.push(&Target::ValueIndex(1), Value::Address(2)).unwrap();
+
+        vm.push(&Target::ValueIndex(4), Value::Address(2)).unwrap();
+        let bind = Instruction::Tilemap(1, TilemapSource::ValueIndex(1), 0);
+        assert!(vm.handle_instruction(bind).is_err());
+    }
+
+    #[test]
+    fn sprites() {
+        let mut vm = VM::default();
+        vm.build_framebuffer();
+
+        vm.assets.push(Asset::sprite("dot".into(), 1, 1, vec![255, 0, 0, 255]));
+
+        for &(slot, x) in &[(0, 1), (1, 1), (2, 3)] {
+            vm.push(&Target::Stack, Value::Address(0)).unwrap();
+            vm.push(&Target::Stack, Value::Integer(x)).unwrap();
+            vm.push(&Target::Stack, Value::Integer(0)).unwrap();
+            vm.handle_instruction(Instruction::Sprite(slot)).unwrap();
+        }
+
+        vm.push(&Target::Stack, Value::Color(0, 0, 255)).unwrap();
+        vm.push(&Target::Stack, Value::Address(3)).unwrap();
+        vm.push(&Target::Stack, Value::Address(0)).unwrap();
+        vm.push(&Target::Stack, Value::Address(1)).unwrap();
+        vm.handle_instruction(Instruction::HSpan).unwrap();
+
+        vm.push(&Target::Stack, Value::Integer(2)).unwrap();
+        vm.push(&Target::Stack, Value::Boolean(true)).unwrap();
+        vm.push(&Target::Stack, Value::Boolean(false)).unwrap();
+        vm.handle_instruction(Instruction::SpriteAttr(1)).unwrap();
+
+        vm.handle_instruction(Instruction::Sig(Signal::FlushFrame)).unwrap();
+
+        // The sprites are drawn to the shown frame only
+        assert_eq!(vm.next_frame.color(1), Some((255, 0, 0)));
+        assert_eq!(vm.framebuffer.color(1), Some((0, 0, 0)));
+
+        vm.handle_instruction(Instruction::SpriteHit(0)).unwrap();
+        vm.handle_instruction(Instruction::SpriteHit(2)).unwrap();
+        vm.handle_instruction(Instruction::BgHit(2)).unwrap();
+        vm.handle_instruction(Instruction::BgHit(0)).unwrap();
+
+        assert_eq!(vm.pop(&Target::Stack).unwrap(), Value::Boolean(false));
+        assert_eq!(vm.pop(&Target::Stack).unwrap(), Value::Boolean(true));
+        assert_eq!(vm.pop(&Target::Stack).unwrap(), Value::Address(0));
+        assert_eq!(vm.pop(&Target::Stack).unwrap(), Value::Address(2));
+
+        vm.handle_instruction(Instruction::SpriteHitPair(1, 0)).unwrap();
+        vm.handle_instruction(Instruction::SpriteHitPair(0, 2)).unwrap();
+
+        assert_eq!(vm.pop(&Target::Stack).unwrap(), Value::Boolean(false));
+        assert_eq!(vm.pop(&Target::Stack).unwrap(), Value::Boolean(true));
+
+        vm.handle_instruction(Instruction::HideSprite(2)).unwrap();
+        vm.handle_instruction(Instruction::Sig(Signal::FlushFrame)).unwrap();
+        assert_eq!(vm.next_frame.color(3), Some((0, 0, 255)));
+
+        vm.push(&Target::Stack, Value::Integer(0)).unwrap();
+        vm.push(&Target::Stack, Value::Integer(0)).unwrap();
+        vm.push(&Target::Stack, Value::Integer(0)).unwrap();
+        assert!(vm.handle_instruction(Instruction::Sprite(0)).is_err());
+    }
+
+    #[test]
+    fn audio() {
+        let (sender, receiver) = mpsc::channel();
+
+        let mut vm = VM {
+            audio: Some(sender),
+            ..VM::default()
+        };
+
+        let mut builder = ImageBuilder::new();
+        builder.add_asset(Asset::data("level".into(), vec![1]));
+        builder.add_asset(Asset::sample("jump".into(), 8000, vec![128, 255]));
+        vm.init(&builder.gen_program()).unwrap();
+
+        let mut samples = BTreeMap::new();
+        samples.insert(
+            1,
+            Sample {
+                rate: 8000.0,
+                data: vec![128, 255],
+            },
+        );
+
+        assert_eq!(receiver.try_recv(), Ok(AudioCommand::Reset(samples)));
+
+        vm.handle_instruction(Instruction::Voice(1, Waveform::Sample(1))).unwrap();
+        assert!(vm.handle_instruction(Instruction::Voice(1, Waveform::Sample(0))).is_err());
+
+        vm.push(&Target::Stack, Value::Integer(440)).unwrap();
+        vm.push(&Target::Stack, Value::Float(0.5)).unwrap();
+        vm.handle_instruction(Instruction::Tone(1)).unwrap();
+
+        vm.push(&Target::Stack, Value::Float(0.25)).unwrap();
+        vm.push(&Target::Stack, Value::Address(0)).unwrap();
+        vm.push(&Target::Stack, Value::Float(0.5)).unwrap();
+        vm.push(&Target::Stack, Value::Address(1)).unwrap();
+        vm.handle_instruction(Instruction::Envelope(2)).unwrap();
+
+        vm.handle_instruction(Instruction::NoteOff(1)).unwrap();
+        vm.handle_instruction(Instruction::Sig(Signal::FlushFrame)).unwrap();
+
+        let commands: Vec<AudioCommand> = receiver.try_iter().collect();
+
+        assert_eq!(
+            commands,
+            vec![
+                AudioCommand::Voice(1, Waveform::Sample(1)),
+                AudioCommand::Tone {
+                    channel: 1,
+                    frequency: 440.0,
+                    volume: 0.5,
+                },
+                AudioCommand::Envelope {
+                    channel: 2,
+                    envelope: Envelope {
+                        attack: 0.25,
+                        decay: 0.0,
+                        sustain: 0.5,
+                        release: 1.0,
+                    },
+                },
+                AudioCommand::NoteOff(1),
+                AudioCommand::Frame,
+            ]
+        );
+
+        vm.push(&Target::Stack, Value::Integer(440)).unwrap();
+        vm.push(&Target::Stack, Value::Integer(2)).unwrap();
+        assert!(vm.handle_instruction(Instruction::Tone(0)).is_err());
+
+        vm.push(&Target::Stack, Value::Float(Float::INFINITY)).unwrap();
+        vm.push(&Target::Stack, Value::Float(0.5)).unwrap();
+        assert!(vm.handle_instruction(Instruction::Tone(0)).is_err());
+
+        // Frequencies are limited to what the audio unit can play
+        vm.push(&Target::Stack, Value::Float(1e20)).unwrap();
+        vm.push(&Target::Stack, Value::Float(0.5)).unwrap();
+        vm.handle_instruction(Instruction::Tone(0)).unwrap();
+        assert_eq!(
+            receiver.try_recv(),
+            Ok(AudioCommand::Tone {
+                channel: 0,
+                frequency: MAX_FREQUENCY,
+                volume: 0.5,
+            })
+        );
+        assert!(vm.handle_instruction(Instruction::NoteOff(CHANNEL_COUNT)).is_err());
+
+        // The audio unit is kept when the VM is reset
+        vm.reset();
+        assert!(vm.audio.is_some());
+    }
+
+    #[test]
+    fn controller() {
+        let mut vm = VM::default();
+        vm.init(&ImageBuilder::new().gen_program()).unwrap();
+
+        assert_eq!(vm.pop(&Target::ValueIndex(Axis::LeftX.slot())).unwrap(), Value::Float(0.0));
+
+        vm.handle_event(Event::ButtonDown(Button::Start)).unwrap();
+        vm.handle_event(Event::ButtonDown(Button::A)).unwrap();
+        vm.handle_event(Event::ButtonUp(Button::A)).unwrap();
+        vm.handle_event(Event::AxisMotion {
+            axis: Axis::TriggerLeft,
+            value: 0.5,
+        }).unwrap();
+
+        assert_eq!(vm.pop(&Target::ButtonRegister(6)).unwrap(), Value::Boolean(true));
+        assert_eq!(vm.pop(&Target::ButtonRegister(0)).unwrap(), Value::Boolean(false));
+        assert_eq!(vm.pop(&Target::ValueIndex(12)).unwrap(), Value::Float(0.5));
+
+        // The button register is read-only
+        vm.push(&Target::ButtonRegister(0), Value::Boolean(true)).unwrap();
+        assert_eq!(vm.pop(&Target::ButtonRegister(0)).unwrap(), Value::Boolean(false));
+
+        // Buttons that are held down survive a reload
+        vm.reload(&ImageBuilder::new().gen_program(), false).unwrap();
+        assert_eq!(vm.pop(&Target::ButtonRegister(6)).unwrap(), Value::Boolean(true));
+    }
+
+    #[test]
+    fn text_input_and_wheel() {
+        let mut vm = VM::default();
+        vm.init(&ImageBuilder::new().gen_program()).unwrap();
+
+        vm.handle_event(Event::MouseWheel { dx: 0, dy: 1 }).unwrap();
+        vm.handle_event(Event::MouseWheel { dx: -1, dy: 2 }).unwrap();
+        vm.handle_event(Event::TextInput('h')).unwrap();
+        vm.handle_event(Event::TextInput('i')).unwrap();
+
+        // The scroll amounts add up until the program resets them
+        assert_eq!(vm.pop(&Target::ValueIndex(14)).unwrap(), Value::Integer(-1));
+        assert_eq!(vm.pop(&Target::ValueIndex(15)).unwrap(), Value::Integer(3));
+        vm.push(&Target::ValueIndex(15), Value::Integer(0)).unwrap();
+        vm.handle_event(Event::MouseWheel { dx: 0, dy: -1 }).unwrap();
+        assert_eq!(vm.pop(&Target::ValueIndex(15)).unwrap(), Value::Integer(-1));
+
+        // Comparing doesn't consume the typed characters
+        vm.push(&Target::Stack, Value::Char('h')).unwrap();
+        vm.cmp(&Target::TextInput, &Target::Stack).unwrap();
+        assert_eq!(vm.cmp_register, Some(Ordering::Equal));
+        assert_eq!(vm.pop(&Target::ValueIndex(16)).unwrap(), Value::Address(2));
+
+        assert_eq!(vm.pop(&Target::TextInput).unwrap(), Value::Char('h'));
+        assert_eq!(vm.pop(&Target::TextInput).unwrap(), Value::Char('i'));
+        assert_eq!(vm.pop(&Target::ValueIndex(16)).unwrap(), Value::Address(0));
+        assert!(vm.pop(&Target::TextInput).is_err());
+        assert!(vm.push(&Target::TextInput, Value::Integer(1)).is_err());
+
+        for _ in 0..TEXT_INPUT_CAPACITY + 1 {
+            vm.handle_event(Event::TextInput('a')).unwrap();
+        }
+
+        assert_eq!(vm.text_input.len(), TEXT_INPUT_CAPACITY);
+    }
+
+    #[test]
+    fn verification() {
+        let mut builder = ImageBuilder::new();
+        builder.add(Target::Stack, Target::Stack);
+        builder.jmp(42);
+        let program = builder.gen_program();
+
+        assert!(VM::default().load_program(&program).is_ok());
+
+        let mut vm = VM {
+            verify: true,
+            ..VM::default()
+        };
+
+        let error = vm.load_program(&program).unwrap_err().to_string();
+
+        assert!(error.contains("error at 0: add pops 2 value(s)"));
+        assert!(error.contains("error at 1: jmp to address 42"));
+
+        vm.reset();
+        assert!(vm.verify);
+
+        let program_data = include_bytes!("stock.img");
+        assert!(vm.load_program(&Program::decode(&program_data[..]).unwrap()).is_ok());
+    }
+
+    #[test]
+    fn inconsistent_base_pointer() {
+        let mut vm = VM::default();
+
+        vm.push(&Target::BasePointer, Value::Address(2)).unwrap();
+        assert!(vm.push(&Target::ValueIndex(NUM_RESERVED_MEM_SLOTS + 5), Value::Address(0)).is_err());
+
+        vm.push(&Target::BasePointer, Value::Address(Address::MAX)).unwrap();
+        assert!(vm.push(&Target::ValueIndex(NUM_RESERVED_MEM_SLOTS), Value::Address(0)).is_err());
+    }
+
     #[test]
     fn allocation() {
         let mut vm = VM::default();
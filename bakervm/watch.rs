@@ -0,0 +1,73 @@
+use core::Program;
+use core::error::*;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::Sender;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime};
+
+/// The interval in which the image file is checked for changes
+const POLL_INTERVAL_MS: u64 = 250;
+
+/// Watches the given image file and sends the program to the VM whenever the
+/// file changes. Images that can't be read are reported using the status
+/// sender and otherwise ignored.
+pub fn start(path: PathBuf, sender: Sender<Program>, status_sender: Sender<String>)
+    -> JoinHandle<()> {
+    thread::spawn(
+        move || {
+            let mut last_modified = modification_time(&path);
+
+            loop {
+                thread::sleep(Duration::from_millis(POLL_INTERVAL_MS));
+
+                let modified = modification_time(&path);
+
+                // The image might be in the middle of being replaced
+                if modified.is_none() || modified == last_modified {
+                    continue;
+                }
+
+                last_modified = modified;
+
+                match read_image(&path) {
+                    Ok(program) => {
+                        println!("RELOAD  {:?}", path);
+
+                        if sender.send(program).is_err() {
+                            return;
+                        }
+                    }
+                    Err(ref e) => {
+                        println!("error: {}", e);
+
+                        for e in e.iter().skip(1) {
+                            println!("caused by: {}", e);
+                        }
+
+                        if status_sender.send(format!("reload failed: {}", e)).is_err() {
+                            return;
+                        }
+                    }
+                }
+            }
+        },
+    )
+}
+
+/// Reads and decodes the image at the given path
+pub fn read_image(path: &Path) -> Result<Program> {
+    let mut file = File::open(path).chain_err(|| "unable to open file")?;
+    let mut buf = Vec::new();
+    file.read_to_end(&mut buf)
+        .chain_err(|| "unable to read from file")?;
+
+    Program::decode(&buf)
+}
+
+fn modification_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
@@ -0,0 +1,809 @@
+//! Static analysis of programs, used for inspecting and validating images
+//! without executing them
+
+use asset::AssetKind;
+use audio::{Waveform, CHANNEL_COUNT};
+use blit::BlitSource;
+use instruction::{Instruction, SPRITE_COUNT};
+use program::Program;
+use signal::Signal;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use target::{Target, LAYER_COUNT};
+use tilemap::TilemapSource;
+use typedef::*;
+use value::Value;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// The image can't be executed correctly
+    Error,
+    /// The image is valid but probably not what the programmer intended
+    Warning,
+}
+
+/// A problem found in a program
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// The address of the instruction the problem was found at
+    pub address: Option<Address>,
+    pub message: String,
+}
+
+impl Diagnostic {
+    fn error(address: Option<Address>, message: String) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Error,
+            address,
+            message,
+        }
+    }
+
+    fn warning(address: Option<Address>, message: String) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Warning,
+            address,
+            message,
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+
+        match self.address {
+            Some(address) => write!(f, "{} at {}: {}", severity, address, self.message),
+            None => write!(f, "{}: {}", severity, self.message),
+        }
+    }
+}
+
+/// Returns the addresses execution may continue at after the instruction at
+/// the given address. Calls are assumed to return. Addresses outside of the
+/// program are left out.
+pub fn successors(program: &Program, address: Address) -> Vec<Address> {
+    let len = program.instructions.len();
+
+    let mut next = match program.instructions.get(address) {
+        Some(&Instruction::Jmp(target)) => vec![target],
+        Some(&Instruction::JmpLt(target)) |
+        Some(&Instruction::JmpGt(target)) |
+        Some(&Instruction::JmpEq(target)) |
+        Some(&Instruction::JmpLtEq(target)) |
+        Some(&Instruction::JmpGtEq(target)) |
+        Some(&Instruction::Call(target)) => vec![address + 1, target],
+        Some(&Instruction::Ret) | Some(&Instruction::Halt) | None => vec![],
+        Some(_) => vec![address + 1],
+    };
+
+    next.retain(|&next| next < len);
+
+    next
+}
+
+/// Returns the addresses of all instructions that may be executed when
+/// starting at the given address
+fn reachable_from(program: &Program, start: Address, follow_calls: bool) -> BTreeSet<Address> {
+    let mut reachable = BTreeSet::new();
+    let mut work = vec![start];
+
+    while let Some(address) = work.pop() {
+        if address >= program.instructions.len() || !reachable.insert(address) {
+            continue;
+        }
+
+        let calls = match program.instructions[address] {
+            Instruction::Call(target) => Some(target),
+            _ => None,
+        };
+
+        for next in successors(program, address) {
+            if follow_calls || Some(next) != calls || next == address + 1 {
+                work.push(next);
+            }
+        }
+    }
+
+    reachable
+}
+
+/// Checks the given program without executing it
+pub fn validate(program: &Program) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+
+    let expected = Program::default();
+
+    if program.preamble != expected.preamble {
+        diagnostics.push(Diagnostic::error(
+            None,
+            format!(
+                "invalid preamble {:?}, expected {:?}",
+                program.preamble,
+                expected.preamble
+            ),
+        ));
+    }
+
+    // Older images are migrated when loading them
+    if program.format_version < expected.format_version {
+        diagnostics.push(Diagnostic::warning(
+            None,
+            format!(
+                "image format version {} is older than the current version {}. Run `hudson upgrade` \
+                 to rewrite the image",
+                program.format_version,
+                expected.format_version
+            ),
+        ));
+    } else if program.format_version != expected.format_version {
+        diagnostics.push(Diagnostic::error(
+            None,
+            format!(
+                "image format version {} doesn't match the supported version {}",
+                program.format_version,
+                expected.format_version
+            ),
+        ));
+    }
+
+    diagnostics.extend(verify(program));
+
+    let len = program.instructions.len();
+
+    let reachable = reachable_from(program, 0, true);
+
+    for (start, end) in ranges(&(0..len).filter(|address| !reachable.contains(address)).collect::<Vec<_>>()) {
+        let message = if start == end {
+            "instruction is unreachable".to_owned()
+        } else {
+            format!("instructions {} to {} are unreachable", start, end)
+        };
+
+        diagnostics.push(Diagnostic::warning(Some(start), message));
+    }
+
+    diagnostics
+}
+
+/// Checks the given program for errors that would make it fail while running:
+/// jumps and calls outside of the program, unknown signals, non-color values
+/// pushed to the framebuffer, missing or malformed assets and popping values
+/// off a stack that is empty on every path to the instruction.
+pub fn verify(program: &Program) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+
+    let len = program.instructions.len();
+
+    for (address, instruction) in program.instructions.iter().enumerate() {
+        if let Some(target) = instruction.target_address() {
+            // Jumping to the end of the program is a valid way to exit
+            if target > len {
+                diagnostics.push(Diagnostic::error(
+                    Some(address),
+                    format!(
+                        "{} to address {} outside of the program of length {}",
+                        instruction.mnemonic(),
+                        target,
+                        len
+                    ),
+                ));
+            }
+        }
+
+        let layer = match *instruction {
+            Instruction::Layer(layer) |
+            Instruction::Scroll(layer) |
+            Instruction::LayerKey(layer) |
+            Instruction::Tilemap(layer, ..) |
+            Instruction::SetTile(layer) |
+            Instruction::GetTile(layer) |
+            Instruction::ScrollMap(layer) |
+            Instruction::Push(Target::FramebufferLayer(layer), _) => Some(layer),
+            _ => None,
+        };
+
+        if let Some(layer) = layer.filter(|&layer| layer >= LAYER_COUNT) {
+            diagnostics.push(Diagnostic::error(
+                Some(address),
+                format!(
+                    "{} uses layer {} but the framebuffer has {} layers",
+                    instruction.mnemonic(),
+                    layer,
+                    LAYER_COUNT
+                ),
+            ));
+        }
+
+        let slots = match *instruction {
+            Instruction::Sprite(slot) |
+            Instruction::SpriteAttr(slot) |
+            Instruction::HideSprite(slot) |
+            Instruction::SpriteHit(slot) |
+            Instruction::BgHit(slot) => vec![slot],
+            Instruction::SpriteHitPair(slot, other) => vec![slot, other],
+            _ => Vec::new(),
+        };
+
+        for slot in slots.into_iter().filter(|&slot| slot >= SPRITE_COUNT) {
+            diagnostics.push(Diagnostic::error(
+                Some(address),
+                format!(
+                    "{} uses sprite {} but the sprite table has {} entries",
+                    instruction.mnemonic(),
+                    slot,
+                    SPRITE_COUNT
+                ),
+            ));
+        }
+
+        let channel = match *instruction {
+            Instruction::Voice(channel, _) |
+            Instruction::Tone(channel) |
+            Instruction::Envelope(channel) |
+            Instruction::NoteOff(channel) => Some(channel),
+            _ => None,
+        };
+
+        if let Some(channel) = channel.filter(|&channel| channel >= CHANNEL_COUNT) {
+            diagnostics.push(Diagnostic::error(
+                Some(address),
+                format!(
+                    "{} uses channel {} but the audio unit has {} channels",
+                    instruction.mnemonic(),
+                    channel,
+                    CHANNEL_COUNT
+                ),
+            ));
+        }
+
+        match *instruction {
+            Instruction::Sig(Signal::Unknown(index)) => {
+                diagnostics.push(Diagnostic::error(
+                    Some(address),
+                    format!("unknown signal with index {}", index),
+                ));
+            }
+            Instruction::Read(_, asset) if asset >= program.assets.len() => {
+                diagnostics.push(Diagnostic::error(
+                    Some(address),
+                    format!(
+                        "read of asset {} but the program has {} asset(s)",
+                        asset,
+                        program.assets.len()
+                    ),
+                ));
+            }
+            Instruction::Blit(BlitSource::Asset(asset), _) => {
+                diagnostics.extend(check_asset_kind(program, address, asset, AssetKind::Sprite));
+            }
+            Instruction::DrawChar(Some(font)) | Instruction::DrawText(_, Some(font)) => {
+                diagnostics.extend(check_asset_kind(program, address, font, AssetKind::Font));
+            }
+            Instruction::Tilemap(_, ref source, tileset) => {
+                if let TilemapSource::Asset(tilemap) = *source {
+                    diagnostics.extend(check_asset_kind(
+                        program,
+                        address,
+                        tilemap,
+                        AssetKind::Tilemap,
+                    ));
+                }
+
+                diagnostics.extend(check_asset_kind(
+                    program,
+                    address,
+                    tileset,
+                    AssetKind::Tileset,
+                ));
+            }
+            Instruction::Voice(_, Waveform::Sample(sample)) => {
+                diagnostics.extend(check_asset_kind(program, address, sample, AssetKind::Sample));
+            }
+            Instruction::Push(Target::Framebuffer, ref value) |
+            Instruction::Push(Target::FramebufferLayer(..), ref value) => {
+                let palette_size = program.config.display.color_mode.palette_size();
+
+                match (value, palette_size) {
+                    (&Value::Color(..), _) => {}
+                    (&Value::Address(index), Some(size)) if index < size => {}
+                    (_, Some(size)) => diagnostics.push(Diagnostic::error(
+                        Some(address),
+                        format!(
+                            "push of {} to the framebuffer, which is neither a color nor an \
+                             index into the palette of {} colors",
+                            value,
+                            size
+                        ),
+                    )),
+                    (_, None) => diagnostics.push(Diagnostic::error(
+                        Some(address),
+                        format!("push of the non-color value {} to the framebuffer", value),
+                    )),
+                }
+            }
+            _ => {}
+        }
+    }
+
+    for (index, asset) in program.assets.iter().enumerate() {
+        if asset.expected_len() != Some(asset.data.len()) {
+            diagnostics.push(Diagnostic::error(
+                None,
+                format!(
+                    "{} asset {} {:?} of size {}x{} holds {} bytes",
+                    asset.kind,
+                    index,
+                    asset.name,
+                    asset.width,
+                    asset.height,
+                    asset.data.len()
+                ),
+            ));
+        }
+    }
+
+    let depths = stack_depths(program);
+
+    for (&address, &depth) in &depths {
+        let (pops, _) = stack_effect(&program.instructions[address]);
+
+        if let Some(depth) = depth {
+            if depth < pops {
+                diagnostics.push(Diagnostic::error(
+                    Some(address),
+                    format!(
+                        "{} pops {} value(s) off a stack holding at most {}",
+                        program.instructions[address].mnemonic(),
+                        pops,
+                        depth
+                    ),
+                ));
+            }
+        }
+    }
+
+    diagnostics
+}
+
+/// Reports an error if the instruction at the given address uses an asset,
+/// that is missing or not of the expected kind
+fn check_asset_kind(
+    program: &Program, address: Address, asset: Address, expected: AssetKind
+) -> Option<Diagnostic> {
+    let mnemonic = program.instructions[address].mnemonic();
+
+    match program.assets.get(asset).map(|asset| asset.kind) {
+        Some(kind) if kind == expected => None,
+        Some(kind) => Some(Diagnostic::error(
+            Some(address),
+            format!(
+                "{} of the {} asset {}, which is not a {}",
+                mnemonic,
+                kind,
+                asset,
+                expected
+            ),
+        )),
+        None => Some(Diagnostic::error(
+            Some(address),
+            format!(
+                "{} of asset {} but the program has {} asset(s)",
+                mnemonic,
+                asset,
+                program.assets.len()
+            ),
+        )),
+    }
+}
+
+/// Returns the number of values the instruction pops off the stack and the
+/// number of values it pushes to the stack afterwards
+fn stack_effect(instruction: &Instruction) -> (usize, usize) {
+    let on_stack = |target: &Target| match *target {
+        Target::Stack => 1,
+        _ => 0,
+    };
+
+    match *instruction {
+        Instruction::Add(ref dest, ref src) |
+        Instruction::Sub(ref dest, ref src) |
+        Instruction::Div(ref dest, ref src) |
+        Instruction::Mul(ref dest, ref src) |
+        Instruction::Rem(ref dest, ref src) => (on_stack(dest) + on_stack(src), on_stack(dest)),
+        Instruction::Mov(ref dest, ref src) => (on_stack(src), on_stack(dest)),
+        Instruction::Cmp(ref a, ref b) | Instruction::Swp(ref a, ref b) => {
+            let count = on_stack(a) + on_stack(b);
+            (count, count)
+        }
+        Instruction::Cast(ref target, _) => (on_stack(target), on_stack(target)),
+        Instruction::Push(ref target, _) => (0, on_stack(target)),
+        Instruction::Dup(ref target) => (on_stack(target), on_stack(target) + 1),
+        Instruction::Read(ref dest, _) => (1, on_stack(dest)),
+        Instruction::Blit(..) => (2, 0),
+        Instruction::Clear => (1, 0),
+        Instruction::Rect | Instruction::Line => (5, 0),
+        Instruction::HSpan | Instruction::VSpan => (4, 0),
+        Instruction::DrawChar(..) | Instruction::DrawText(..) => (4, 0),
+        Instruction::SetPalette | Instruction::RotatePalette => (2, 0),
+        Instruction::Scroll(..) => (2, 0),
+        Instruction::LayerKey(..) => (1, 0),
+        Instruction::SetTile(..) => (3, 0),
+        Instruction::GetTile(..) => (2, 1),
+        Instruction::ScrollMap(..) => (2, 0),
+        Instruction::Sprite(..) => (3, 0),
+        Instruction::SpriteAttr(..) => (3, 0),
+        Instruction::HideSprite(..) => (0, 0),
+        Instruction::SpriteHit(..) | Instruction::SpriteHitPair(..) => (0, 1),
+        Instruction::BgHit(..) => (0, 1),
+        Instruction::Tone(..) => (2, 0),
+        Instruction::Envelope(..) => (4, 0),
+        _ => (0, 0),
+    }
+}
+
+/// Computes the largest possible stack depth before each reachable
+/// instruction. The depth is unknown (`None`) at the start of functions and
+/// after calls, as functions take their arguments from the stack, and in
+/// loops growing the stack.
+fn stack_depths(program: &Program) -> BTreeMap<Address, Option<usize>> {
+    let len = program.instructions.len();
+
+    let mut depths: BTreeMap<Address, Option<usize>> = BTreeMap::new();
+    let mut work: Vec<(Address, Option<usize>)> = Vec::new();
+
+    if len > 0 {
+        work.push((0, Some(0)));
+    }
+
+    while let Some((address, depth)) = work.pop() {
+        let depth = match depths.get(&address) {
+            None => depth,
+            // Nothing new is known
+            Some(&None) => continue,
+            Some(&Some(known)) => match depth {
+                Some(depth) if depth <= known => continue,
+                // Values are accumulated in a loop, so the depth is unknown
+                _ => None,
+            },
+        };
+
+        depths.insert(address, depth);
+
+        let instruction = &program.instructions[address];
+
+        let next_depth = depth.map(|depth| {
+            let (pops, pushes) = stack_effect(instruction);
+            depth.saturating_sub(pops) + pushes
+        });
+
+        for next in successors(program, address) {
+            match *instruction {
+                Instruction::Call(_) => work.push((next, None)),
+                _ => work.push((next, next_depth)),
+            }
+        }
+    }
+
+    depths
+}
+
+/// Groups the given sorted addresses into ranges of consecutive addresses
+fn ranges(addresses: &[Address]) -> Vec<(Address, Address)> {
+    let mut ranges: Vec<(Address, Address)> = Vec::new();
+
+    for &address in addresses {
+        match ranges.last_mut() {
+            Some(&mut (_, ref mut end)) if *end + 1 == address => *end = address,
+            _ => ranges.push((address, address)),
+        }
+    }
+
+    ranges
+}
+
+/// A function of a program, starting at the entry point or a call target
+#[derive(Debug, Clone)]
+pub struct Function {
+    pub address: Address,
+    /// The number of instructions executed as part of the function, not
+    /// counting called functions
+    pub size: usize,
+    /// The addresses of the functions called by this function
+    pub calls: BTreeSet<Address>,
+}
+
+/// The functions of a program and the calls between them
+#[derive(Debug, Clone)]
+pub struct CallGraph {
+    pub functions: BTreeMap<Address, Function>,
+}
+
+impl CallGraph {
+    pub fn new(program: &Program) -> CallGraph {
+        let len = program.instructions.len();
+
+        let mut entries: BTreeSet<Address> = program
+            .instructions
+            .iter()
+            .filter_map(|instruction| match *instruction {
+                Instruction::Call(target) if target < len => Some(target),
+                _ => None,
+            })
+            .collect();
+
+        if len > 0 {
+            entries.insert(0);
+        }
+
+        let functions = entries
+            .into_iter()
+            .map(|address| {
+                let body = reachable_from(program, address, false);
+
+                let calls = body.iter()
+                    .filter_map(|&address| match program.instructions[address] {
+                        Instruction::Call(target) if target < len => Some(target),
+                        _ => None,
+                    })
+                    .collect();
+
+                let function = Function {
+                    address,
+                    size: body.len(),
+                    calls,
+                };
+
+                (address, function)
+            })
+            .collect();
+
+        CallGraph { functions }
+    }
+
+    /// Returns the functions that are not called by any other function
+    pub fn roots(&self) -> Vec<&Function> {
+        self.functions
+            .values()
+            .filter(|function| {
+                !self.functions.values().any(|caller| {
+                    caller.address != function.address && caller.calls.contains(&function.address)
+                })
+            })
+            .collect()
+    }
+
+    /// Returns the functions sorted by their size, beginning with the largest
+    pub fn largest(&self) -> Vec<&Function> {
+        let mut functions: Vec<&Function> = self.functions.values().collect();
+
+        functions.sort_by(|a, b| b.size.cmp(&a.size).then(a.address.cmp(&b.address)));
+
+        functions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use asset::Asset;
+    use config::ColorMode;
+    use image_builder::ImageBuilder;
+    use IMAGE_FORMAT_VERSION;
+
+    fn program() -> Program {
+        let mut builder = ImageBuilder::new();
+        builder.call(4); // 0
+        builder.call(6); // 1
+        builder.halt(); // 2
+        builder.nop(); // 3, unreachable
+        builder.push(Target::Stack, Value::Integer(1)); // 4
+        builder.ret(); // 5
+        builder.call(4); // 6
+        builder.jmp(9); // 7
+        builder.nop(); // 8, unreachable
+        builder.ret(); // 9
+
+        builder.gen_program()
+    }
+
+    #[test]
+    fn call_graph() {
+        let graph = CallGraph::new(&program());
+
+        assert_eq!(graph.functions.len(), 3);
+        assert_eq!(graph.functions[&0].size, 3);
+        assert_eq!(graph.functions[&6].size, 3);
+        assert!(graph.functions[&6].calls.contains(&4));
+
+        let roots: Vec<Address> = graph.roots().iter().map(|function| function.address).collect();
+        assert_eq!(roots, vec![0]);
+
+        assert_eq!(graph.largest()[0].address, 0);
+    }
+
+    #[test]
+    fn validation() {
+        let mut program = program();
+        program.instructions.push(Instruction::Jmp(42));
+        program.instructions.push(Instruction::Sig(Signal::Unknown(3)));
+        program.format_version = 0;
+
+        let diagnostics = validate(&program);
+        let messages: Vec<String> = diagnostics.iter().map(|d| d.to_string()).collect();
+
+        assert!(messages.contains(&"warning at 3: instruction is unreachable".to_owned()));
+        assert!(messages.contains(&"warning at 10: instructions 10 to 11 are unreachable".to_owned()));
+        assert!(messages.iter().any(|m| m.starts_with("error at 10: jmp to address 42")));
+        assert!(messages.contains(&"error at 11: unknown signal with index 3".to_owned()));
+        assert!(messages.iter().any(|m| m.starts_with("warning: image format version 0 is older")));
+
+        program.format_version = IMAGE_FORMAT_VERSION + 1;
+        let messages: Vec<String> = validate(&program).iter().map(|d| d.to_string()).collect();
+        assert!(messages.iter().any(|m| m.starts_with("error: image format version")));
+    }
+
+    #[test]
+    fn verification() {
+        let mut builder = ImageBuilder::new();
+        builder.push(Target::Stack, Value::Integer(1)); // 0
+        builder.mov(Target::ValueIndex(8), Target::Stack); // 1
+        builder.add(Target::Stack, Target::Stack); // 2, underflow
+        builder.push(Target::Framebuffer, Value::Integer(0)); // 3
+        builder.push(Target::Framebuffer, Value::Color(0, 0, 0)); // 4
+        builder.call(8); // 5
+        builder.add(Target::Stack, Target::Stack); // 6, unknown after call
+        builder.halt(); // 7
+        builder.add(Target::Stack, Target::Stack); // 8, arguments unknown
+        builder.push(Target::Stack, Value::Integer(1)); // 9
+        builder.jmp_lt(8); // 10
+        builder.jmp(20); // 11
+        builder.ret(); // 12
+        builder.read(Target::Stack, 0); // 13, unreachable
+        builder.read(Target::Stack, 2); // 14, unreachable
+        builder.blit(BlitSource::Asset(0), Default::default()); // 15, unreachable
+        builder.draw_text(24, Some(1)); // 16, unreachable
+        builder.tilemap(1, TilemapSource::Asset(0), 1); // 17, unreachable
+
+        let mut program = builder.gen_program();
+        program.assets.push(Asset::data("level".into(), vec![0; 4]));
+        program.assets.push(Asset::sprite("player".into(), 2, 2, vec![0; 4]));
+
+        let diagnostics = verify(&program);
+        let messages: Vec<String> = diagnostics.iter().map(|d| d.to_string()).collect();
+
+        assert_eq!(
+            messages,
+            vec![
+                "error at 3: push of the non-color value 0 to the framebuffer",
+                "error at 11: jmp to address 20 outside of the program of length 18",
+                "error at 14: read of asset 2 but the program has 2 asset(s)",
+                "error at 15: blit of the data asset 0, which is not a sprite",
+                "error at 16: drawtext of the sprite asset 1, which is not a font",
+                "error at 17: tilemap of the data asset 0, which is not a tilemap",
+                "error at 17: tilemap of the sprite asset 1, which is not a tileset",
+                "error: sprite asset 1 \"player\" of size 2x2 holds 4 bytes",
+                "error at 2: add pops 2 value(s) off a stack holding at most 0",
+            ]
+        );
+    }
+
+    #[test]
+    fn mov_to_stack() {
+        // Moving to the stack only pops the source
+        let mut builder = ImageBuilder::new();
+        builder.push(Target::ValueIndex(8), Value::Integer(5));
+        builder.mov(Target::Stack, Target::ValueIndex(8));
+        builder.mov(Target::ValueIndex(9), Target::Stack);
+        builder.halt();
+
+        assert!(verify(&builder.gen_program()).is_empty());
+    }
+
+    #[test]
+    fn indexed_framebuffer() {
+        let mut builder = ImageBuilder::new();
+        builder.push(Target::Framebuffer, Value::Address(15)); // 0
+        builder.push(Target::Framebuffer, Value::Color(0, 0, 0)); // 1
+        builder.push(Target::Framebuffer, Value::Address(16)); // 2
+
+        let mut program = builder.gen_program();
+        program.config.display.color_mode = ColorMode::Indexed16;
+
+        let messages: Vec<String> = verify(&program).iter().map(|d| d.to_string()).collect();
+
+        assert_eq!(
+            messages,
+            vec![
+                "error at 2: push of @16 to the framebuffer, which is neither a color nor an \
+                 index into the palette of 16 colors",
+            ]
+        );
+    }
+
+    #[test]
+    fn layers() {
+        let mut builder = ImageBuilder::new();
+        builder.push(Target::FramebufferLayer(7), Value::Color(0, 0, 0)); // 0
+        builder.push(Target::FramebufferLayer(8), Value::Color(0, 0, 0)); // 1
+        builder.push(Target::FramebufferLayer(1), Value::Integer(0)); // 2
+        builder.layer(8); // 3
+
+        let messages: Vec<String> = verify(&builder.gen_program())
+            .iter()
+            .map(|d| d.to_string())
+            .collect();
+
+        assert_eq!(
+            messages,
+            vec![
+                "error at 1: push uses layer 8 but the framebuffer has 8 layers",
+                "error at 2: push of the non-color value 0 to the framebuffer",
+                "error at 3: layer uses layer 8 but the framebuffer has 8 layers",
+            ]
+        );
+    }
+
+    #[test]
+    fn sprite_slots() {
+        let mut builder = ImageBuilder::new();
+        builder.hide_sprite(63); // 0
+        builder.hide_sprite(64); // 1
+        builder.sprite_hit(64); // 2
+        builder.mov(Target::ValueIndex(0), Target::Stack); // 3
+
+        let messages: Vec<String> = verify(&builder.gen_program())
+            .iter()
+            .map(|d| d.to_string())
+            .collect();
+
+        assert_eq!(
+            messages,
+            vec![
+                "error at 1: hidesprite uses sprite 64 but the sprite table has 64 entries",
+                "error at 2: spritehit uses sprite 64 but the sprite table has 64 entries",
+            ]
+        );
+    }
+
+    #[test]
+    fn audio_channels() {
+        let mut builder = ImageBuilder::new();
+        builder.add_asset(Asset::data("level".into(), vec![1]));
+        builder.note_off(3); // 0
+        builder.note_off(4); // 1
+        builder.voice(0, Waveform::Sample(0)); // 2
+        builder.voice(0, Waveform::Sample(1)); // 3
+
+        let messages: Vec<String> = verify(&builder.gen_program())
+            .iter()
+            .map(|d| d.to_string())
+            .collect();
+
+        assert_eq!(
+            messages,
+            vec![
+                "error at 1: noteoff uses channel 4 but the audio unit has 4 channels",
+                "error at 2: voice of the data asset 0, which is not a sample",
+                "error at 3: voice of asset 1 but the program has 1 asset(s)",
+            ]
+        );
+    }
+
+    #[test]
+    fn stack_depth_in_loops() {
+        let mut builder = ImageBuilder::new();
+        builder.push(Target::Stack, Value::Integer(1)); // 0
+        builder.cmp(Target::ValueIndex(8), Target::ValueIndex(9)); // 1
+        builder.jmp_lt(0); // 2
+        builder.mov(Target::ValueIndex(8), Target::Stack); // 3
+        builder.mov(Target::ValueIndex(8), Target::Stack); // 4, may succeed after the loop
+        builder.halt(); // 5
+
+        assert!(verify(&builder.gen_program()).is_empty());
+    }
+}
@@ -0,0 +1,180 @@
+//! Binary data embedded into images, e.g. sprites
+
+use font::GLYPH_COUNT;
+use std::fmt;
+use std::str::FromStr;
+use typedef::*;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum AssetKind {
+    /// Raw bytes
+    Data,
+    /// A picture stored row by row with 4 bytes (RGBA) per pixel
+    Sprite,
+    /// The glyphs of a font one after another, stored row by row with one
+    /// byte per pixel, which is either 0 or 1
+    Font,
+    /// Tiles of the same size one after another, stored like sprites
+    Tileset,
+    /// The tiles of a map row by row, stored as 16 bit little endian indices.
+    /// 0 is an empty tile, all others are the index of a tile plus 1.
+    Tilemap,
+    /// Mono sound stored as unsigned 8 bit samples, where 128 is silence
+    Sample,
+}
+
+impl FromStr for AssetKind {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "data" => Ok(AssetKind::Data),
+            "sprite" => Ok(AssetKind::Sprite),
+            "font" => Ok(AssetKind::Font),
+            "tileset" => Ok(AssetKind::Tileset),
+            "tilemap" => Ok(AssetKind::Tilemap),
+            "sample" => Ok(AssetKind::Sample),
+            _ => Err(
+                "unknown asset kind. Asset kind must be one of [data, sprite, font, tileset, \
+                 tilemap, sample]",
+            ),
+        }
+    }
+}
+
+impl fmt::Display for AssetKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            AssetKind::Data => write!(f, "data"),
+            AssetKind::Sprite => write!(f, "sprite"),
+            AssetKind::Font => write!(f, "font"),
+            AssetKind::Tileset => write!(f, "tileset"),
+            AssetKind::Tilemap => write!(f, "tilemap"),
+            AssetKind::Sample => write!(f, "sample"),
+        }
+    }
+}
+
+/// A named section of binary data, referenced by its index in the program
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Asset {
+    pub name: String,
+    pub kind: AssetKind,
+    /// The width of the asset. Raw data has the width of its length, fonts
+    /// the width of a glyph, tilesets the width of a tile, tilemaps their
+    /// width in tiles and samples their sample rate in Hz.
+    pub width: Address,
+    /// The height of the asset. Raw data and samples have a height of 1, fonts
+    /// the height of a glyph, tilesets the height of a tile and tilemaps their
+    /// height in tiles.
+    pub height: Address,
+    pub data: Vec<Byte>,
+}
+
+impl Asset {
+    /// Creates an asset holding raw bytes
+    pub fn data(name: String, data: Vec<Byte>) -> Asset {
+        Asset {
+            name,
+            kind: AssetKind::Data,
+            width: data.len(),
+            height: 1,
+            data,
+        }
+    }
+
+    /// Creates a sprite from the given RGBA pixels
+    pub fn sprite(name: String, width: Address, height: Address, data: Vec<Byte>) -> Asset {
+        Asset {
+            name,
+            kind: AssetKind::Sprite,
+            width,
+            height,
+            data,
+        }
+    }
+
+    /// Creates a font from the pixels of its glyphs
+    pub fn font(name: String, width: Address, height: Address, data: Vec<Byte>) -> Asset {
+        Asset {
+            name,
+            kind: AssetKind::Font,
+            width,
+            height,
+            data,
+        }
+    }
+
+    /// Creates a tileset from the RGBA pixels of its tiles
+    pub fn tileset(name: String, width: Address, height: Address, data: Vec<Byte>) -> Asset {
+        Asset {
+            name,
+            kind: AssetKind::Tileset,
+            width,
+            height,
+            data,
+        }
+    }
+
+    /// Creates a tilemap from the tiles of its rows
+    pub fn tilemap(name: String, width: Address, height: Address, tiles: &[u16]) -> Asset {
+        let mut data = Vec::with_capacity(tiles.len() * 2);
+
+        for &tile in tiles {
+            data.push(tile as Byte);
+            data.push((tile >> 8) as Byte);
+        }
+
+        Asset {
+            name,
+            kind: AssetKind::Tilemap,
+            width,
+            height,
+            data,
+        }
+    }
+
+    /// Creates a sample from unsigned 8 bit samples played at the given rate
+    pub fn sample(name: String, rate: Address, data: Vec<Byte>) -> Asset {
+        Asset {
+            name,
+            kind: AssetKind::Sample,
+            width: rate,
+            height: 1,
+            data,
+        }
+    }
+
+    /// Returns the number of bytes the asset should hold according to its
+    /// kind and dimensions. Tilesets hold any number of tiles and samples
+    /// any number of samples.
+    pub fn expected_len(&self) -> Option<usize> {
+        let pixels = self.width.checked_mul(self.height)?;
+
+        match self.kind {
+            AssetKind::Data => Some(pixels),
+            AssetKind::Sprite => pixels.checked_mul(4),
+            AssetKind::Font => pixels.checked_mul(GLYPH_COUNT),
+            AssetKind::Tileset => {
+                let tile_len = pixels.checked_mul(4)?;
+
+                if self.data.len().checked_rem(tile_len) == Some(0) {
+                    Some(self.data.len())
+                } else {
+                    None
+                }
+            }
+            AssetKind::Tilemap => pixels.checked_mul(2),
+            AssetKind::Sample if self.width > 0 && self.height == 1 => Some(self.data.len()),
+            AssetKind::Sample => None,
+        }
+    }
+
+    /// Returns the tiles of a tilemap row by row
+    pub fn tiles(&self) -> Vec<Address> {
+        self.data
+            .chunks(2)
+            .map(|tile| tile[0] as Address | (*tile.get(1).unwrap_or(&0) as Address) << 8)
+            .collect()
+    }
+}
@@ -0,0 +1,62 @@
+//! The operands of the audio instructions
+
+use std::fmt;
+use std::str::FromStr;
+use typedef::*;
+use value::Value;
+
+/// The number of channels of the audio unit
+pub const CHANNEL_COUNT: usize = 4;
+
+/// What an audio channel plays
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Waveform {
+    Square,
+    Triangle,
+    Noise,
+    /// The sample asset with the given index, which is played once
+    Sample(Address),
+}
+
+impl Waveform {
+    /// Returns the waveform a channel plays before it's changed. The channels
+    /// start out like the ones of a retro console: two square waves, a
+    /// triangle wave and noise.
+    pub fn default_for(channel: Address) -> Waveform {
+        match channel {
+            2 => Waveform::Triangle,
+            3 => Waveform::Noise,
+            _ => Waveform::Square,
+        }
+    }
+}
+
+impl FromStr for Waveform {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "square" => Ok(Waveform::Square),
+            "triangle" => Ok(Waveform::Triangle),
+            "noise" => Ok(Waveform::Noise),
+            _ => match s.parse::<Value>() {
+                Ok(Value::Address(asset)) => Ok(Waveform::Sample(asset)),
+                _ => Err(
+                    "unable to parse waveform. Waveform must be one of [square, triangle, noise] \
+                     or a sample asset",
+                ),
+            },
+        }
+    }
+}
+
+impl fmt::Display for Waveform {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Waveform::Square => write!(f, "square"),
+            Waveform::Triangle => write!(f, "triangle"),
+            Waveform::Noise => write!(f, "noise"),
+            Waveform::Sample(asset) => write!(f, "@{}", asset),
+        }
+    }
+}
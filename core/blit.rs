@@ -0,0 +1,108 @@
+//! The operands of the `Blit` instruction
+
+use std::fmt;
+use std::str::FromStr;
+use target::Target;
+use typedef::*;
+use value::Value;
+
+/// Where the pixels of a blit are read from
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum BlitSource {
+    /// A sprite asset with the given index
+    Asset(Address),
+    /// A sprite in the value-index, starting at the given index with its
+    /// width and height, followed by its pixels row by row
+    ValueIndex(Address),
+}
+
+impl FromStr for BlitSource {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match (s.parse::<Value>(), s.parse::<Target>()) {
+            (Ok(Value::Address(asset)), _) => Ok(BlitSource::Asset(asset)),
+            (_, Ok(Target::ValueIndex(index))) => Ok(BlitSource::ValueIndex(index)),
+            _ => Err("unable to parse blit source. Blit source must be an asset or $vi(#)"),
+        }
+    }
+}
+
+impl fmt::Display for BlitSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BlitSource::Asset(asset) => write!(f, "@{}", asset),
+            BlitSource::ValueIndex(index) => write!(f, "$vi({})", index),
+        }
+    }
+}
+
+/// Changes how the pixels of a blit are drawn
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct BlitOptions {
+    /// Mirrors the sprite horizontally
+    pub flip_x: bool,
+    /// Mirrors the sprite vertically
+    pub flip_y: bool,
+    /// Pixels of this color are not drawn
+    pub color_key: Option<Color>,
+}
+
+impl BlitOptions {
+    /// Parses the options from BASM arguments like `flip_x` or `#ff00ff`
+    pub fn parse(args: &[String]) -> Result<Self, &'static str> {
+        let mut options = BlitOptions::default();
+
+        for arg in args {
+            match arg.as_str() {
+                "flip_x" => options.flip_x = true,
+                "flip_y" => options.flip_y = true,
+                _ => match arg.parse::<Value>() {
+                    Ok(Value::Color(r, g, b)) => options.color_key = Some((r, g, b)),
+                    _ => return Err("unknown blit option. Options must be flip_x, flip_y or a color key"),
+                },
+            }
+        }
+
+        Ok(options)
+    }
+}
+
+impl fmt::Display for BlitOptions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.flip_x {
+            write!(f, ", flip_x")?;
+        }
+
+        if self.flip_y {
+            write!(f, ", flip_y")?;
+        }
+
+        if let Some((r, g, b)) = self.color_key {
+            write!(f, ", {}", Value::Color(r, g, b))?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parsing() {
+        assert_eq!("@3".parse::<BlitSource>(), Ok(BlitSource::Asset(3)));
+        assert_eq!("$vi(24)".parse::<BlitSource>(), Ok(BlitSource::ValueIndex(24)));
+        assert!("$st".parse::<BlitSource>().is_err());
+
+        let args: Vec<String> = vec!["flip_y".into(), "#ff00ff".into()];
+        let options = BlitOptions::parse(&args).unwrap();
+
+        assert!(!options.flip_x && options.flip_y);
+        assert_eq!(options.color_key, Some((255, 0, 255)));
+        assert_eq!(options.to_string(), ", flip_y, #ff00ff");
+
+        assert!(BlitOptions::parse(&["mirror".to_owned()]).is_err());
+    }
+}
@@ -1,5 +1,9 @@
 //! The configuration format for the program container
 
+use controller::Button;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
 use typedef::*;
 
 pub const DEFAULT_SCALE: f64 = 4.0;
@@ -16,6 +20,8 @@ pub struct DisplayConfig {
     pub default_scale: Float,
     #[serde(default)]
     pub hide_cursor: bool,
+    #[serde(default)]
+    pub color_mode: ColorMode,
 }
 
 impl Default for DisplayConfig {
@@ -24,6 +30,53 @@ impl Default for DisplayConfig {
             resolution: Default::default(),
             default_scale: DEFAULT_SCALE,
             hide_cursor: true,
+            color_mode: Default::default(),
+        }
+    }
+}
+
+/// How the pixels of the framebuffer are stored
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+pub enum ColorMode {
+    /// Every pixel holds a color
+    #[default]
+    Direct,
+    /// Every pixel holds an index into a palette of 16 colors
+    Indexed16,
+    /// Every pixel holds an index into a palette of 256 colors
+    Indexed256,
+}
+
+impl ColorMode {
+    /// Returns the number of palette entries, if the mode uses a palette
+    pub fn palette_size(&self) -> Option<usize> {
+        match *self {
+            ColorMode::Direct => None,
+            ColorMode::Indexed16 => Some(16),
+            ColorMode::Indexed256 => Some(256),
+        }
+    }
+}
+
+impl FromStr for ColorMode {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "direct" => Ok(ColorMode::Direct),
+            "indexed16" => Ok(ColorMode::Indexed16),
+            "indexed256" => Ok(ColorMode::Indexed256),
+            _ => Err("unknown color mode. Color mode must be one of [direct, indexed16, indexed256]"),
+        }
+    }
+}
+
+impl fmt::Display for ColorMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ColorMode::Direct => write!(f, "direct"),
+            ColorMode::Indexed16 => write!(f, "indexed16"),
+            ColorMode::Indexed256 => write!(f, "indexed256"),
         }
     }
 }
@@ -43,6 +96,24 @@ impl Default for DisplayResolution {
     }
 }
 
+impl FromStr for DisplayResolution {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut split = s.splitn(2, 'x');
+
+        let width = split.next().and_then(|width| width.trim().parse().ok());
+        let height = split.next().and_then(|height| height.trim().parse().ok());
+
+        match (width, height) {
+            (Some(width), Some(height)) if width > 0 && height > 0 => {
+                Ok(DisplayResolution { width, height })
+            }
+            _ => Err("unable to parse resolution. Resolutions must look like WIDTHxHEIGHT"),
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Config {
     #[serde(default)]
@@ -51,6 +122,9 @@ pub struct Config {
     pub display: DisplayConfig,
     #[serde(default)]
     pub input_enabled: bool,
+    /// The key codes that the buttons of a game controller press
+    #[serde(default)]
+    pub button_map: BTreeMap<Button, Address>,
 }
 
 impl Default for Config {
@@ -59,6 +133,7 @@ impl Default for Config {
             title: DEFAULT_WINDOW_TITLE.into(),
             display: Default::default(),
             input_enabled: true,
+            button_map: BTreeMap::new(),
         }
     }
 }
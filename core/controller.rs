@@ -0,0 +1,133 @@
+//! The buttons and axes of a game controller
+
+use std::fmt;
+use std::str::FromStr;
+use typedef::*;
+
+/// The number of value-index slots holding the axes of the controller
+pub const AXIS_COUNT: usize = 6;
+
+/// The reserved value-index slot of the first axis
+pub const FIRST_AXIS_SLOT: Address = 8;
+
+/// A button of a game controller. The buttons are laid out like the ones of
+/// an Xbox controller.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Button {
+    A,
+    B,
+    X,
+    Y,
+    Back,
+    Guide,
+    Start,
+    LeftStick,
+    RightStick,
+    LeftShoulder,
+    RightShoulder,
+    DPadUp,
+    DPadDown,
+    DPadLeft,
+    DPadRight,
+}
+
+impl Button {
+    /// Returns the index of the button in the button register
+    pub fn index(&self) -> Address {
+        *self as Address
+    }
+}
+
+impl FromStr for Button {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "a" => Ok(Button::A),
+            "b" => Ok(Button::B),
+            "x" => Ok(Button::X),
+            "y" => Ok(Button::Y),
+            "back" => Ok(Button::Back),
+            "guide" => Ok(Button::Guide),
+            "start" => Ok(Button::Start),
+            "leftstick" => Ok(Button::LeftStick),
+            "rightstick" => Ok(Button::RightStick),
+            "leftshoulder" => Ok(Button::LeftShoulder),
+            "rightshoulder" => Ok(Button::RightShoulder),
+            "dpup" => Ok(Button::DPadUp),
+            "dpdown" => Ok(Button::DPadDown),
+            "dpleft" => Ok(Button::DPadLeft),
+            "dpright" => Ok(Button::DPadRight),
+            _ => Err(
+                "unknown button. Button must be one of [a, b, x, y, back, guide, start, \
+                 leftstick, rightstick, leftshoulder, rightshoulder, dpup, dpdown, dpleft, \
+                 dpright]",
+            ),
+        }
+    }
+}
+
+impl fmt::Display for Button {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            Button::A => "a",
+            Button::B => "b",
+            Button::X => "x",
+            Button::Y => "y",
+            Button::Back => "back",
+            Button::Guide => "guide",
+            Button::Start => "start",
+            Button::LeftStick => "leftstick",
+            Button::RightStick => "rightstick",
+            Button::LeftShoulder => "leftshoulder",
+            Button::RightShoulder => "rightshoulder",
+            Button::DPadUp => "dpup",
+            Button::DPadDown => "dpdown",
+            Button::DPadLeft => "dpleft",
+            Button::DPadRight => "dpright",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+/// An axis of a game controller
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Axis {
+    LeftX,
+    LeftY,
+    RightX,
+    RightY,
+    TriggerLeft,
+    TriggerRight,
+}
+
+impl Axis {
+    /// Returns the reserved value-index slot holding the axis
+    pub fn slot(&self) -> Address {
+        FIRST_AXIS_SLOT + *self as Address
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn button_names() {
+        let names = [
+            "a", "b", "x", "y", "back", "guide", "start", "leftstick", "rightstick",
+            "leftshoulder", "rightshoulder", "dpup", "dpdown", "dpleft", "dpright",
+        ];
+
+        for (index, name) in names.iter().enumerate() {
+            let button: Button = name.parse().unwrap();
+
+            assert_eq!(button.index(), index);
+            assert_eq!(&button.to_string(), name);
+        }
+
+        assert!("select".parse::<Button>().is_err());
+        assert_eq!(Axis::TriggerRight.slot(), 13);
+    }
+}
@@ -1,6 +1,7 @@
 //! Interrupts for communicating with the VM from the outside and also for
 //! letting the VM communicate with the outside
 
+use controller::{Axis, Button};
 use typedef::*;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -19,4 +20,12 @@ pub enum Event {
     },
     MouseMove { x: Address, y: Address },
     Halt,
+    ButtonDown(Button),
+    ButtonUp(Button),
+    /// The value of a stick axis ranges from -1 to 1, the one of a trigger
+    /// from 0 to 1
+    AxisMotion { axis: Axis, value: Float },
+    /// Positive values scroll right and up
+    MouseWheel { dx: Integer, dy: Integer },
+    TextInput(char),
 }
@@ -0,0 +1,180 @@
+//! The container images are stored in. It wraps the encoded program with a
+//! header and a checksum, so damaged images are detected before decoding.
+//! The layout is described in `docs/image_format.md`.
+
+use IMAGE_FORMAT_VERSION;
+use error::*;
+use flate2::Crc;
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+use std::io::{Read, Write};
+use typedef::*;
+
+pub const IMAGE_MAGIC: &[u8] = b"BAKERIMG";
+
+/// The payload is compressed using DEFLATE
+pub const FLAG_COMPRESSED: u32 = 1;
+
+/// All flags known to this version
+const KNOWN_FLAGS: u32 = FLAG_COMPRESSED;
+
+const HEADER_LEN: usize = 28;
+
+/// Wraps the given encoded program in an image container
+pub fn pack(payload: &[u8], flags: u32) -> Result<ImageData> {
+    ensure!(flags & !KNOWN_FLAGS == 0, "unknown image flags {:#x}", flags);
+
+    let payload = if flags & FLAG_COMPRESSED != 0 {
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::best());
+
+        encoder
+            .write_all(payload)
+            .chain_err(|| "unable to compress image")?;
+
+        encoder.finish().chain_err(|| "unable to compress image")?
+    } else {
+        payload.to_vec()
+    };
+
+    let mut crc = Crc::new();
+    crc.update(&payload);
+
+    let mut image = Vec::with_capacity(HEADER_LEN + payload.len());
+    image.extend_from_slice(IMAGE_MAGIC);
+    image.extend_from_slice(&IMAGE_FORMAT_VERSION.to_le_bytes());
+    image.extend_from_slice(&flags.to_le_bytes());
+    image.extend_from_slice(&(payload.len() as u64).to_le_bytes());
+    image.extend_from_slice(&crc.sum().to_le_bytes());
+    image.extend_from_slice(&payload);
+
+    Ok(image)
+}
+
+/// Returns true if the given image uses the container. Images of formats
+/// older than 3 are plain encoded programs.
+pub fn is_container(image: &[u8]) -> bool {
+    image.starts_with(IMAGE_MAGIC)
+}
+
+/// Checks the given image container and returns its uncompressed payload
+pub fn unpack(image: &[u8]) -> Result<Vec<u8>> {
+    ensure!(is_container(image), "not a bakerVM image");
+    ensure!(
+        image.len() >= HEADER_LEN,
+        "the image is truncated: the header is incomplete"
+    );
+
+    let format_version = read_u32(&image[8..12]);
+    let flags = read_u32(&image[12..16]);
+    let payload_len = read_u64(&image[16..24]);
+    let checksum = read_u32(&image[24..28]);
+
+    ensure!(
+        format_version <= IMAGE_FORMAT_VERSION,
+        "the image format version {} is newer than the supported version {}. Update bakerVM \
+         to run this image",
+        format_version,
+        IMAGE_FORMAT_VERSION
+    );
+
+    ensure!(
+        flags & !KNOWN_FLAGS == 0,
+        "the image uses unknown flags {:#x}. Update bakerVM to run this image",
+        flags
+    );
+
+    let payload = &image[HEADER_LEN..];
+
+    ensure!(
+        payload.len() as u64 >= payload_len,
+        "the image is truncated: expected {} bytes of program data, found {}",
+        payload_len,
+        payload.len()
+    );
+    ensure!(
+        payload.len() as u64 == payload_len,
+        "the image is corrupted: found {} bytes after the program data",
+        payload.len() as u64 - payload_len
+    );
+
+    let mut crc = Crc::new();
+    crc.update(payload);
+
+    ensure!(
+        crc.sum() == checksum,
+        "the image is corrupted: the checksum {:#010x} doesn't match {:#010x}",
+        crc.sum(),
+        checksum
+    );
+
+    if flags & FLAG_COMPRESSED != 0 {
+        let mut buf = Vec::new();
+
+        DeflateDecoder::new(payload)
+            .read_to_end(&mut buf)
+            .chain_err(|| "unable to decompress image")?;
+
+        Ok(buf)
+    } else {
+        Ok(payload.to_vec())
+    }
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    let mut buf = [0; 4];
+    buf.copy_from_slice(bytes);
+
+    u32::from_le_bytes(buf)
+}
+
+fn read_u64(bytes: &[u8]) -> u64 {
+    let mut buf = [0; 8];
+    buf.copy_from_slice(bytes);
+
+    u64::from_le_bytes(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let payload: Vec<u8> = (0..1000).map(|i| (i % 7) as u8).collect();
+
+        let compressed = pack(&payload, FLAG_COMPRESSED).unwrap();
+        let uncompressed = pack(&payload, 0).unwrap();
+
+        assert!(compressed.len() < payload.len());
+        assert_eq!(uncompressed.len(), HEADER_LEN + payload.len());
+
+        assert_eq!(unpack(&compressed).unwrap(), payload);
+        assert_eq!(unpack(&uncompressed).unwrap(), payload);
+    }
+
+    #[test]
+    fn damaged_images() {
+        let image = pack(b"some program", FLAG_COMPRESSED).unwrap();
+
+        let error = unpack(&image[..image.len() - 3]).unwrap_err().to_string();
+        assert!(error.starts_with("the image is truncated"));
+
+        let error = unpack(&image[..20]).unwrap_err().to_string();
+        assert!(error.starts_with("the image is truncated"));
+
+        let mut corrupted = image.clone();
+        corrupted[HEADER_LEN + 2] ^= 0xff;
+        let error = unpack(&corrupted).unwrap_err().to_string();
+        assert!(error.starts_with("the image is corrupted"));
+
+        let mut newer = image.clone();
+        newer[8] = IMAGE_FORMAT_VERSION as u8 + 1;
+        let error = unpack(&newer).unwrap_err().to_string();
+        assert!(error.contains("newer than the supported version"));
+
+        let mut flagged = image;
+        flagged[12] |= 0x80;
+        assert!(unpack(&flagged).is_err());
+    }
+}
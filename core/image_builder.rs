@@ -1,11 +1,14 @@
 //! A helpful image builder used in tests and for generating the stock image
 
+use asset::Asset;
+use audio::Waveform;
+use blit::{BlitOptions, BlitSource};
+use config::Config;
 use instruction::Instruction;
 use program::Program;
-use rmp_serde;
-use serde::Serialize;
 use signal::Signal;
 use target::Target;
+use tilemap::TilemapSource;
 use type_t::Type;
 use typedef::*;
 use value::Value;
@@ -13,11 +16,21 @@ use value::Value;
 #[derive(Default, Clone)]
 pub struct ImageBuilder {
     instructions: Vec<Instruction>,
+    config: Config,
+    assets: Vec<Asset>,
 }
 
 impl ImageBuilder {
     pub fn new() -> ImageBuilder {
-        ImageBuilder { instructions: Vec::new() }
+        ImageBuilder::default()
+    }
+
+    pub fn set_config(&mut self, config: Config) {
+        self.config = config;
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
     }
 
     pub fn len(&mut self) -> usize {
@@ -138,21 +151,133 @@ impl ImageBuilder {
 
 
 
-    pub fn gen(self) -> ImageData {
-        let program = self.gen_program();
+    pub fn read(&mut self, dest: Target, asset: Address) {
+        self.add_instruction(Instruction::Read(dest, asset));
+    }
+
+    pub fn blit(&mut self, source: BlitSource, options: BlitOptions) {
+        self.add_instruction(Instruction::Blit(source, options));
+    }
+
+    pub fn clear(&mut self) {
+        self.add_instruction(Instruction::Clear);
+    }
+
+    pub fn rect(&mut self) {
+        self.add_instruction(Instruction::Rect);
+    }
+
+    pub fn line(&mut self) {
+        self.add_instruction(Instruction::Line);
+    }
+
+    pub fn hspan(&mut self) {
+        self.add_instruction(Instruction::HSpan);
+    }
+
+    pub fn vspan(&mut self) {
+        self.add_instruction(Instruction::VSpan);
+    }
+
+    pub fn draw_char(&mut self, font: Option<Address>) {
+        self.add_instruction(Instruction::DrawChar(font));
+    }
+
+    pub fn draw_text(&mut self, text: Address, font: Option<Address>) {
+        self.add_instruction(Instruction::DrawText(text, font));
+    }
+
+    pub fn set_palette(&mut self) {
+        self.add_instruction(Instruction::SetPalette);
+    }
+
+    pub fn rotate_palette(&mut self) {
+        self.add_instruction(Instruction::RotatePalette);
+    }
+
+    pub fn layer(&mut self, layer: Address) {
+        self.add_instruction(Instruction::Layer(layer));
+    }
+
+    pub fn scroll(&mut self, layer: Address) {
+        self.add_instruction(Instruction::Scroll(layer));
+    }
+
+    pub fn layer_key(&mut self, layer: Address) {
+        self.add_instruction(Instruction::LayerKey(layer));
+    }
+
+    pub fn tilemap(&mut self, layer: Address, source: TilemapSource, tileset: Address) {
+        self.add_instruction(Instruction::Tilemap(layer, source, tileset));
+    }
 
-        let mut buf = Vec::new();
+    pub fn set_tile(&mut self, layer: Address) {
+        self.add_instruction(Instruction::SetTile(layer));
+    }
+
+    pub fn get_tile(&mut self, layer: Address) {
+        self.add_instruction(Instruction::GetTile(layer));
+    }
+
+    pub fn scroll_map(&mut self, layer: Address) {
+        self.add_instruction(Instruction::ScrollMap(layer));
+    }
+
+    pub fn sprite(&mut self, slot: Address) {
+        self.add_instruction(Instruction::Sprite(slot));
+    }
+
+    pub fn sprite_attr(&mut self, slot: Address) {
+        self.add_instruction(Instruction::SpriteAttr(slot));
+    }
+
+    pub fn hide_sprite(&mut self, slot: Address) {
+        self.add_instruction(Instruction::HideSprite(slot));
+    }
+
+    pub fn sprite_hit(&mut self, slot: Address) {
+        self.add_instruction(Instruction::SpriteHit(slot));
+    }
+
+    pub fn sprite_hit_pair(&mut self, slot: Address, other: Address) {
+        self.add_instruction(Instruction::SpriteHitPair(slot, other));
+    }
+
+    pub fn bg_hit(&mut self, slot: Address) {
+        self.add_instruction(Instruction::BgHit(slot));
+    }
 
-        program
-            .serialize(&mut rmp_serde::Serializer::new(&mut buf))
-            .expect("unable to encode program");
+    pub fn voice(&mut self, channel: Address, waveform: Waveform) {
+        self.add_instruction(Instruction::Voice(channel, waveform));
+    }
+
+    pub fn tone(&mut self, channel: Address) {
+        self.add_instruction(Instruction::Tone(channel));
+    }
+
+    pub fn envelope(&mut self, channel: Address) {
+        self.add_instruction(Instruction::Envelope(channel));
+    }
 
-        buf
+    pub fn note_off(&mut self, channel: Address) {
+        self.add_instruction(Instruction::NoteOff(channel));
+    }
+
+    /// Adds the given asset to the image and returns its index
+    pub fn add_asset(&mut self, asset: Asset) -> Address {
+        self.assets.push(asset);
+        self.assets.len() - 1
+    }
+
+    pub fn gen(self) -> ImageData {
+        self.gen_program().encode().expect("unable to encode program")
     }
 
     pub fn gen_program(&self) -> Program {
         Program {
+            config: self.config.clone(),
             instructions: self.instructions.clone(),
+            assets: self.assets.clone(),
             ..Program::default()
         }
     }
@@ -170,4 +295,21 @@ mod tests {
         builder.add(Target::Stack, Target::ValueIndex(0));
         builder.gen();
     }
+
+    #[test]
+    fn config() {
+        let mut builder = ImageBuilder::new();
+
+        let mut config = Config {
+            title: "My Game".into(),
+            ..Config::default()
+        };
+        config.display.resolution.width = 320;
+        builder.set_config(config);
+
+        let program = builder.gen_program();
+
+        assert_eq!(program.config.title, "My Game");
+        assert_eq!(program.config.display.resolution.width, 320);
+    }
 }
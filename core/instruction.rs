@@ -1,11 +1,17 @@
 //! The instructions, the VM is able to interpret.
 
+use audio::Waveform;
+use blit::{BlitOptions, BlitSource};
 use signal::Signal;
 use target::Target;
+use tilemap::TilemapSource;
 use type_t::Type;
 use typedef::*;
 use value::Value;
 
+/// The number of entries of the sprite table
+pub const SPRITE_COUNT: usize = 64;
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum Instruction {
     Add(Target, Target),
@@ -36,4 +42,128 @@ pub enum Instruction {
     Pause,
     Nop,
     Sig(Signal),
+
+    Read(Target, Address),
+    Blit(BlitSource, BlitOptions),
+
+    Clear,
+    Rect,
+    Line,
+    HSpan,
+    VSpan,
+
+    DrawChar(Option<Address>),
+    DrawText(Address, Option<Address>),
+
+    SetPalette,
+    RotatePalette,
+
+    Layer(Address),
+    Scroll(Address),
+    LayerKey(Address),
+
+    Tilemap(Address, TilemapSource, Address),
+    SetTile(Address),
+    GetTile(Address),
+    ScrollMap(Address),
+
+    Sprite(Address),
+    SpriteAttr(Address),
+    HideSprite(Address),
+    SpriteHit(Address),
+    BgHit(Address),
+
+    Voice(Address, Waveform),
+    Tone(Address),
+    Envelope(Address),
+    NoteOff(Address),
+
+    SpriteHitPair(Address, Address),
+}
+
+impl Instruction {
+    /// Returns the BASM name of the instruction
+    pub fn mnemonic(&self) -> &'static str {
+        match *self {
+            Instruction::Add(..) => "add",
+            Instruction::Sub(..) => "sub",
+            Instruction::Div(..) => "div",
+            Instruction::Mul(..) => "mul",
+            Instruction::Rem(..) => "rem",
+
+            Instruction::Cmp(..) => "cmp",
+            Instruction::Jmp(..) => "jmp",
+            Instruction::JmpLt(..) => "jmplt",
+            Instruction::JmpGt(..) => "jmpgt",
+            Instruction::JmpEq(..) => "jmpeq",
+            Instruction::JmpLtEq(..) => "jmplteq",
+            Instruction::JmpGtEq(..) => "jmpgteq",
+
+            Instruction::Cast(..) => "cast",
+
+            Instruction::Push(..) => "push",
+            Instruction::Mov(..) => "mov",
+            Instruction::Swp(..) => "swp",
+            Instruction::Dup(..) => "dup",
+
+            Instruction::Call(..) => "call",
+            Instruction::Ret => "ret",
+
+            Instruction::Halt => "halt",
+            Instruction::Pause => "pause",
+            Instruction::Nop => "nop",
+            Instruction::Sig(..) => "sig",
+
+            Instruction::Read(..) => "read",
+            Instruction::Blit(..) => "blit",
+
+            Instruction::Clear => "clear",
+            Instruction::Rect => "rect",
+            Instruction::Line => "line",
+            Instruction::HSpan => "hspan",
+            Instruction::VSpan => "vspan",
+
+            Instruction::DrawChar(..) => "drawchar",
+            Instruction::DrawText(..) => "drawtext",
+
+            Instruction::SetPalette => "setpal",
+            Instruction::RotatePalette => "rotpal",
+
+            Instruction::Layer(..) => "layer",
+            Instruction::Scroll(..) => "scroll",
+            Instruction::LayerKey(..) => "layerkey",
+
+            Instruction::Tilemap(..) => "tilemap",
+            Instruction::SetTile(..) => "settile",
+            Instruction::GetTile(..) => "gettile",
+            Instruction::ScrollMap(..) => "scrollmap",
+
+            Instruction::Sprite(..) => "sprite",
+            Instruction::SpriteAttr(..) => "spriteattr",
+            Instruction::HideSprite(..) => "hidesprite",
+            Instruction::SpriteHit(..) => "spritehit",
+            Instruction::BgHit(..) => "bghit",
+
+            Instruction::Voice(..) => "voice",
+            Instruction::Tone(..) => "tone",
+            Instruction::Envelope(..) => "envelope",
+            Instruction::NoteOff(..) => "noteoff",
+
+            Instruction::SpriteHitPair(..) => "spritehit",
+        }
+    }
+
+    /// Returns the address a jump or call instruction transfers control to
+    pub fn target_address(&self) -> Option<Address> {
+        match *self {
+            Instruction::Jmp(addr) |
+            Instruction::JmpLt(addr) |
+            Instruction::JmpGt(addr) |
+            Instruction::JmpEq(addr) |
+            Instruction::JmpLtEq(addr) |
+            Instruction::JmpGtEq(addr) |
+            Instruction::Call(addr) => Some(addr),
+            _ => None,
+        }
+    }
 }
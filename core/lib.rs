@@ -2,6 +2,7 @@
 
 #[macro_use]
 extern crate error_chain;
+extern crate flate2;
 #[macro_use]
 extern crate serde_derive;
 extern crate serde;
@@ -10,26 +11,47 @@ extern crate regex;
 #[macro_use]
 extern crate lazy_static;
 
+mod analysis;
+mod asset;
+mod audio;
+mod blit;
 mod config;
+mod controller;
 mod image_builder;
 mod instruction;
 mod event;
+mod font;
 mod signal;
 mod program;
 mod target;
+mod tilemap;
 mod value;
 mod type_t;
 pub mod error;
+pub mod image;
 pub mod typedef;
 
+pub use analysis::*;
+pub use asset::*;
+pub use audio::*;
+pub use blit::*;
 pub use config::*;
+pub use controller::*;
 pub use event::*;
+pub use font::*;
 pub use image_builder::*;
 pub use instruction::*;
 pub use program::*;
 pub use signal::*;
 pub use target::*;
+pub use tilemap::*;
 pub use type_t::*;
 pub use value::*;
 
+use typedef::FormatVersion;
+
 pub const PREAMBLE: &str = "BAKERVM";
+
+/// The version of the image format written by this release. See
+/// `docs/image_format.md` for the compatibility policy.
+pub const IMAGE_FORMAT_VERSION: FormatVersion = 15;
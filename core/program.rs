@@ -1,24 +1,440 @@
 //! The program container holding the instructions and all configuration options
 
-use PREAMBLE;
-use config::Config;
+use {IMAGE_FORMAT_VERSION, PREAMBLE};
+use asset::Asset;
+use config::{Config, DisplayConfig, DisplayResolution};
+use error::*;
+use image::{self, FLAG_COMPRESSED};
 use instruction::Instruction;
+use rmp_serde;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use typedef::*;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Program {
     pub preamble: String,
+    /// The version of the image format. Together with the preamble, it has
+    /// to stay the first field in all future formats.
+    pub format_version: FormatVersion,
+    /// The version of the bakerVM tools that built the image
     pub version: String,
     pub config: Config,
     pub instructions: Vec<Instruction>,
+    pub debug: Option<DebugInfo>,
+    /// Binary data sections referenced by their index
+    pub assets: Vec<Asset>,
+}
+
+/// Information about the source of a program, that is not needed to execute it
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct DebugInfo {
+    /// The names of all labels and the addresses they point to
+    pub labels: BTreeMap<String, Address>,
 }
 
 impl Default for Program {
     fn default() -> Self {
         Program {
             preamble: String::from(PREAMBLE),
+            format_version: IMAGE_FORMAT_VERSION,
             version: String::from(env!("CARGO_PKG_VERSION")),
             config: Default::default(),
             instructions: Default::default(),
+            debug: None,
+            assets: Vec::new(),
+        }
+    }
+}
+
+impl Program {
+    /// Encodes the program into a compressed image
+    pub fn encode(&self) -> Result<ImageData> {
+        let mut buf = Vec::new();
+
+        self.serialize(&mut rmp_serde::Serializer::new(&mut buf))
+            .chain_err(|| "unable to encode program")?;
+
+        image::pack(&buf, FLAG_COMPRESSED)
+    }
+
+    /// Decodes a program from the given image. Images of older formats are
+    /// migrated to the current format.
+    pub fn decode(image: &[u8]) -> Result<Program> {
+        Program::decode_versioned(image).map(|(program, _)| program)
+    }
+
+    /// Decodes a program from the given image and returns it along with the
+    /// format version the image was written in
+    pub fn decode_versioned(image: &[u8]) -> Result<(Program, FormatVersion)> {
+        let (mut program, format_version) = if image::is_container(image) {
+            Program::decode_payload(&image::unpack(image)?)?
+        } else {
+            Program::decode_payload(image)?
+        };
+
+        program.format_version = IMAGE_FORMAT_VERSION;
+
+        Ok((program, format_version))
+    }
+
+    /// Decodes a program encoded in any of the known formats
+    fn decode_payload(image: &[u8]) -> Result<(Program, FormatVersion)> {
+        let mut de = rmp_serde::Deserializer::new(image);
+
+        // Trailing fields are ignored when decoding, so images of newer
+        // formats might decode without an error
+        if let Ok(header) = ImageHeader::deserialize(&mut de) {
+            if header.preamble == PREAMBLE && header.format_version > IMAGE_FORMAT_VERSION {
+                bail!(
+                    "the image format version {} is newer than the supported version {}. \
+                     Update bakerVM to run this image",
+                    header.format_version,
+                    IMAGE_FORMAT_VERSION
+                );
+            }
+        }
+
+        let mut de = rmp_serde::Deserializer::new(image);
+
+        let result: Result<Program> =
+            Deserialize::deserialize(&mut de).chain_err(|| "unable to decode image file");
+
+        if let Ok(program) = result {
+            let format_version = program.format_version;
+
+            return Ok((program, format_version));
+        }
+
+        let mut de = rmp_serde::Deserializer::new(image);
+
+        if let Ok(program) = ProgramV7::deserialize(&mut de) {
+            let format_version = program.format_version;
+
+            return Ok((program.migrate(), format_version));
+        }
+
+        let mut de = rmp_serde::Deserializer::new(image);
+
+        if let Ok(program) = ProgramV3::deserialize(&mut de) {
+            let format_version = program.format_version;
+
+            return Ok((program.migrate(), format_version));
+        }
+
+        let mut de = rmp_serde::Deserializer::new(image);
+
+        if let Ok(program) = ProgramV1::deserialize(&mut de) {
+            return Ok((program.migrate(), 1));
         }
+
+        let mut de = rmp_serde::Deserializer::new(image);
+
+        if let Ok(program) = ProgramV0::deserialize(&mut de) {
+            return Ok((program.migrate(), 0));
+        }
+
+        result.map(|program| (program, IMAGE_FORMAT_VERSION))
+    }
+}
+
+/// The first fields of every image, used to detect images of newer formats.
+/// Older formats don't have these fields.
+#[derive(Deserialize)]
+struct ImageHeader {
+    preamble: String,
+    format_version: FormatVersion,
+}
+
+/// Images of format 4 to 7 have no color mode in their display configuration
+#[derive(Deserialize)]
+struct ProgramV7 {
+    preamble: String,
+    format_version: FormatVersion,
+    version: String,
+    config: ConfigV7,
+    instructions: Vec<Instruction>,
+    debug: Option<DebugInfo>,
+    assets: Vec<Asset>,
+}
+
+impl ProgramV7 {
+    fn migrate(self) -> Program {
+        Program {
+            preamble: self.preamble,
+            format_version: IMAGE_FORMAT_VERSION,
+            version: self.version,
+            config: self.config.migrate(),
+            instructions: self.instructions,
+            debug: self.debug,
+            assets: self.assets,
+        }
+    }
+}
+
+/// The configuration of images up to format 7
+#[derive(Deserialize)]
+struct ConfigV7 {
+    title: String,
+    display: DisplayConfigV7,
+    input_enabled: bool,
+}
+
+#[derive(Deserialize)]
+struct DisplayConfigV7 {
+    resolution: DisplayResolution,
+    default_scale: Float,
+    hide_cursor: bool,
+}
+
+impl ConfigV7 {
+    fn migrate(self) -> Config {
+        Config {
+            title: self.title,
+            display: DisplayConfig {
+                resolution: self.display.resolution,
+                default_scale: self.display.default_scale,
+                hide_cursor: self.display.hide_cursor,
+                color_mode: Default::default(),
+            },
+            input_enabled: self.input_enabled,
+            button_map: Default::default(),
+        }
+    }
+}
+
+/// Images of format 2 and 3 have no assets
+#[derive(Deserialize)]
+struct ProgramV3 {
+    preamble: String,
+    format_version: FormatVersion,
+    version: String,
+    config: ConfigV7,
+    instructions: Vec<Instruction>,
+    debug: Option<DebugInfo>,
+}
+
+impl ProgramV3 {
+    fn migrate(self) -> Program {
+        ProgramV7 {
+            preamble: self.preamble,
+            format_version: self.format_version,
+            version: self.version,
+            config: self.config,
+            instructions: self.instructions,
+            debug: self.debug,
+            assets: Vec::new(),
+        }.migrate()
+    }
+}
+
+/// Images of format 1 have no format version. Only the bakerVM version that
+/// built them was stored, and images were rejected if it differed.
+#[derive(Deserialize)]
+struct ProgramV1 {
+    preamble: String,
+    version: String,
+    config: ConfigV7,
+    instructions: Vec<Instruction>,
+    debug: Option<DebugInfo>,
+}
+
+impl ProgramV1 {
+    fn migrate(self) -> Program {
+        ProgramV3 {
+            preamble: self.preamble,
+            format_version: 1,
+            version: self.version,
+            config: self.config,
+            instructions: self.instructions,
+            debug: self.debug,
+        }.migrate()
+    }
+}
+
+/// Images of format 0 were built before the debug section was added
+#[derive(Deserialize)]
+struct ProgramV0 {
+    preamble: String,
+    version: String,
+    config: ConfigV7,
+    instructions: Vec<Instruction>,
+}
+
+impl ProgramV0 {
+    fn migrate(self) -> Program {
+        ProgramV1 {
+            preamble: self.preamble,
+            version: self.version,
+            config: self.config,
+            instructions: self.instructions,
+            debug: None,
+        }.migrate()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::ColorMode;
+
+    /// The configuration of images up to format 7, which has no color mode
+    fn old_config() -> (&'static str, ((usize, usize), Float, bool), bool) {
+        ("bakerVM", ((160, 100), 4.0, true), true)
+    }
+
+    #[test]
+    fn decode_without_debug_info() {
+        let mut buf = Vec::new();
+
+        let old_program = (
+            PREAMBLE,
+            "0.9.0",
+            old_config(),
+            vec![Instruction::Nop],
+        );
+
+        old_program
+            .serialize(&mut rmp_serde::Serializer::new(&mut buf))
+            .unwrap();
+
+        let (program, format_version) = Program::decode_versioned(&buf).unwrap();
+
+        assert_eq!(format_version, 0);
+        assert_eq!(program.format_version, IMAGE_FORMAT_VERSION);
+        assert_eq!(program.instructions.len(), 1);
+        assert!(program.debug.is_none());
+    }
+
+    #[test]
+    fn migrate_format_1() {
+        let mut buf = Vec::new();
+
+        let old_program = (
+            PREAMBLE,
+            "0.9.0",
+            old_config(),
+            vec![Instruction::Nop, Instruction::Halt],
+            Some(DebugInfo::default()),
+        );
+
+        old_program
+            .serialize(&mut rmp_serde::Serializer::new(&mut buf))
+            .unwrap();
+
+        let (program, format_version) = Program::decode_versioned(&buf).unwrap();
+
+        assert_eq!(format_version, 1);
+        assert_eq!(program.version, "0.9.0");
+        assert_eq!(program.instructions.len(), 2);
+        assert!(program.debug.is_some());
+
+        let (_, format_version) = Program::decode_versioned(&program.encode().unwrap()).unwrap();
+
+        assert_eq!(format_version, IMAGE_FORMAT_VERSION);
+    }
+
+    #[test]
+    fn decode_without_container() {
+        let mut buf = Vec::new();
+
+        let old_program = (
+            PREAMBLE,
+            2,
+            "0.9.0",
+            old_config(),
+            vec![Instruction::Halt],
+            None::<DebugInfo>,
+        );
+
+        old_program
+            .serialize(&mut rmp_serde::Serializer::new(&mut buf))
+            .unwrap();
+
+        let (program, format_version) = Program::decode_versioned(&buf).unwrap();
+
+        assert_eq!(format_version, 2);
+        assert_eq!(program.format_version, IMAGE_FORMAT_VERSION);
+        assert_eq!(program.instructions.len(), 1);
+
+        let (program, format_version) =
+            Program::decode_versioned(&image::pack(&buf, FLAG_COMPRESSED).unwrap()).unwrap();
+
+        assert_eq!(format_version, 2);
+        assert!(program.assets.is_empty());
+    }
+
+    #[test]
+    fn assets() {
+        let program = Program {
+            assets: vec![Asset::data("level".into(), vec![1, 2, 200])],
+            ..Program::default()
+        };
+
+        let program = Program::decode(&program.encode().unwrap()).unwrap();
+
+        assert_eq!(program.assets[0].name, "level");
+        assert_eq!(program.assets[0].data, vec![1, 2, 200]);
+    }
+
+    #[test]
+    fn decode_without_color_mode() {
+        let mut buf = Vec::new();
+
+        let old_program = (
+            PREAMBLE,
+            7,
+            "0.9.0",
+            ("Old Game", ((320, 200), 2.0, false), true),
+            vec![Instruction::Halt],
+            None::<DebugInfo>,
+            Vec::<Asset>::new(),
+        );
+
+        old_program
+            .serialize(&mut rmp_serde::Serializer::new(&mut buf))
+            .unwrap();
+
+        let (program, format_version) = Program::decode_versioned(&buf).unwrap();
+
+        assert_eq!(format_version, 7);
+        assert_eq!(program.config.title, "Old Game");
+        assert_eq!(program.config.display.resolution.width, 320);
+        assert_eq!(program.config.display.color_mode, ColorMode::Direct);
+    }
+
+    #[test]
+    fn reject_newer_format() {
+        let mut buf = Vec::new();
+
+        let new_program = (
+            PREAMBLE,
+            IMAGE_FORMAT_VERSION + 1,
+            "99.0.0",
+            Config::default(),
+            vec![Instruction::Nop],
+            None::<DebugInfo>,
+            "a section added later",
+        );
+
+        new_program
+            .serialize(&mut rmp_serde::Serializer::new(&mut buf))
+            .unwrap();
+
+        let error = Program::decode(&buf).unwrap_err().to_string();
+
+        assert!(error.contains("newer than the supported version"));
+    }
+
+    #[test]
+    fn debug_info() {
+        let mut program = Program::default();
+        let mut debug = DebugInfo::default();
+        debug.labels.insert("start".into(), 3);
+        program.debug = Some(debug);
+
+        let program = Program::decode(&program.encode().unwrap()).unwrap();
+
+        assert_eq!(program.debug.unwrap().labels["start"], 3);
     }
 }
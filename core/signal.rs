@@ -1,11 +1,20 @@
 //! Interrupts for communicating with the VM from the outside and also for
 //! letting the VM communicate with the outside
 
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde::de::{EnumAccess, VariantAccess, Visitor};
+use std::fmt;
 use std::str::FromStr;
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+/// The names of the known signals in the order of their indices
+const SIGNAL_NAMES: &[&str] = &["FlushFrame"];
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Signal {
     FlushFrame,
+    /// A signal this version doesn't know, e.g. from an image built by a newer
+    /// toolchain. Holds the index of the signal.
+    Unknown(u32),
 }
 
 impl FromStr for Signal {
@@ -18,3 +27,71 @@ impl FromStr for Signal {
         }
     }
 }
+
+impl fmt::Display for Signal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Signal::FlushFrame => write!(f, "%flush_frame%"),
+            Signal::Unknown(index) => write!(f, "%unknown_{}%", index),
+        }
+    }
+}
+
+// Signals are encoded like derived unit variants, but unknown indices are
+// decoded into `Signal::Unknown` instead of failing to decode the whole image
+
+impl Serialize for Signal {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match *self {
+            Signal::FlushFrame => serializer.serialize_unit_variant("Signal", 0, SIGNAL_NAMES[0]),
+            Signal::Unknown(index) => serializer.serialize_unit_variant("Signal", index, "Unknown"),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Signal {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_enum("Signal", SIGNAL_NAMES, SignalVisitor)
+    }
+}
+
+struct SignalVisitor;
+
+impl<'de> Visitor<'de> for SignalVisitor {
+    type Value = Signal;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a signal")
+    }
+
+    fn visit_enum<A: EnumAccess<'de>>(self, data: A) -> Result<Self::Value, A::Error> {
+        let (index, variant): (u32, _) = data.variant()?;
+        variant.unit_variant()?;
+
+        Ok(match index {
+            0 => Signal::FlushFrame,
+            index => Signal::Unknown(index),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rmp_serde;
+
+    fn round_trip(signal: &Signal) -> Signal {
+        let mut buf = Vec::new();
+        signal
+            .serialize(&mut rmp_serde::Serializer::new(&mut buf))
+            .unwrap();
+
+        Signal::deserialize(&mut rmp_serde::Deserializer::new(&buf[..])).unwrap()
+    }
+
+    #[test]
+    fn encoding() {
+        assert_eq!(round_trip(&Signal::FlushFrame), Signal::FlushFrame);
+        assert_eq!(round_trip(&Signal::Unknown(7)), Signal::Unknown(7));
+    }
+}
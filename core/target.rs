@@ -1,14 +1,20 @@
 //! A Target is an abstract representation of a memory section inside the VM
 
 use regex::Regex;
+use std::fmt;
 use std::str::FromStr;
 use typedef::*;
 
 lazy_static! {
     static ref VALUEINDEX_RE: Regex = Regex::new(r"^\$vi\((\d+)\)$").unwrap();
     static ref KEY_REGISTER_RE: Regex = Regex::new(r"^\$key\((\d+)\)$").unwrap();
+    static ref FRAMEBUFFER_LAYER_RE: Regex = Regex::new(r"^\$fb\((\d+)\)$").unwrap();
+    static ref BUTTON_REGISTER_RE: Regex = Regex::new(r"^\$btn\((\d+)\)$").unwrap();
 }
 
+/// The number of layers of the framebuffer. `$fb` is the bottom layer.
+pub const LAYER_COUNT: usize = 8;
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum Target {
     Framebuffer,
@@ -16,6 +22,11 @@ pub enum Target {
     Stack,
     BasePointer,
     KeyRegister(Address),
+    FramebufferLayer(Address),
+    /// The buttons of the game controller, that are held down
+    ButtonRegister(Address),
+    /// The queue of the characters typed by the user
+    TextInput,
 }
 
 impl FromStr for Target {
@@ -32,18 +43,45 @@ impl FromStr for Target {
             let index: Address = value[1].parse().unwrap();
 
             Ok(Target::KeyRegister(index))
+        } else if FRAMEBUFFER_LAYER_RE.is_match(s) {
+            let value = FRAMEBUFFER_LAYER_RE.captures_iter(s).next().unwrap();
+            let index: Address = value[1].parse().unwrap();
+
+            Ok(Target::FramebufferLayer(index))
+        } else if BUTTON_REGISTER_RE.is_match(s) {
+            let value = BUTTON_REGISTER_RE.captures_iter(s).next().unwrap();
+            let index: Address = value[1].parse().unwrap();
+
+            Ok(Target::ButtonRegister(index))
         } else if s == "$fb" {
             Ok(Target::Framebuffer)
         } else if s == "$st" {
             Ok(Target::Stack)
         } else if s == "$bp" {
             Ok(Target::BasePointer)
+        } else if s == "$txt" {
+            Ok(Target::TextInput)
         } else {
             Err("unable to parse target")
         }
     }
 }
 
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Target::Framebuffer => write!(f, "$fb"),
+            Target::ValueIndex(index) => write!(f, "$vi({})", index),
+            Target::Stack => write!(f, "$st"),
+            Target::BasePointer => write!(f, "$bp"),
+            Target::KeyRegister(key_code) => write!(f, "$key({})", key_code),
+            Target::FramebufferLayer(layer) => write!(f, "$fb({})", layer),
+            Target::ButtonRegister(button) => write!(f, "$btn({})", button),
+            Target::TextInput => write!(f, "$txt"),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -61,6 +99,15 @@ mod tests {
         }
     }
 
+    #[test]
+    fn display() {
+        for input in &["$fb", "$vi(123)", "$st", "$bp", "$key(42)", "$fb(3)", "$btn(11)", "$txt"] {
+            let target: Target = input.parse().unwrap();
+
+            assert_eq!(&target.to_string(), input);
+        }
+    }
+
     #[test]
     fn key_register_regex() {
         let input = "$key(123)";
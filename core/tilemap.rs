@@ -0,0 +1,38 @@
+//! The operands of the `Tilemap` instruction
+
+use std::fmt;
+use std::str::FromStr;
+use target::Target;
+use typedef::*;
+use value::Value;
+
+/// Where the tiles of a tilemap are read from
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum TilemapSource {
+    /// A tilemap asset with the given index
+    Asset(Address),
+    /// A tilemap in the value-index, starting at the given index with its
+    /// width and height in tiles, followed by its tiles row by row
+    ValueIndex(Address),
+}
+
+impl FromStr for TilemapSource {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match (s.parse::<Value>(), s.parse::<Target>()) {
+            (Ok(Value::Address(asset)), _) => Ok(TilemapSource::Asset(asset)),
+            (_, Ok(Target::ValueIndex(index))) => Ok(TilemapSource::ValueIndex(index)),
+            _ => Err("unable to parse tilemap source. Tilemap source must be an asset or $vi(#)"),
+        }
+    }
+}
+
+impl fmt::Display for TilemapSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TilemapSource::Asset(asset) => write!(f, "@{}", asset),
+            TilemapSource::ValueIndex(index) => write!(f, "$vi({})", index),
+        }
+    }
+}
@@ -1,3 +1,4 @@
+use std::fmt;
 use std::str::FromStr;
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, PartialOrd)]
@@ -25,3 +26,18 @@ impl FromStr for Type {
         }
     }
 }
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            Type::Address => "addr",
+            Type::Boolean => "bool",
+            Type::Float => "float",
+            Type::Integer => "int",
+            Type::Color => "color",
+            Type::Char => "char",
+        };
+
+        write!(f, "{}", name)
+    }
+}
@@ -12,3 +12,4 @@ pub type Address = usize;
 pub type ImageData = Vec<Byte>;
 pub type Color = (u8, u8, u8);
 pub type Frame = Vec<Color>;
+pub type FormatVersion = u32;
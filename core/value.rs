@@ -2,6 +2,7 @@
 
 use error::*;
 use regex::Regex;
+use std::fmt;
 use std::ops::{Add, Div, Mul, Rem, Sub};
 use std::result;
 use std::str::FromStr;
@@ -12,6 +13,7 @@ lazy_static! {
     static ref ADDRESS_RE: Regex = Regex::new(r"^@(\d+)$").unwrap();
     static ref BOOLEAN_RE: Regex = Regex::new(r"^true|false$").unwrap();
     static ref FLOAT_RE: Regex = Regex::new(r"^(-?\d+)?\.[0-9]+$").unwrap();
+    static ref NON_FINITE_RE: Regex = Regex::new(r"^(-?inf|NaN)$").unwrap();
     static ref INTEGER_RE: Regex = Regex::new(r"^(-?\d+)?$").unwrap();
     static ref COLOR_RE: Regex = Regex::new(r"^#([0-9abcdefABCDEF]{6})$").unwrap();
     static ref CHAR_RE: Regex = Regex::new(r"^'(.)'$").unwrap();
@@ -140,7 +142,7 @@ impl FromStr for Value {
             Ok(Value::Address(address_cap[1].parse().unwrap()))
         } else if BOOLEAN_RE.is_match(s) {
             Ok(Value::Boolean(s.parse().unwrap()))
-        } else if FLOAT_RE.is_match(s) {
+        } else if FLOAT_RE.is_match(s) || NON_FINITE_RE.is_match(s) {
             Ok(Value::Float(s.parse().unwrap()))
         } else if INTEGER_RE.is_match(s) {
             Ok(Value::Integer(s.parse().unwrap()))
@@ -162,6 +164,29 @@ impl FromStr for Value {
     }
 }
 
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Value::Address(addr) => write!(f, "@{}", addr),
+            Value::Boolean(boolean) => write!(f, "{}", boolean),
+            Value::Float(float) => {
+                let text = float.to_string();
+
+                // Floats need a decimal point to be parsed as floats again,
+                // except for `inf`, `-inf` and `NaN`
+                if text.contains('.') || !float.is_finite() {
+                    write!(f, "{}", text)
+                } else {
+                    write!(f, "{}.0", text)
+                }
+            }
+            Value::Integer(integer) => write!(f, "{}", integer),
+            Value::Color(r, g, b) => write!(f, "#{:02x}{:02x}{:02x}", r, g, b),
+            Value::Char(character) => write!(f, "'{}'", character),
+        }
+    }
+}
+
 impl Add for Value {
     type Output = Result<Value>;
 
@@ -249,6 +274,30 @@ impl Rem for Value {
 mod tests {
     use super::*;
 
+    #[test]
+    fn display() {
+        let values = [
+            Value::Address(42),
+            Value::Boolean(false),
+            Value::Float(-3.0),
+            Value::Float(0.125),
+            Value::Float(Float::INFINITY),
+            Value::Float(Float::NEG_INFINITY),
+            Value::Float(Float::NAN),
+            Value::Integer(-17),
+            Value::Color(0xab, 0x08, 0xff),
+            Value::Char('x'),
+        ];
+
+        // NaN never equals itself, so the debug output is compared instead
+        for value in &values {
+            assert_eq!(
+                format!("{:?}", value.to_string().parse::<Value>().unwrap()),
+                format!("{:?}", value)
+            );
+        }
+    }
+
     #[test]
     fn boolean_regex() {
         let input_boolean = "true";
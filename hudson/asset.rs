@@ -0,0 +1,835 @@
+//! Loading files into assets embedded by the `.asset` directive
+
+use core::{Asset, AssetKind, GLYPH_COUNT};
+use core::error::*;
+use image::{self, ImageFormat, ImageRgba8, Rgba, RgbaImage};
+use roxmltree::{Document, Node};
+use serde_json::{self, Value};
+use std::path::Path;
+use tracker::{Module, INSTRUMENT_RATE};
+
+/// The number of glyphs in a row of a font glyph sheet
+const GLYPH_SHEET_COLUMNS: u32 = 16;
+
+/// The file extensions of pictures loaded as sprites by default
+const SPRITE_EXTENSIONS: &[&str] = &["png", "jpg", "jpeg", "gif", "bmp"];
+
+/// The file extensions of Tiled maps loaded as tilemaps by default
+const TILEMAP_EXTENSIONS: &[&str] = &["tmx", "tmj"];
+
+/// The file extensions of sounds loaded as samples by default
+const SAMPLE_EXTENSIONS: &[&str] = &["wav"];
+
+/// The bits of a Tiled global tile id that flip or rotate the tile
+const TILED_FLIP_BITS: u32 = 0xf000_0000;
+
+/// Returns the kind of asset a file is loaded as, if no kind is given
+pub fn default_kind(path: &Path) -> AssetKind {
+    let extension = path.extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| extension.to_lowercase());
+
+    match extension {
+        Some(ref extension) if SPRITE_EXTENSIONS.contains(&extension.as_str()) => AssetKind::Sprite,
+        Some(ref extension) if TILEMAP_EXTENSIONS.contains(&extension.as_str()) => AssetKind::Tilemap,
+        Some(ref extension) if SAMPLE_EXTENSIONS.contains(&extension.as_str()) => AssetKind::Sample,
+        _ => AssetKind::Data,
+    }
+}
+
+/// Creates an asset of the given kind from the contents of a file. Tilesets
+/// need the size of their tiles like `16x16` as an option, while tilemaps
+/// optionally take the name of the layer to load. Samples are loaded from the
+/// instrument of a MOD file with the number given as an option.
+pub fn load(
+    name: &str, path: &Path, data: Vec<u8>, kind: AssetKind, option: Option<&str>
+) -> Result<Asset> {
+    ensure!(
+        option.is_none() || kind == AssetKind::Tileset || kind == AssetKind::Tilemap
+            || kind == AssetKind::Sample,
+        "{} assets take no further options",
+        kind
+    );
+
+    match kind {
+        AssetKind::Data => Ok(Asset::data(name.to_owned(), data)),
+        AssetKind::Sprite => {
+            let picture = decode_picture(path, &data)?;
+            let (width, height) = picture.dimensions();
+
+            Ok(Asset::sprite(
+                name.to_owned(),
+                width as usize,
+                height as usize,
+                picture.into_raw(),
+            ))
+        }
+        AssetKind::Font => load_font(name, path, &data),
+        AssetKind::Tileset => {
+            if let Some(tile_size) = option {
+                load_tileset(name, path, &data, tile_size)
+            } else {
+                bail!("the tileset {:?} needs the size of its tiles like 16x16", path);
+            }
+        }
+        AssetKind::Tilemap => load_tilemap(name, path, &data, option),
+        AssetKind::Sample => if let Some(instrument) = option {
+            load_instrument(name, path, &data, instrument)
+        } else {
+            load_sample(name, path, &data)
+        },
+    }
+}
+
+/// Creates a sample from an uncompressed WAV file with 8 or 16 bit samples.
+/// Multiple channels are mixed down to mono.
+pub fn load_sample(name: &str, path: &Path, data: &[u8]) -> Result<Asset> {
+    ensure!(
+        data.len() >= 12 && &data[..4] == b"RIFF" && &data[8..12] == b"WAVE",
+        "the sound {:?} is not a WAV file",
+        path
+    );
+
+    let mut format = None;
+    let mut samples = None;
+    let mut rest = &data[12..];
+
+    while rest.len() >= 8 {
+        let id = &rest[..4];
+        let len = read_u32(&rest[4..]) as usize;
+        let body = &rest[8..];
+
+        ensure!(len <= body.len(), "the sound {:?} is truncated", path);
+
+        match id {
+            b"fmt " if len >= 16 => format = Some(&body[..len]),
+            b"data" => samples = Some(&body[..len]),
+            _ => {}
+        }
+
+        // Chunks are padded to an even length
+        let next = (8 + len + len % 2).min(rest.len());
+        rest = &rest[next..];
+    }
+
+    let (format, samples) = match (format, samples) {
+        (Some(format), Some(samples)) => (format, samples),
+        _ => bail!("the sound {:?} lacks a format or a data chunk", path),
+    };
+
+    let encoding = read_u16(format);
+    let channels = read_u16(&format[2..]) as usize;
+    let rate = read_u32(&format[4..]) as usize;
+    let bits = read_u16(&format[14..]);
+
+    ensure!(
+        encoding == 1 && (bits == 8 || bits == 16) && channels > 0 && rate > 0,
+        "the sound {:?} must hold uncompressed 8 or 16 bit samples",
+        path
+    );
+
+    let frame_len = channels * bits as usize / 8;
+
+    let mono = samples
+        .chunks(frame_len)
+        .filter(|frame| frame.len() == frame_len)
+        .map(|frame| {
+            let sum: i32 = if bits == 8 {
+                frame.iter().map(|&sample| i32::from(sample) - 128).sum()
+            } else {
+                frame
+                    .chunks(2)
+                    .map(|sample| i32::from(read_u16(sample) as i16) >> 8)
+                    .sum()
+            };
+
+            (sum / channels as i32 + 128) as u8
+        })
+        .collect();
+
+    Ok(Asset::sample(name.to_owned(), rate, mono))
+}
+
+/// Creates a sample from an instrument of a MOD file, which plays at its
+/// native rate for the note C-2
+fn load_instrument(name: &str, path: &Path, data: &[u8], instrument: &str) -> Result<Asset> {
+    let number = instrument
+        .trim()
+        .parse()
+        .chain_err(|| format!("the instrument of a MOD file must be a number. Found {:?}", instrument))?;
+
+    let module = Module::parse(data).chain_err(|| format!("unable to read song {:?}", path))?;
+    let instrument = module.instrument(number)?;
+
+    ensure!(
+        !instrument.data.is_empty(),
+        "the instrument {} of the song {:?} holds no sample",
+        number,
+        path
+    );
+
+    // The samples are signed
+    let samples = instrument.data.iter().map(|&sample| (sample as u8) ^ 0x80).collect();
+
+    Ok(Asset::sample(name.to_owned(), INSTRUMENT_RATE, samples))
+}
+
+fn read_u16(bytes: &[u8]) -> u16 {
+    u16::from(bytes[0]) | u16::from(bytes[1]) << 8
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    u32::from(read_u16(bytes)) | u32::from(read_u16(&bytes[2..])) << 16
+}
+
+/// Creates a tileset by slicing a picture into tiles of the given size, row by
+/// row from the top left corner
+fn load_tileset(name: &str, path: &Path, data: &[u8], tile_size: &str) -> Result<Asset> {
+    let (tile_width, tile_height) = parse_tile_size(tile_size)?;
+
+    let picture = decode_picture(path, data)?;
+    let (width, height) = picture.dimensions();
+
+    ensure!(
+        width > 0 && height > 0 && width % tile_width == 0 && height % tile_height == 0,
+        "the tileset {:?} of size {}x{} can't be split into tiles of size {}x{}",
+        path,
+        width,
+        height,
+        tile_width,
+        tile_height
+    );
+
+    let mut pixels = Vec::with_capacity((width * height * 4) as usize);
+
+    for top in (0..height).step_by(tile_height as usize) {
+        for left in (0..width).step_by(tile_width as usize) {
+            for y in top..top + tile_height {
+                for x in left..left + tile_width {
+                    pixels.extend_from_slice(&picture.get_pixel(x, y).data);
+                }
+            }
+        }
+    }
+
+    Ok(Asset::tileset(
+        name.to_owned(),
+        tile_width as usize,
+        tile_height as usize,
+        pixels,
+    ))
+}
+
+/// Parses a tile size like `16x16`
+fn parse_tile_size(text: &str) -> Result<(u32, u32)> {
+    let mut parts = text.splitn(2, 'x');
+
+    let size = match (parts.next(), parts.next()) {
+        (Some(width), Some(height)) => (width.trim().parse::<u32>(), height.trim().parse::<u32>()),
+        _ => bail!("a tile size must look like 16x16. Found {:?}", text),
+    };
+
+    match size {
+        (Ok(width), Ok(height)) if width > 0 && height > 0 => Ok((width, height)),
+        _ => bail!("a tile size must look like 16x16. Found {:?}", text),
+    }
+}
+
+/// Creates a tilemap from a tile layer of a map made with the Tiled editor,
+/// saved either as XML (`.tmx`) or as JSON (`.tmj`). Without a layer name, the
+/// first tile layer is loaded.
+fn load_tilemap(name: &str, path: &Path, data: &[u8], layer: Option<&str>) -> Result<Asset> {
+    let is_xml = data.iter().find(|byte| !byte.is_ascii_whitespace()) == Some(&b'<');
+
+    let (width, height, gids, first_gid) = if is_xml {
+        parse_tmx(path, data, layer)?
+    } else {
+        parse_tmj(path, data, layer)?
+    };
+
+    ensure!(
+        Some(gids.len()) == width.checked_mul(height),
+        "the layer of the tilemap {:?} of size {}x{} holds {} tiles",
+        path,
+        width,
+        height,
+        gids.len()
+    );
+
+    let mut tiles = Vec::with_capacity(gids.len());
+
+    for gid in gids {
+        let gid = gid & !TILED_FLIP_BITS;
+
+        if gid == 0 {
+            tiles.push(0);
+            continue;
+        }
+
+        ensure!(
+            gid >= first_gid && gid - first_gid < u32::from(u16::MAX),
+            "the tilemap {:?} uses the tile {} which is not part of its tileset",
+            path,
+            gid
+        );
+
+        tiles.push((gid - first_gid + 1) as u16);
+    }
+
+    Ok(Asset::tilemap(name.to_owned(), width, height, &tiles))
+}
+
+/// The size of a tile layer, its global tile ids and the first global tile id
+/// of the tileset
+type TiledLayer = (usize, usize, Vec<u32>, u32);
+
+/// Reads a tile layer of a Tiled map in the XML format
+fn parse_tmx(path: &Path, data: &[u8], layer: Option<&str>) -> Result<TiledLayer> {
+    let text = ::std::str::from_utf8(data).chain_err(|| format!("the map {:?} is not UTF-8", path))?;
+    let document = Document::parse(text).chain_err(|| format!("unable to parse map {:?}", path))?;
+
+    let map = document.root_element();
+
+    let tilesets = elements(map, "tileset");
+    ensure!(tilesets.len() == 1, "the map {:?} must use exactly one tileset", path);
+
+    let first_gid = parse_attribute(path, tilesets[0], "firstgid")?;
+
+    let layers = elements(map, "layer");
+    let layer_node = *find_layer(path, &layers, layer, |node| node.attribute("name"))?;
+
+    let width = parse_attribute(path, layer_node, "width")? as usize;
+    let height = parse_attribute(path, layer_node, "height")? as usize;
+
+    let data_node = if let Some(&node) = elements(layer_node, "data").first() {
+        node
+    } else {
+        bail!("the layer of the map {:?} holds no data", path);
+    };
+
+    ensure!(
+        elements(data_node, "chunk").is_empty(),
+        "the map {:?} is infinite, which is not supported",
+        path
+    );
+
+    let gids = match data_node.attribute("encoding") {
+        Some("csv") => {
+            let mut gids = Vec::new();
+
+            for gid in data_node.text().unwrap_or("").split(',') {
+                gids.push(gid.trim()
+                    .parse()
+                    .chain_err(|| format!("invalid tile {:?} in the map {:?}", gid.trim(), path))?);
+            }
+
+            gids
+        }
+        None => {
+            let mut gids = Vec::new();
+
+            for tile in elements(data_node, "tile") {
+                gids.push(if tile.has_attribute("gid") {
+                    parse_attribute(path, tile, "gid")?
+                } else {
+                    0
+                });
+            }
+
+            gids
+        }
+        Some(encoding) => bail!(
+            "the map {:?} uses the {} encoding. Save it with the CSV encoding instead",
+            path,
+            encoding
+        ),
+    };
+
+    Ok((width, height, gids, first_gid))
+}
+
+/// Returns the child elements of a node with the given tag
+fn elements<'a, 'input>(node: Node<'a, 'input>, tag: &str) -> Vec<Node<'a, 'input>> {
+    node.children()
+        .filter(|child| child.is_element() && child.tag_name().name() == tag)
+        .collect()
+}
+
+fn parse_attribute(path: &Path, node: Node, name: &str) -> Result<u32> {
+    if let Some(Ok(value)) = node.attribute(name).map(str::parse) {
+        Ok(value)
+    } else {
+        bail!("the {} of a {} in the map {:?} must be a number", name, node.tag_name().name(), path);
+    }
+}
+
+/// Reads a tile layer of a Tiled map in the JSON format
+fn parse_tmj(path: &Path, data: &[u8], layer: Option<&str>) -> Result<TiledLayer> {
+    let map: Value = serde_json::from_slice(data).chain_err(|| format!("unable to parse map {:?}", path))?;
+
+    ensure!(
+        map.get("infinite").and_then(Value::as_bool) != Some(true),
+        "the map {:?} is infinite, which is not supported",
+        path
+    );
+
+    let tilesets = map.get("tilesets").and_then(Value::as_array);
+    let first_gid = match tilesets {
+        Some(tilesets) if tilesets.len() == 1 => tilesets[0].get("firstgid").and_then(Value::as_u64),
+        _ => bail!("the map {:?} must use exactly one tileset", path),
+    };
+
+    let first_gid = match first_gid {
+        Some(first_gid) if first_gid <= u64::from(u32::MAX) => first_gid as u32,
+        _ => bail!("the firstgid of the tileset in the map {:?} must be a number", path),
+    };
+
+    let layers: Vec<&Value> = map.get("layers")
+        .and_then(Value::as_array)
+        .map(|layers| {
+            layers
+                .iter()
+                .filter(|layer| layer.get("type").and_then(Value::as_str) == Some("tilelayer"))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let layer = find_layer(path, &layers, layer, |layer| {
+        layer.get("name").and_then(Value::as_str)
+    })?;
+
+    let size = |key: &str| -> Result<usize> {
+        if let Some(size) = layer.get(key).and_then(Value::as_u64) {
+            Ok(size as usize)
+        } else {
+            bail!("the {} of a layer in the map {:?} must be a number", key, path);
+        }
+    };
+
+    let (width, height) = (size("width")?, size("height")?);
+
+    let gids = match layer.get("data") {
+        Some(Value::Array(tiles)) => {
+            let mut gids = Vec::with_capacity(tiles.len());
+
+            for tile in tiles {
+                match tile.as_u64() {
+                    Some(gid) if gid <= u64::from(u32::MAX) => gids.push(gid as u32),
+                    _ => bail!("invalid tile {} in the map {:?}", tile, path),
+                }
+            }
+
+            gids
+        }
+        Some(Value::String(_)) => bail!(
+            "the map {:?} uses the base64 encoding. Save it with the CSV encoding instead",
+            path
+        ),
+        _ => bail!("the layer of the map {:?} holds no data", path),
+    };
+
+    Ok((width, height, gids, first_gid))
+}
+
+/// Returns the tile layer with the given name or the first one if no name is
+/// given
+fn find_layer<'a, T, F>(path: &Path, layers: &'a [T], name: Option<&str>, layer_name: F) -> Result<&'a T>
+where
+    F: Fn(&T) -> Option<&str>,
+{
+    let layer = if let Some(name) = name {
+        layers.iter().find(|&layer| layer_name(layer) == Some(name))
+    } else {
+        layers.first()
+    };
+
+    if let Some(layer) = layer {
+        Ok(layer)
+    } else if let Some(name) = name {
+        bail!("the map {:?} has no tile layer named {:?}", path, name);
+    } else {
+        bail!("the map {:?} has no tile layers", path);
+    }
+}
+
+/// Creates a font from a glyph sheet holding the glyphs from `' '` to
+/// `'\u{7f}'` in 16 columns and 6 rows. Opaque, bright pixels are set.
+fn load_font(name: &str, path: &Path, data: &[u8]) -> Result<Asset> {
+    let picture = decode_picture(path, data)?;
+    let (width, height) = picture.dimensions();
+
+    let rows = GLYPH_COUNT as u32 / GLYPH_SHEET_COLUMNS;
+
+    ensure!(
+        width > 0 && height > 0 && width % GLYPH_SHEET_COLUMNS == 0 && height % rows == 0,
+        "the glyph sheet {:?} of size {}x{} must hold {} columns and {} rows of glyphs",
+        path,
+        width,
+        height,
+        GLYPH_SHEET_COLUMNS,
+        rows
+    );
+
+    let glyph_width = width / GLYPH_SHEET_COLUMNS;
+    let glyph_height = height / rows;
+
+    let mut pixels = Vec::with_capacity((width * height) as usize);
+
+    for glyph in 0..GLYPH_COUNT as u32 {
+        let left = (glyph % GLYPH_SHEET_COLUMNS) * glyph_width;
+        let top = (glyph / GLYPH_SHEET_COLUMNS) * glyph_height;
+
+        for y in top..top + glyph_height {
+            for x in left..left + glyph_width {
+                let [r, g, b, a] = picture.get_pixel(x, y).data;
+                let set = a >= 128 && (r >= 128 || g >= 128 || b >= 128);
+
+                pixels.push(set as u8);
+            }
+        }
+    }
+
+    Ok(Asset::font(
+        name.to_owned(),
+        glyph_width as usize,
+        glyph_height as usize,
+        pixels,
+    ))
+}
+
+/// A file, that loads back into an asset using the given kind and option
+pub struct ExportedAsset {
+    pub extension: &'static str,
+    pub option: Option<String>,
+    pub data: Vec<u8>,
+}
+
+/// Turns an asset back into a file, that `load` turns into the same asset
+pub fn export(asset: &Asset) -> Result<ExportedAsset> {
+    ensure!(
+        asset.expected_len() == Some(asset.data.len()),
+        "the {} asset {:?} is malformed",
+        asset.kind,
+        asset.name
+    );
+
+    let (width, height) = (asset.width as u32, asset.height as u32);
+
+    let exported = |extension, option, data| {
+        Ok(ExportedAsset {
+            extension,
+            option,
+            data,
+        })
+    };
+
+    match asset.kind {
+        AssetKind::Data => exported("bin", None, asset.data.clone()),
+        AssetKind::Sprite => {
+            let picture = RgbaImage::from_raw(width, height, asset.data.clone());
+
+            exported("png", None, encode_picture(picture)?)
+        }
+        AssetKind::Font => {
+            let rows = GLYPH_COUNT as u32 / GLYPH_SHEET_COLUMNS;
+            let mut picture = RgbaImage::new(width * GLYPH_SHEET_COLUMNS, height * rows);
+
+            for (index, &set) in asset.data.iter().enumerate() {
+                let index = index as u32;
+                let glyph = index / (width * height);
+                let x = glyph % GLYPH_SHEET_COLUMNS * width + index % width;
+                let y = glyph / GLYPH_SHEET_COLUMNS * height + index / width % height;
+
+                if set != 0 {
+                    picture.put_pixel(x, y, Rgba { data: [255, 255, 255, 255] });
+                }
+            }
+
+            exported("png", None, encode_picture(Some(picture))?)
+        }
+        AssetKind::Tileset => {
+            // The tiles are stacked on top of each other
+            let tiles = asset.data.len() as u32 / (width * height * 4);
+
+            ensure!(tiles > 0, "the tileset {:?} holds no tiles", asset.name);
+
+            let picture = RgbaImage::from_raw(width, height * tiles, asset.data.clone());
+
+            exported("png", Some(format!("{}x{}", width, height)), encode_picture(picture)?)
+        }
+        AssetKind::Tilemap => {
+            // Tiles are numbered like global tile ids of a tileset starting at 1
+            let tiles: Vec<u16> = asset.data
+                .chunks(2)
+                .map(|tile| u16::from(tile[0]) | u16::from(tile[1]) << 8)
+                .collect();
+
+            let map = json!({
+                "width": asset.width,
+                "height": asset.height,
+                "tilesets": [{ "firstgid": 1 }],
+                "layers": [{
+                    "type": "tilelayer",
+                    "name": asset.name,
+                    "width": asset.width,
+                    "height": asset.height,
+                    "data": tiles,
+                }],
+            });
+
+            exported("tmj", None, map.to_string().into_bytes())
+        }
+        AssetKind::Sample => {
+            let mut wav = Vec::with_capacity(44 + asset.data.len());
+            let rate = width;
+            let len = asset.data.len() as u32;
+
+            wav.extend_from_slice(b"RIFF");
+            wav.extend_from_slice(&(36 + len + len % 2).to_le_bytes());
+            wav.extend_from_slice(b"WAVEfmt ");
+            wav.extend_from_slice(&16u32.to_le_bytes());
+            // Uncompressed, mono, 1 byte per frame, 8 bit
+            for &field in &[1u16, 1] {
+                wav.extend_from_slice(&field.to_le_bytes());
+            }
+            wav.extend_from_slice(&rate.to_le_bytes());
+            wav.extend_from_slice(&rate.to_le_bytes());
+            for &field in &[1u16, 8] {
+                wav.extend_from_slice(&field.to_le_bytes());
+            }
+            wav.extend_from_slice(b"data");
+            wav.extend_from_slice(&len.to_le_bytes());
+            wav.extend_from_slice(&asset.data);
+
+            // Chunks are padded to an even length
+            if len % 2 == 1 {
+                wav.push(0);
+            }
+
+            exported("wav", None, wav)
+        }
+    }
+}
+
+fn encode_picture(picture: Option<RgbaImage>) -> Result<Vec<u8>> {
+    let picture = if let Some(picture) = picture {
+        picture
+    } else {
+        bail!("unable to build picture");
+    };
+
+    let mut png = Vec::new();
+
+    ImageRgba8(picture)
+        .save(&mut png, ImageFormat::PNG)
+        .chain_err(|| "unable to encode picture")?;
+
+    Ok(png)
+}
+
+fn decode_picture(path: &Path, data: &[u8]) -> Result<RgbaImage> {
+    Ok(
+        image::load_from_memory(data)
+            .chain_err(|| format!("unable to decode picture {:?}", path))?
+            .to_rgba(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sprite() {
+        let mut picture = RgbaImage::new(3, 2);
+        picture.get_pixel_mut(2, 1).data = [10, 20, 30, 255];
+
+        let mut png = Vec::new();
+        ImageRgba8(picture)
+            .save(&mut png, ImageFormat::PNG)
+            .unwrap();
+
+        let path = Path::new("player.PNG");
+        assert_eq!(default_kind(path), AssetKind::Sprite);
+
+        let asset = load("player", path, png, default_kind(path), None).unwrap();
+
+        assert_eq!((asset.width, asset.height), (3, 2));
+        assert_eq!(&asset.data[20..24], &[10, 20, 30, 255]);
+        assert_eq!(asset.expected_len(), Some(asset.data.len()));
+
+        assert!(load("broken", path, vec![1, 2, 3], AssetKind::Sprite, None).is_err());
+    }
+
+    #[test]
+    fn font() {
+        // A sheet of 2x3 glyphs, where only the top left pixel of 'A' is set
+        let mut picture = RgbaImage::new(32, 18);
+        let glyph = 'A' as u32 - ' ' as u32;
+        let (x, y) = (glyph % 16 * 2, glyph / 16 * 3);
+        picture.get_pixel_mut(x, y).data = [255, 255, 255, 255];
+
+        let mut png = Vec::new();
+        ImageRgba8(picture)
+            .save(&mut png, ImageFormat::PNG)
+            .unwrap();
+
+        let path = Path::new("font.png");
+        let asset = load("font", path, png.clone(), AssetKind::Font, None).unwrap();
+
+        assert_eq!((asset.width, asset.height), (2, 3));
+        assert_eq!(asset.expected_len(), Some(asset.data.len()));
+        assert_eq!(asset.data.iter().filter(|&&pixel| pixel == 1).count(), 1);
+        assert_eq!(asset.data[glyph as usize * 6], 1);
+
+        let mut png = Vec::new();
+        ImageRgba8(RgbaImage::new(30, 18))
+            .save(&mut png, ImageFormat::PNG)
+            .unwrap();
+
+        assert!(load("font", path, png, AssetKind::Font, None).is_err());
+    }
+
+    #[test]
+    fn data() {
+        let path = Path::new("level.bin");
+        assert_eq!(default_kind(path), AssetKind::Data);
+
+        let asset = load("level", path, vec![1, 2, 3], AssetKind::Data, None).unwrap();
+
+        assert_eq!((asset.width, asset.height), (3, 1));
+    }
+
+    #[test]
+    fn sample() {
+        // A stereo sound with two 16 bit frames
+        let mut wav = b"RIFF\0\0\0\0WAVEfmt ".to_vec();
+        wav.extend_from_slice(&[16, 0, 0, 0, 1, 0, 2, 0, 0x11, 0x2b, 0, 0, 0x44, 0xac, 0, 0, 4, 0, 16, 0]);
+        wav.extend_from_slice(b"LIST\x01\0\0\0\0\0data\x08\0\0\0");
+        wav.extend_from_slice(&[0x00, 0x40, 0x00, 0x40, 0x00, 0x80, 0x00, 0x00]);
+
+        let path = Path::new("jump.wav");
+        assert_eq!(default_kind(path), AssetKind::Sample);
+
+        let asset = load("jump", path, wav.clone(), default_kind(path), None).unwrap();
+
+        assert_eq!((asset.width, asset.height), (11025, 1));
+        assert_eq!(asset.data, vec![192, 64]);
+        assert_eq!(asset.expected_len(), Some(asset.data.len()));
+
+        wav[20] = 3;
+        assert!(load("jump", path, wav, AssetKind::Sample, None).is_err());
+        assert!(load("jump", path, b"RIFF".to_vec(), AssetKind::Sample, None).is_err());
+    }
+
+    #[test]
+    fn instrument() {
+        let path = Path::new("song.mod");
+        let song = ::tracker::tests::build_mod(&[]);
+
+        let asset = load("lead", path, song.clone(), AssetKind::Sample, Some("1")).unwrap();
+
+        assert_eq!((asset.width, asset.height), (INSTRUMENT_RATE, 1));
+        assert_eq!(asset.data, vec![128, 192, 0, 127]);
+
+        assert!(load("lead", path, song.clone(), AssetKind::Sample, Some("2")).is_err());
+        assert!(load("lead", path, song.clone(), AssetKind::Sample, Some("lead")).is_err());
+        assert!(load("lead", path, song, AssetKind::Sample, None).is_err());
+    }
+
+    #[test]
+    fn tileset() {
+        // Two rows of two 2x1 tiles, where only the third tile is colored
+        let mut picture = RgbaImage::new(4, 2);
+        picture.get_pixel_mut(0, 1).data = [1, 2, 3, 255];
+        picture.get_pixel_mut(1, 1).data = [4, 5, 6, 255];
+
+        let mut png = Vec::new();
+        ImageRgba8(picture)
+            .save(&mut png, ImageFormat::PNG)
+            .unwrap();
+
+        let path = Path::new("tiles.png");
+        let asset = load("tiles", path, png.clone(), AssetKind::Tileset, Some("2x1")).unwrap();
+
+        assert_eq!((asset.width, asset.height), (2, 1));
+        assert_eq!(asset.data.len(), 4 * 2 * 4);
+        assert_eq!(asset.expected_len(), Some(asset.data.len()));
+        assert_eq!(&asset.data[16..24], &[1, 2, 3, 255, 4, 5, 6, 255]);
+
+        assert!(load("tiles", path, png.clone(), AssetKind::Tileset, None).is_err());
+        assert!(load("tiles", path, png.clone(), AssetKind::Tileset, Some("3x1")).is_err());
+        assert!(load("tiles", path, png.clone(), AssetKind::Tileset, Some("0x1")).is_err());
+        assert!(load("tiles", path, png, AssetKind::Sprite, Some("2x1")).is_err());
+    }
+
+    #[test]
+    fn tiled_xml() {
+        let map = r#"<?xml version="1.0" encoding="UTF-8"?>
+            <map version="1.2" orientation="orthogonal" width="3" height="1" tilewidth="16" tileheight="16">
+             <tileset firstgid="5" source="tiles.tsx"/>
+             <layer id="1" name="Background" width="3" height="1">
+              <data encoding="csv">
+            5,0,
+            2147483654
+            </data>
+             </layer>
+             <layer id="2" name="Walls" width="3" height="1">
+              <data>
+               <tile gid="7"/>
+               <tile/>
+               <tile gid="5"/>
+              </data>
+             </layer>
+            </map>"#;
+
+        let path = Path::new("level.tmx");
+        assert_eq!(default_kind(path), AssetKind::Tilemap);
+
+        let asset = load("level", path, map.into(), AssetKind::Tilemap, None).unwrap();
+
+        assert_eq!((asset.width, asset.height), (3, 1));
+        assert_eq!(asset.tiles(), vec![1, 0, 2]);
+
+        let asset = load("level", path, map.into(), AssetKind::Tilemap, Some("Walls")).unwrap();
+        assert_eq!(asset.tiles(), vec![3, 0, 1]);
+
+        assert!(load("level", path, map.into(), AssetKind::Tilemap, Some("Sky")).is_err());
+
+        let base64 = map.replace("encoding=\"csv\"", "encoding=\"base64\"");
+        let error = load("level", path, base64.into(), AssetKind::Tilemap, None).unwrap_err();
+        assert!(error.to_string().contains("base64"));
+
+        let foreign = map.replace("firstgid=\"5\"", "firstgid=\"6\"");
+        assert!(load("level", path, foreign.into(), AssetKind::Tilemap, None).is_err());
+    }
+
+    #[test]
+    fn tiled_json() {
+        let map = r#"{
+            "width": 2,
+            "height": 2,
+            "infinite": false,
+            "tilesets": [{ "firstgid": 1, "source": "tiles.tsj" }],
+            "layers": [
+                { "type": "objectgroup", "name": "Objects", "objects": [] },
+                { "type": "tilelayer", "name": "Ground", "width": 2, "height": 2, "data": [1, 2, 0, 3] }
+            ]
+        }"#;
+
+        let path = Path::new("level.tmj");
+        assert_eq!(default_kind(path), AssetKind::Tilemap);
+
+        let asset = load("level", path, map.into(), AssetKind::Tilemap, None).unwrap();
+
+        assert_eq!((asset.width, asset.height), (2, 2));
+        assert_eq!(asset.tiles(), vec![1, 2, 0, 3]);
+        assert_eq!(asset.expected_len(), Some(asset.data.len()));
+
+        let infinite = map.replace("\"infinite\": false", "\"infinite\": true");
+        assert!(load("level", path, infinite.into(), AssetKind::Tilemap, None).is_err());
+
+        let short = map.replace("[1, 2, 0, 3]", "[1, 2, 0]");
+        assert!(load("level", path, short.into(), AssetKind::Tilemap, None).is_err());
+    }
+}
@@ -1,38 +1,226 @@
-use core::ImageBuilder;
+use asset;
+use core::{Asset, AssetKind, BlitOptions, Button, Config, DebugInfo, DisplayResolution, ImageBuilder,
+           Program, Signal, Target, Type, Value};
 use core::error::*;
 use core::typedef::*;
+use expr;
 use mnemonic::Mnemonic;
 use regex::Regex;
 use std::collections::{HashMap, HashSet};
+use std::collections::hash_map::{DefaultHasher, Entry};
 use std::env;
 use std::fs::File;
-use std::io::BufReader;
+use std::hash::{Hash, Hasher};
 use std::io::prelude::*;
+use std::mem;
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
 lazy_static! {
     static ref LABELED_MNEMONIC_RE: Regex = Regex::new(r"^\.(.+?) +?(.+)$").unwrap();
     static ref LABEL_RE: Regex = Regex::new(r"^\.([^\s]+)$").unwrap();
     static ref INCLUDE_RE: Regex = Regex::new(r"^include! +([^\s]+)$").unwrap();
+    static ref MACRO_RE: Regex = Regex::new(r"^macro +([^\s(!]+) *\((.*)\)$").unwrap();
+    static ref ENDMACRO_RE: Regex = Regex::new(r"^endmacro$").unwrap();
+    static ref MACRO_CALL_RE: Regex = Regex::new(r"^([^\s(!]+)!\((.*)\)$").unwrap();
+    static ref SYMBOL_RE: Regex =
+        Regex::new(r"^\.(equ|define|local) +([A-Za-z_]\w*)(?: *= *| +)(.+)$").unwrap();
+    static ref INDEXED_TARGET_RE: Regex = Regex::new(r"^\$(vi|key|fb|btn)\((.+)\)$").unwrap();
+    static ref CONFIG_RE: Regex =
+        Regex::new(r"^\.(title|resolution|scale|hide_cursor|color_mode|input_enabled|map_button) +(.+)$").unwrap();
+    static ref CONDITION_RE: Regex = Regex::new(r"^\.(if|ifdef|ifndef) +(.+)$").unwrap();
+    static ref ELSE_RE: Regex = Regex::new(r"^\.else$").unwrap();
+    static ref ENDIF_RE: Regex = Regex::new(r"^\.endif$").unwrap();
+    static ref DEFINE_RE: Regex = Regex::new(r"^([A-Za-z_]\w*)(?:=(.*))?$").unwrap();
+    static ref ASSET_RE: Regex = Regex::new(r"^\.asset +([A-Za-z_]\w*) +(.+)$").unwrap();
+}
+
+const BASM_EXTENSION: &str = "basm";
+
+/// The environment variable overriding the location of the bundled library
+const LIBRARY_PATH_VAR: &str = "BAKERVM_LIB_PATH";
+
+/// The name of the directory of the bundled library
+const LIBRARY_NAME: &str = "std";
+
+/// The maximum depth of nested macro expansions before the compiler assumes a
+/// recursive macro
+const MAX_MACRO_DEPTH: usize = 64;
+
+/// The maximum number of substitution passes before the compiler assumes a
+/// recursive symbol definition
+const MAX_SUBSTITUTION_DEPTH: usize = 32;
+
+/// A constant defined from outside the source code, e.g. `-D DEBUG=1`
+#[derive(Clone, Debug)]
+pub struct Define {
+    pub name: String,
+    pub value: String,
+}
+
+impl FromStr for Define {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> ::std::result::Result<Self, Self::Err> {
+        if let Some(captures) = DEFINE_RE.captures(s) {
+            Ok(Define {
+                name: captures[1].to_owned(),
+                value: captures.get(2).map_or("1", |value| value.as_str()).to_owned(),
+            })
+        } else {
+            Err("unable to parse define. Defines must look like NAME or NAME=VALUE")
+        }
+    }
+}
+
+/// Options for compiling a BASM file
+#[derive(Clone, Debug, Default)]
+pub struct Options {
+    pub defines: Vec<Define>,
+    pub include_paths: Vec<PathBuf>,
+    /// Adds the names of all labels to the image
+    pub debug: bool,
+}
+
+/// The state of a single `.if` block
+#[derive(Clone, Debug, Hash)]
+struct Condition {
+    /// Whether the lines of the current branch are compiled
+    active: bool,
+    /// Whether any branch of the block has been taken already
+    taken: bool,
+    /// Whether the enclosing block is active
+    parent_active: bool,
+    has_else: bool,
+}
+
+#[derive(Clone, Debug)]
+struct Macro {
+    params: Vec<String>,
+    body: Vec<String>,
+}
+
+/// The compiler state a file may read or change. A file compiled with the
+/// same source and the same state always has the same result.
+#[derive(Clone, Debug, Default)]
+struct FileState {
+    symbols: HashMap<String, String>,
+    macros: HashMap<String, Macro>,
+    compiled_files: HashSet<String>,
+    expansions: usize,
+    config: Config,
+    assets: Vec<Asset>,
+}
+
+/// The cached result of compiling a single file, including all files it
+/// included
+#[derive(Clone, Debug)]
+struct CacheEntry {
+    /// The fingerprint of the compiler state before compiling the file
+    fingerprint: u64,
+    /// All files read while compiling the file and the hashes of their
+    /// sources, beginning with the file itself
+    files: Vec<(PathBuf, u64)>,
+    mnemonics: Vec<Mnemonic>,
+    /// The labels defined by the file, relative to its first mnemonic
+    labels: Vec<(String, Address)>,
+    state: FileState,
+}
+
+/// Keeps the mnemonics of compiled files between compilations, so only files
+/// that changed have to be parsed again
+#[derive(Debug, Default)]
+pub struct Cache {
+    entries: HashMap<String, CacheEntry>,
+    files: Vec<PathBuf>,
+    reused: usize,
+}
+
+impl Cache {
+    /// The files read during the last compilation
+    pub fn files(&self) -> &[PathBuf] {
+        &self.files
+    }
+
+    /// The number of files taken from the cache during the last compilation
+    pub fn reused(&self) -> usize {
+        self.reused
+    }
+
+    /// Takes the entry of the given file out of the cache if it is still valid
+    /// for the given state
+    fn take(&mut self, path: &str, fingerprint: u64, hash: u64) -> Option<CacheEntry> {
+        let entry = self.entries.remove(path)?;
+
+        if entry.fingerprint != fingerprint || entry.files[0].1 != hash {
+            return None;
+        }
+
+        for &(ref path, hash) in &entry.files[1..] {
+            let mut contents = Vec::new();
+
+            let unchanged = match File::open(path) {
+                Ok(mut file) => {
+                    file.read_to_end(&mut contents).is_ok() && hash_contents(&contents) == hash
+                }
+                Err(_) => false,
+            };
+
+            if !unchanged {
+                return None;
+            }
+        }
+
+        Some(entry)
+    }
 }
 
 #[derive(Default)]
 struct BASMCompiler {
     label_addr_map: HashMap<String, Address>,
+    /// The labels in the order of their definition
+    labels: Vec<String>,
     mnemonics: Vec<Mnemonic>,
     builder: ImageBuilder,
     compiled_files: HashSet<String>,
     deep: usize,
+    macros: HashMap<String, Macro>,
+    /// The name and definition of the macro that is currently being defined
+    recording: Option<(String, Macro)>,
+    expansions: usize,
+    macro_depth: usize,
+    /// Constants and value-index aliases, mapped to the text they stand for
+    symbols: HashMap<String, String>,
+    /// The stack of currently open `.if` blocks
+    conditions: Vec<Condition>,
+    /// Directories searched for included files
+    include_paths: Vec<PathBuf>,
+    /// The files and line numbers of the `include!` statements that led to
+    /// the file currently being compiled
+    include_chain: Vec<(PathBuf, usize)>,
+    line_number: usize,
+    /// The program configuration set by directives like `.title`
+    config: Config,
+    /// The assets embedded by `.asset` directives
+    assets: Vec<Asset>,
+    cache: Cache,
+    /// The files read so far and the hashes of their sources
+    read_files: Vec<(PathBuf, u64)>,
 }
 
 impl BASMCompiler {
     fn add_label(&mut self, label: String) -> Result<()> {
+        let addr = self.mnemonics.len();
+
+        self.insert_label(label, addr)
+    }
+
+    fn insert_label(&mut self, label: String, addr: Address) -> Result<()> {
         if self.label_addr_map.contains_key(&label) {
             bail!("label {:?} already exists", label)
         } else {
-            self.label_addr_map
-                .entry(label)
-                .or_insert(self.mnemonics.len());
+            self.labels.push(label.clone());
+            self.label_addr_map.entry(label).or_insert(addr);
 
             Ok(())
         }
@@ -50,116 +238,623 @@ impl BASMCompiler {
 
         if self.compiled_files.contains(&path_string) {
             return Ok(());
-        } else {
-            self.compiled_files.insert(path_string);
         }
 
-        let file = File::open(orig_path).chain_err(|| "unable to open file")?;
+        let fingerprint = self.fingerprint();
 
-        let reader = BufReader::new(file);
+        self.compiled_files.insert(path_string.clone());
 
-        for line in reader.lines() {
-            let line = line.chain_err(|| "unable to read line")?;
+        let mut file = File::open(orig_path).chain_err(|| "unable to open file")?;
 
-            if line.is_empty() {
-                continue;
-            }
+        let mut source = String::new();
 
-            let mut r_split = line.rsplitn(2, ';');
+        file.read_to_string(&mut source)
+            .chain_err(|| "unable to read file")?;
 
-            if r_split.clone().count() > 1 {
-                r_split.next();
-            }
+        let hash = hash_contents(source.as_bytes());
+
+        if let Some(entry) = self.cache.take(&path_string, fingerprint, hash) {
+            self.replay(&entry)?;
+            self.cache.entries.insert(path_string, entry);
+            self.cache.reused += 1;
 
-            if let Some(first_half) = r_split.next() {
-                let first_half = first_half.trim();
+            return Ok(());
+        }
+
+        let first_mnemonic = self.mnemonics.len();
+        let first_label = self.labels.len();
+        let first_file = self.read_files.len();
+
+        self.read_files.push((orig_path.to_path_buf(), hash));
 
-                if first_half.is_empty() {
-                    continue;
+        self.compile_source(&source, orig_path)?;
+
+        let labels = self.labels[first_label..]
+            .iter()
+            .map(|label| (label.clone(), self.label_addr_map[label] - first_mnemonic))
+            .collect();
+
+        let entry = CacheEntry {
+            fingerprint,
+            files: self.read_files[first_file..].to_vec(),
+            mnemonics: self.mnemonics[first_mnemonic..].to_vec(),
+            labels,
+            state: self.file_state(),
+        };
+
+        self.cache.entries.insert(path_string, entry);
+
+        Ok(())
+    }
+
+    /// Applies the cached result of compiling a file
+    fn replay(&mut self, entry: &CacheEntry) -> Result<()> {
+        let first_mnemonic = self.mnemonics.len();
+
+        for &(ref label, offset) in &entry.labels {
+            self.insert_label(label.clone(), first_mnemonic + offset)?;
+        }
+
+        self.mnemonics.extend(entry.mnemonics.iter().cloned());
+        self.read_files.extend(entry.files.iter().cloned());
+
+        let state = entry.state.clone();
+
+        self.symbols = state.symbols;
+        self.macros = state.macros;
+        self.compiled_files = state.compiled_files;
+        self.expansions = state.expansions;
+        self.config = state.config;
+        self.assets = state.assets;
+
+        Ok(())
+    }
+
+    fn file_state(&self) -> FileState {
+        FileState {
+            symbols: self.symbols.clone(),
+            macros: self.macros.clone(),
+            compiled_files: self.compiled_files.clone(),
+            expansions: self.expansions,
+            config: self.config.clone(),
+            assets: self.assets.clone(),
+        }
+    }
+
+    /// Hashes everything that may influence the result of compiling a file,
+    /// except for the file itself
+    fn fingerprint(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+
+        let mut symbols: Vec<_> = self.symbols.iter().collect();
+        symbols.sort();
+        symbols.hash(&mut hasher);
+
+        let mut macros: Vec<_> = self.macros
+            .iter()
+            .map(|(name, definition)| (name, &definition.params, &definition.body))
+            .collect();
+        macros.sort();
+        macros.hash(&mut hasher);
+
+        let mut compiled_files: Vec<_> = self.compiled_files.iter().collect();
+        compiled_files.sort();
+        compiled_files.hash(&mut hasher);
+
+        self.expansions.hash(&mut hasher);
+        self.macro_depth.hash(&mut hasher);
+        self.conditions.hash(&mut hasher);
+        self.include_paths.hash(&mut hasher);
+        format!("{:?}", self.config).hash(&mut hasher);
+
+        // The contents of the assets are covered by the files they were read
+        // from
+        for asset in &self.assets {
+            asset.name.hash(&mut hasher);
+        }
+
+        hasher.finish()
+    }
+
+    fn compile_source(&mut self, source: &str, orig_path: &Path) -> Result<()> {
+        let open_conditions = self.conditions.len();
+
+        for (index, line) in source.lines().enumerate() {
+            self.line_number = index + 1;
+            self.compile_line(line, orig_path)?;
+        }
+
+        if self.recording.is_none() && self.conditions.len() > open_conditions {
+            bail!("missing .endif in {:?}", orig_path);
+        }
+
+        if let Some((ref name, _)) = self.recording {
+            bail!("macro {:?} is missing its endmacro", name);
+        }
+
+        Ok(())
+    }
+
+    fn compile_line(&mut self, line: &str, orig_path: &Path) -> Result<()> {
+        if line.is_empty() {
+            return Ok(());
+        }
+
+        let first_half = strip_comment(line).trim();
+
+        if first_half.is_empty() {
+            return Ok(());
+        }
+
+        if self.recording.is_some() {
+            return self.record_macro_line(first_half);
+        }
+
+        if self.compile_condition(first_half)? || !self.is_active() {
+            return Ok(());
+        }
+
+        let first_half: String = if CONFIG_RE.is_match(first_half) {
+            let captures = if let Some(captures) = CONFIG_RE.captures_iter(first_half).next() {
+                captures
+            } else {
+                bail!("no config capture found")
+            };
+
+            return self.compile_config(&captures[1], captures[2].trim());
+        } else if ASSET_RE.is_match(first_half) {
+            let captures = if let Some(captures) = ASSET_RE.captures_iter(first_half).next() {
+                captures
+            } else {
+                bail!("no asset capture found")
+            };
+
+            return self.compile_asset(&captures[1], captures[2].trim(), orig_path);
+        } else if SYMBOL_RE.is_match(first_half) {
+            let captures = if let Some(captures) = SYMBOL_RE.captures_iter(first_half).next() {
+                captures
+            } else {
+                bail!("no symbol capture found")
+            };
+
+            let value = captures[3].trim();
+
+            let value = match &captures[1] {
+                "equ" => expr::eval(&self.resolve_symbols(value)?)?.to_string(),
+                "local" => {
+                    let index = expr::eval(&self.resolve_symbols(value)?)?.as_integer();
+
+                    ensure!(index >= 0, "value-index {} must not be negative", index);
+
+                    format!("$vi({})", index)
                 }
+                _ => value.to_owned(),
+            };
 
-                let first_half: String = if LABELED_MNEMONIC_RE.is_match(first_half) {
-                    let captures = if let Some(captures) =
-                        LABELED_MNEMONIC_RE.captures_iter(first_half).next()
-                    {
-                        captures
-                    } else {
-                        bail!("no label capture found")
-                    };
+            self.add_symbol(captures[2].to_owned(), value)?;
 
-                    let label = captures[1].trim();
+            return Ok(());
+        } else if MACRO_RE.is_match(first_half) {
+            let captures = if let Some(captures) = MACRO_RE.captures_iter(first_half).next() {
+                captures
+            } else {
+                bail!("no macro capture found")
+            };
 
-                    self.add_label(label.to_owned())?;
+            let params = split_args(&captures[2]);
 
-                    captures[2].trim().to_owned()
-                } else if LABEL_RE.is_match(first_half) {
-                    let captures = if let Some(captures) = LABEL_RE.captures_iter(first_half).next()
-                    {
-                        captures
-                    } else {
-                        bail!("no label capture found")
-                    };
+            for (index, param) in params.iter().enumerate() {
+                if params[..index].contains(param) {
+                    bail!("parameter {:?} of macro {:?} is declared twice", param, &captures[1]);
+                }
+            }
 
-                    let label = captures[1].trim();
+            self.recording = Some((
+                captures[1].to_owned(),
+                Macro {
+                    params,
+                    body: Vec::new(),
+                },
+            ));
 
-                    self.add_label(label.to_owned())?;
-                    continue;
-                } else if INCLUDE_RE.is_match(first_half) {
-                    let captures =
-                        if let Some(captures) = INCLUDE_RE.captures_iter(first_half).next() {
-                            captures
-                        } else {
-                            bail!("no include capture found")
-                        };
+            return Ok(());
+        } else if ENDMACRO_RE.is_match(first_half) {
+            bail!("endmacro without a matching macro definition");
+        } else if LABELED_MNEMONIC_RE.is_match(first_half) {
+            let captures = if let Some(captures) =
+                LABELED_MNEMONIC_RE.captures_iter(first_half).next()
+            {
+                captures
+            } else {
+                bail!("no label capture found")
+            };
 
-                    self.deep += 1;
+            let label = captures[1].trim();
 
-                    let parent = if let Some(ref parent) = orig_path.parent() {
-                        parent.to_path_buf().clone()
-                    } else {
-                        bail!("unable to get parent directory")
-                    };
+            self.add_label(label.to_owned())?;
 
-                    env::set_current_dir(parent.clone())
-                        .chain_err(|| "unable to switch directories")?;
+            captures[2].trim().to_owned()
+        } else if LABEL_RE.is_match(first_half) {
+            let captures = if let Some(captures) = LABEL_RE.captures_iter(first_half).next() {
+                captures
+            } else {
+                bail!("no label capture found")
+            };
 
-                    let path = Path::new(&(captures[1].trim().to_owned() + ".basm"))
-                        .canonicalize()
-                        .chain_err(|| "unable to canonicalize path")?;
+            let label = captures[1].trim();
 
-                    self.compile_mnemonics(&path)?;
+            self.add_label(label.to_owned())?;
+            return Ok(());
+        } else if INCLUDE_RE.is_match(first_half) {
+            let captures = if let Some(captures) = INCLUDE_RE.captures_iter(first_half).next() {
+                captures
+            } else {
+                bail!("no include capture found")
+            };
 
-                    env::set_current_dir(parent).chain_err(|| "unable to switch directories")?;
-                    self.deep -= 1;
+            let line_number = self.line_number;
+            self.include_chain.push((orig_path.to_path_buf(), line_number));
 
-                    continue;
-                } else {
-                    first_half.to_owned()
-                };
+            let path = self.find_include(captures[1].trim(), orig_path)?;
+
+            self.deep += 1;
+            self.compile_mnemonics(&path)?;
+            self.deep -= 1;
+
+            self.include_chain.pop();
+            self.line_number = line_number;
 
-                let mut first_half_split = first_half.splitn(2, ' ');
+            return Ok(());
+        } else {
+            first_half.to_owned()
+        };
+
+        if MACRO_CALL_RE.is_match(&first_half) {
+            let captures = if let Some(captures) = MACRO_CALL_RE.captures_iter(&first_half).next()
+            {
+                captures
+            } else {
+                bail!("no macro call capture found")
+            };
+
+            return self.expand_macro(&captures[1], split_args(&captures[2]), orig_path);
+        }
 
-                if let Some(opcode) = first_half_split.next() {
-                    let opcode = opcode.trim().to_lowercase();
+        let mut first_half_split = first_half.splitn(2, ' ');
 
-                    let args: Vec<String> = if let Some(args) = first_half_split.next() {
-                        args.split(',').map(|arg| arg.trim().to_owned()).collect()
+        if let Some(opcode) = first_half_split.next() {
+            let opcode = opcode.trim().to_lowercase();
+
+            let mut args: Vec<String> = Vec::new();
+
+            if let Some(text) = first_half_split.next() {
+                for arg in split_unquoted(text, ',') {
+                    let arg = if is_label_opcode(&opcode) {
+                        self.resolve_symbols(arg.trim())?
                     } else {
-                        Vec::new()
+                        self.resolve_operand(arg.trim())?
                     };
 
-                    self.mnemonics.push(text_to_mnemonic(opcode, args)?);
-                } else {
-                    bail!("opcode expected. Found {:?}", first_half_split);
+                    args.push(arg);
                 }
+            }
+
+            self.mnemonics.push(text_to_mnemonic(opcode, args)?);
+        } else {
+            bail!("opcode expected. Found {:?}", first_half_split);
+        }
+
+        Ok(())
+    }
+
+    /// Applies a configuration directive like `.title "My Game"` to the
+    /// program configuration
+    fn compile_config(&mut self, key: &str, value: &str) -> Result<()> {
+        let value = if value.starts_with('"') {
+            value.to_owned()
+        } else {
+            self.resolve_symbols(value)?
+        };
+
+        apply_config(&mut self.config, key, &value)
+    }
+
+    /// Embeds the file of an `.asset` directive like
+    /// `.asset player "player.png", sprite` and defines the name of the asset
+    /// as a symbol for its index
+    fn compile_asset(&mut self, name: &str, args: &str, orig_path: &Path) -> Result<()> {
+        let args = split_args(args);
+
+        ensure!(
+            !args.is_empty() && args.len() <= 3,
+            ".asset expects a file name, an optional kind and an optional kind specific option"
+        );
+
+        let file_name = parse_string(&args[0])?;
+
+        let path = orig_path
+            .parent()
+            .map_or_else(|| PathBuf::from(&file_name), |dir| dir.join(&file_name));
+
+        let kind = if let Some(kind) = args.get(1) {
+            kind.parse::<AssetKind>()?
+        } else {
+            asset::default_kind(&path)
+        };
+
+        let mut data = Vec::new();
+
+        File::open(&path)
+            .and_then(|mut file| file.read_to_end(&mut data))
+            .chain_err(|| format!("unable to read asset {:?}", path))?;
+
+        self.read_files.push((path.clone(), hash_contents(&data)));
+
+        let option = match args.get(2) {
+            Some(option) if option.starts_with('"') => Some(parse_string(option)?),
+            Some(option) => Some(option.to_owned()),
+            None => None,
+        };
+
+        let asset = asset::load(name, &path, data, kind, option.as_deref())?;
+
+        self.add_symbol(name.to_owned(), format!("@{}", self.assets.len()))?;
+        self.assets.push(asset);
+
+        Ok(())
+    }
+
+    /// Searches the file of an `include!` statement relative to the including
+    /// file, then in the include paths and finally in the bundled library
+    fn find_include(&self, name: &str, orig_path: &Path) -> Result<PathBuf> {
+        let file_name = format!("{}.{}", name, BASM_EXTENSION);
+
+        let mut search_paths = Vec::new();
+
+        if let Some(parent) = orig_path.parent() {
+            search_paths.push(parent.to_path_buf());
+        }
+
+        search_paths.extend(self.include_paths.iter().cloned());
+        search_paths.extend(bundled_library_path());
+
+        for search_path in &search_paths {
+            let candidate = search_path.join(&file_name);
+
+            if candidate.is_file() {
+                return candidate
+                    .canonicalize()
+                    .chain_err(|| format!("unable to canonicalize path {:?}", candidate));
+            }
+        }
+
+        let mut message = format!("unable to find {:?}. Searched in:", file_name);
+
+        for search_path in &search_paths {
+            message += &format!("\n    {}", search_path.display());
+        }
+
+        for &(ref path, line_number) in self.include_chain.iter().rev() {
+            message += &format!("\n  included from {}:{}", path.display(), line_number);
+        }
+
+        bail!(message)
+    }
+
+    /// Returns true if lines are currently compiled, i.e. they are not inside
+    /// a skipped conditional block
+    fn is_active(&self) -> bool {
+        match self.conditions.last() {
+            Some(condition) => condition.active,
+            None => true,
+        }
+    }
+
+    /// Handles conditional assembly directives. Returns true if the given line
+    /// was a directive.
+    fn compile_condition(&mut self, line: &str) -> Result<bool> {
+        if let Some(captures) = CONDITION_RE.captures(line) {
+            let parent_active = self.is_active();
+
+            // Conditions inside skipped blocks are never evaluated, so they may
+            // refer to symbols that don't exist
+            let met = parent_active &&
+                match &captures[1] {
+                    "ifdef" => self.symbols.contains_key(captures[2].trim()),
+                    "ifndef" => !self.symbols.contains_key(captures[2].trim()),
+                    _ => {
+                        let condition = self.resolve_symbols(captures[2].trim())?;
+
+                        expr::eval(&condition)
+                            .chain_err(|| format!("unable to evaluate condition {:?}", condition))?
+                            .is_true()
+                    }
+                };
+
+            self.conditions.push(Condition {
+                active: met,
+                taken: met,
+                parent_active,
+                has_else: false,
+            });
+        } else if ELSE_RE.is_match(line) {
+            let condition = if let Some(condition) = self.conditions.last_mut() {
+                condition
             } else {
-                bail!("instruction expected. Found {:?}", line.to_string());
+                bail!(".else without a matching .if");
+            };
+
+            ensure!(!condition.has_else, "duplicate .else in conditional block");
+
+            condition.has_else = true;
+            condition.active = condition.parent_active && !condition.taken;
+            condition.taken = true;
+        } else if ENDIF_RE.is_match(line) {
+            if self.conditions.pop().is_none() {
+                bail!(".endif without a matching .if");
+            }
+        } else {
+            return Ok(false);
+        }
+
+        Ok(true)
+    }
+
+    fn add_symbol(&mut self, name: String, value: String) -> Result<()> {
+        match self.symbols.entry(name) {
+            Entry::Occupied(entry) => bail!("symbol {:?} already exists", entry.key()),
+            Entry::Vacant(entry) => {
+                entry.insert(value);
+
+                Ok(())
+            }
+        }
+    }
+
+    /// Substitutes all known symbols in the given text
+    fn resolve_symbols(&self, text: &str) -> Result<String> {
+        let mut text = text.to_owned();
+
+        for _ in 0..MAX_SUBSTITUTION_DEPTH {
+            let mut replaced = false;
+
+            let next = substitute_words(&text, |word| {
+                let replacement = self.symbols.get(word).cloned();
+                replaced |= replacement.is_some();
+                replacement
+            });
+
+            if !replaced {
+                return Ok(next);
+            }
+
+            text = next;
+        }
+
+        bail!("symbols in {:?} are nested too deeply", text)
+    }
+
+    /// Substitutes all known symbols in an operand and evaluates compile-time
+    /// expressions, so the result is a plain target, value, type or signal
+    fn resolve_operand(&self, operand: &str) -> Result<String> {
+        let operand = self.resolve_symbols(operand)?;
+
+        if operand.parse::<Value>().is_ok() || operand.parse::<Target>().is_ok() ||
+           operand.parse::<Type>().is_ok() || operand.parse::<Signal>().is_ok()
+        {
+            return Ok(operand);
+        }
+
+        if let Some(addr_expr) = operand.strip_prefix('@') {
+            let addr = expr::eval(addr_expr)
+                .chain_err(|| format!("unable to evaluate address {:?}", operand))?
+                .as_integer();
+
+            ensure!(addr >= 0, "address {} must not be negative", addr);
+
+            Ok(format!("@{}", addr))
+        } else if let Some(captures) = INDEXED_TARGET_RE.captures(&operand) {
+            // Buttons may be given by their names, like `$btn(start)`
+            if &captures[1] == "btn" {
+                if let Ok(button) = captures[2].trim().parse::<Button>() {
+                    return Ok(format!("$btn({})", button.index()));
+                }
+            }
+
+            let index = expr::eval(&captures[2])
+                .chain_err(|| format!("unable to evaluate target {:?}", operand))?
+                .as_integer();
+
+            ensure!(index >= 0, "target index {} must not be negative", index);
+
+            Ok(format!("${}({})", &captures[1], index))
+        } else if expr::has_operator(&operand) {
+            Ok(
+                expr::eval(&operand)
+                    .chain_err(|| format!("unable to evaluate {:?}", operand))?
+                    .to_string(),
+            )
+        } else {
+            Ok(operand)
+        }
+    }
+
+    /// Appends a line to the body of the macro that is currently being
+    /// defined, finishing the definition at `endmacro`
+    fn record_macro_line(&mut self, line: &str) -> Result<()> {
+        if ENDMACRO_RE.is_match(line) {
+            if let Some((name, definition)) = self.recording.take() {
+                if self.macros.contains_key(&name) {
+                    bail!("macro {:?} already exists", name);
+                }
+
+                self.macros.insert(name, definition);
             }
+        } else if MACRO_RE.is_match(line) {
+            bail!("macro definitions can't be nested");
+        } else if let Some((_, ref mut definition)) = self.recording {
+            definition.body.push(line.to_owned());
         }
 
         Ok(())
     }
 
+    /// Compiles the body of the given macro, substituting its parameters with
+    /// the given arguments. Labels beginning with `%%` are made unique for
+    /// every expansion.
+    fn expand_macro(&mut self, name: &str, args: Vec<String>, orig_path: &Path) -> Result<()> {
+        let definition = if let Some(definition) = self.macros.get(name) {
+            definition.clone()
+        } else {
+            bail!("macro {:?} not found", name)
+        };
+
+        ensure!(
+            args.len() == definition.params.len(),
+            "macro {:?} expects {} arguments, found {}",
+            name,
+            definition.params.len(),
+            args.len()
+        );
+
+        ensure!(
+            self.macro_depth < MAX_MACRO_DEPTH,
+            "macro {:?} exceeds the maximum expansion depth of {}",
+            name,
+            MAX_MACRO_DEPTH
+        );
+
+        self.expansions += 1;
+        let local_prefix = format!("{}__{}_", name, self.expansions);
+
+        self.macro_depth += 1;
+        let open_conditions = self.conditions.len();
+
+        for line in &definition.body {
+            let line = substitute_words(line, |word| {
+                definition
+                    .params
+                    .iter()
+                    .position(|param| param == word)
+                    .map(|index| args[index].clone())
+            });
+            let line = line.replace("%%", &local_prefix);
+
+            self.compile_line(&line, orig_path)
+                .chain_err(|| format!("in expansion of macro {:?}", name))?;
+        }
+
+        ensure!(
+            self.conditions.len() == open_conditions,
+            "unbalanced conditional block in macro {:?}",
+            name
+        );
+
+        self.macro_depth -= 1;
+
+        Ok(())
+    }
+
     fn compile_instruction(&mut self, mnemonic: Mnemonic) -> Result<()> {
         match mnemonic {
             Mnemonic::Add(dest, src) => self.builder.add(dest, src),
@@ -211,6 +906,42 @@ impl BASMCompiler {
             Mnemonic::Pause => self.builder.pause(),
             Mnemonic::Nop => self.builder.nop(),
             Mnemonic::Sig(sig) => self.builder.sig(sig),
+
+            Mnemonic::Read(dest, asset) => self.builder.read(dest, asset),
+            Mnemonic::Blit(source, options) => self.builder.blit(source, options),
+
+            Mnemonic::Clear => self.builder.clear(),
+            Mnemonic::Rect => self.builder.rect(),
+            Mnemonic::Line => self.builder.line(),
+            Mnemonic::HSpan => self.builder.hspan(),
+            Mnemonic::VSpan => self.builder.vspan(),
+
+            Mnemonic::DrawChar(font) => self.builder.draw_char(font),
+            Mnemonic::DrawText(text, font) => self.builder.draw_text(text, font),
+
+            Mnemonic::SetPalette => self.builder.set_palette(),
+            Mnemonic::RotatePalette => self.builder.rotate_palette(),
+
+            Mnemonic::Layer(layer) => self.builder.layer(layer),
+            Mnemonic::Scroll(layer) => self.builder.scroll(layer),
+            Mnemonic::LayerKey(layer) => self.builder.layer_key(layer),
+
+            Mnemonic::Tilemap(layer, source, tileset) => self.builder.tilemap(layer, source, tileset),
+            Mnemonic::SetTile(layer) => self.builder.set_tile(layer),
+            Mnemonic::GetTile(layer) => self.builder.get_tile(layer),
+            Mnemonic::ScrollMap(layer) => self.builder.scroll_map(layer),
+
+            Mnemonic::Sprite(slot) => self.builder.sprite(slot),
+            Mnemonic::SpriteAttr(slot) => self.builder.sprite_attr(slot),
+            Mnemonic::HideSprite(slot) => self.builder.hide_sprite(slot),
+            Mnemonic::SpriteHit(slot, None) => self.builder.sprite_hit(slot),
+            Mnemonic::SpriteHit(slot, Some(other)) => self.builder.sprite_hit_pair(slot, other),
+            Mnemonic::BgHit(slot) => self.builder.bg_hit(slot),
+
+            Mnemonic::Voice(channel, waveform) => self.builder.voice(channel, waveform),
+            Mnemonic::Tone(channel) => self.builder.tone(channel),
+            Mnemonic::Envelope(channel) => self.builder.envelope(channel),
+            Mnemonic::NoteOff(channel) => self.builder.note_off(channel),
         }
 
         Ok(())
@@ -224,69 +955,438 @@ impl BASMCompiler {
         }
     }
 
-    pub fn compile(&mut self, path: PathBuf) -> Result<ImageData> {
+    pub fn compile(&mut self, path: PathBuf) -> Result<Program> {
         ensure!(path.is_absolute(), "file name must be absolute");
 
-        let parent = if let Some(ref parent) = path.parent() {
-            parent.to_path_buf().clone()
-        } else {
-            bail!("unable to get parent directory")
-        };
-
-        env::set_current_dir(parent.clone()).chain_err(|| "unable to switch directories")?;
-
         self.compile_mnemonics(&path)?;
 
-        env::set_current_dir(parent).chain_err(|| "unable to switch directories")?;
+        self.builder.set_config(self.config.clone());
+
+        for asset in self.assets.clone() {
+            self.builder.add_asset(asset);
+        }
 
         for mnemonic in self.mnemonics.clone() {
             self.compile_instruction(mnemonic)?;
         }
 
-        Ok(self.builder.clone().gen())
+        Ok(self.builder.gen_program())
     }
 }
 
 fn text_to_mnemonic(opcode: String, args: Vec<String>) -> Result<Mnemonic> {
     match opcode.as_str() {
-        "add" => Ok(Mnemonic::Add(args[0].parse()?, args[1].parse()?)),
-        "sub" => Ok(Mnemonic::Sub(args[0].parse()?, args[1].parse()?)),
-        "div" => Ok(Mnemonic::Div(args[0].parse()?, args[1].parse()?)),
-        "mul" => Ok(Mnemonic::Mul(args[0].parse()?, args[1].parse()?)),
-        "rem" => Ok(Mnemonic::Rem(args[0].parse()?, args[1].parse()?)),
-
-        "cmp" => Ok(Mnemonic::Cmp(args[0].parse()?, args[1].parse()?)),
-        "jmp" => Ok(Mnemonic::Jmp(args[0].parse()?)),
-        "jmplt" => Ok(Mnemonic::JmpLt(args[0].parse()?)),
-        "jmpgt" => Ok(Mnemonic::JmpGt(args[0].parse()?)),
-        "jmpeq" => Ok(Mnemonic::JmpEq(args[0].parse()?)),
-        "jmplteq" => Ok(Mnemonic::JmpLtEq(args[0].parse()?)),
-        "jmpgteq" => Ok(Mnemonic::JmpGtEq(args[0].parse()?)),
-
-        "cast" => Ok(Mnemonic::Cast(args[0].parse()?, args[1].parse()?)),
-        "push" => Ok(Mnemonic::Push(args[0].parse()?, args[1].parse()?)),
-        "mov" => Ok(Mnemonic::Mov(args[0].parse()?, args[1].parse()?)),
-        "swp" => Ok(Mnemonic::Swp(args[0].parse()?, args[1].parse()?)),
-        "dup" => Ok(Mnemonic::Dup(args[0].parse()?)),
-
-        "call" => Ok(Mnemonic::Call(args[0].parse()?)),
+        "add" => Ok(Mnemonic::Add(arg(&args, 0)?.parse()?, arg(&args, 1)?.parse()?)),
+        "sub" => Ok(Mnemonic::Sub(arg(&args, 0)?.parse()?, arg(&args, 1)?.parse()?)),
+        "div" => Ok(Mnemonic::Div(arg(&args, 0)?.parse()?, arg(&args, 1)?.parse()?)),
+        "mul" => Ok(Mnemonic::Mul(arg(&args, 0)?.parse()?, arg(&args, 1)?.parse()?)),
+        "rem" => Ok(Mnemonic::Rem(arg(&args, 0)?.parse()?, arg(&args, 1)?.parse()?)),
+
+        "cmp" => Ok(Mnemonic::Cmp(arg(&args, 0)?.parse()?, arg(&args, 1)?.parse()?)),
+        "jmp" => Ok(Mnemonic::Jmp(arg(&args, 0)?.parse()?)),
+        "jmplt" => Ok(Mnemonic::JmpLt(arg(&args, 0)?.parse()?)),
+        "jmpgt" => Ok(Mnemonic::JmpGt(arg(&args, 0)?.parse()?)),
+        "jmpeq" => Ok(Mnemonic::JmpEq(arg(&args, 0)?.parse()?)),
+        "jmplteq" => Ok(Mnemonic::JmpLtEq(arg(&args, 0)?.parse()?)),
+        "jmpgteq" => Ok(Mnemonic::JmpGtEq(arg(&args, 0)?.parse()?)),
+
+        "cast" => Ok(Mnemonic::Cast(arg(&args, 0)?.parse()?, arg(&args, 1)?.parse()?)),
+        "push" => Ok(Mnemonic::Push(arg(&args, 0)?.parse()?, arg(&args, 1)?.parse()?)),
+        "mov" => Ok(Mnemonic::Mov(arg(&args, 0)?.parse()?, arg(&args, 1)?.parse()?)),
+        "swp" => Ok(Mnemonic::Swp(arg(&args, 0)?.parse()?, arg(&args, 1)?.parse()?)),
+        "dup" => Ok(Mnemonic::Dup(arg(&args, 0)?.parse()?)),
+
+        "call" => Ok(Mnemonic::Call(arg(&args, 0)?.parse()?)),
         "ret" => Ok(Mnemonic::Ret),
 
         "halt" => Ok(Mnemonic::Halt),
         "pause" => Ok(Mnemonic::Pause),
         "nop" => Ok(Mnemonic::Nop),
-        "sig" => Ok(Mnemonic::Sig(args[0].parse()?)),
+        "sig" => Ok(Mnemonic::Sig(arg(&args, 0)?.parse()?)),
+
+        "read" => match arg(&args, 1)?.parse()? {
+            Value::Address(asset) => Ok(Mnemonic::Read(arg(&args, 0)?.parse()?, asset)),
+            _ => bail!("read expects an asset like @0 or the name of an asset"),
+        },
+        "blit" => Ok(Mnemonic::Blit(
+            arg(&args, 0)?.parse()?,
+            BlitOptions::parse(&args[1..])?,
+        )),
+
+        "clear" => Ok(Mnemonic::Clear),
+        "rect" => Ok(Mnemonic::Rect),
+        "line" => Ok(Mnemonic::Line),
+        "hspan" => Ok(Mnemonic::HSpan),
+        "vspan" => Ok(Mnemonic::VSpan),
+
+        "drawchar" => Ok(Mnemonic::DrawChar(parse_font(args.first())?)),
+        "drawtext" => match arg(&args, 0)?.parse()? {
+            Target::ValueIndex(text) => Ok(Mnemonic::DrawText(text, parse_font(args.get(1))?)),
+            _ => bail!("drawtext expects the text to be in the value-index like $vi(24)"),
+        },
+
+        "setpal" => Ok(Mnemonic::SetPalette),
+        "rotpal" => Ok(Mnemonic::RotatePalette),
+
+        "layer" => Ok(Mnemonic::Layer(parse_layer(arg(&args, 0)?)?)),
+        "scroll" => Ok(Mnemonic::Scroll(parse_layer(arg(&args, 0)?)?)),
+        "layerkey" => Ok(Mnemonic::LayerKey(parse_layer(arg(&args, 0)?)?)),
+
+        "tilemap" => match arg(&args, 2)?.parse()? {
+            Value::Address(tileset) => Ok(Mnemonic::Tilemap(
+                parse_layer(arg(&args, 0)?)?,
+                arg(&args, 1)?.parse()?,
+                tileset,
+            )),
+            _ => bail!("tilemap expects a tileset like @0 or the name of an asset"),
+        },
+        "settile" => Ok(Mnemonic::SetTile(parse_layer(arg(&args, 0)?)?)),
+        "gettile" => Ok(Mnemonic::GetTile(parse_layer(arg(&args, 0)?)?)),
+        "scrollmap" => Ok(Mnemonic::ScrollMap(parse_layer(arg(&args, 0)?)?)),
+
+        "sprite" => Ok(Mnemonic::Sprite(parse_slot(arg(&args, 0)?)?)),
+        "spriteattr" => Ok(Mnemonic::SpriteAttr(parse_slot(arg(&args, 0)?)?)),
+        "hidesprite" => Ok(Mnemonic::HideSprite(parse_slot(arg(&args, 0)?)?)),
+        "spritehit" => Ok(Mnemonic::SpriteHit(
+            parse_slot(arg(&args, 0)?)?,
+            args.get(1).map(|other| parse_slot(other)).transpose()?,
+        )),
+        "bghit" => Ok(Mnemonic::BgHit(parse_slot(arg(&args, 0)?)?)),
+
+        "voice" => Ok(Mnemonic::Voice(parse_channel(arg(&args, 0)?)?, arg(&args, 1)?.parse()?)),
+        "tone" => Ok(Mnemonic::Tone(parse_channel(arg(&args, 0)?)?)),
+        "envelope" => Ok(Mnemonic::Envelope(parse_channel(arg(&args, 0)?)?)),
+        "noteoff" => Ok(Mnemonic::NoteOff(parse_channel(arg(&args, 0)?)?)),
         _ => bail!("unkwnown opcode {:?}", opcode),
     }
 }
 
-pub fn compile(path: PathBuf) -> Result<ImageData> {
-    BASMCompiler::default().compile(path)
+/// Returns the operand at the given index or fails if it is missing
+fn arg(args: &[String], index: usize) -> Result<&String> {
+    match args.get(index) {
+        Some(arg) => Ok(arg),
+        None => bail!("expected {} operands, found {}", index + 1, args.len()),
+    }
+}
+
+/// Parses the optional font argument of a text instruction
+fn parse_font(arg: Option<&String>) -> Result<Option<Address>> {
+    match arg.map(|arg| arg.parse()) {
+        None => Ok(None),
+        Some(Ok(Value::Address(font))) => Ok(Some(font)),
+        Some(_) => bail!("the font must be an asset like @0 or the name of an asset"),
+    }
+}
+
+/// Parses the layer operand of a layer instruction
+fn parse_layer(arg: &str) -> Result<Address> {
+    parse_number(arg, "layer")
+}
+
+/// Parses the slot operand of a sprite table instruction
+fn parse_slot(arg: &str) -> Result<Address> {
+    parse_number(arg, "sprite slot")
+}
+
+/// Parses the channel operand of an audio instruction
+fn parse_channel(arg: &str) -> Result<Address> {
+    parse_number(arg, "channel")
+}
+
+fn parse_number(arg: &str, name: &str) -> Result<Address> {
+    match arg.parse()? {
+        Value::Integer(number) if number >= 0 => Ok(number as Address),
+        Value::Address(number) => Ok(number),
+        _ => bail!("the {} must be a number like 1. Found {:?}", name, arg),
+    }
+}
+
+/// Applies the value of a configuration directive like `.title` to the
+/// given configuration. Symbols in the value must be resolved already.
+pub fn apply_config(config: &mut Config, key: &str, value: &str) -> Result<()> {
+    match key {
+        "title" => config.title = parse_string(value)?,
+        "resolution" => {
+            let args = split_args(value);
+
+            ensure!(args.len() == 2, ".resolution expects a width and a height");
+
+            let width = expr::eval(&args[0])?.as_integer();
+            let height = expr::eval(&args[1])?.as_integer();
+
+            ensure!(
+                width > 0 && height > 0,
+                "invalid resolution {}x{}",
+                width,
+                height
+            );
+
+            config.display.resolution = DisplayResolution {
+                width: width as usize,
+                height: height as usize,
+            };
+        }
+        "scale" => {
+            let scale = expr::eval(value)?.as_float();
+
+            ensure!(scale >= 1.0, "display scale can't be less than 1");
+
+            config.display.default_scale = scale;
+        }
+        "hide_cursor" => config.display.hide_cursor = parse_flag(value)?,
+        "color_mode" => config.display.color_mode = value.parse()?,
+        "map_button" => {
+            let args = split_args(value);
+
+            ensure!(args.len() == 2, ".map_button expects a button and a key code");
+
+            let button: Button = args[0].parse()?;
+            let key = expr::eval(&args[1])?.as_integer();
+
+            ensure!(key >= 0, "key code {} must not be negative", key);
+
+            config.button_map.insert(button, key as Address);
+        }
+        "input_enabled" => config.input_enabled = parse_flag(value)?,
+        _ => bail!("unknown configuration {:?}", key),
+    }
+
+    Ok(())
+}
+
+/// Parses a string literal like `"My Game"`
+fn parse_string(text: &str) -> Result<String> {
+    ensure!(
+        text.len() >= 2 && text.starts_with('"') && text.ends_with('"'),
+        "string literal expected. Found {:?}",
+        text
+    );
+
+    let mut result = String::new();
+    let mut chars = text[1..(text.len() - 1)].chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('n') => result.push('\n'),
+            Some('t') => result.push('\t'),
+            Some(escaped) => result.push(escaped),
+            None => bail!("unterminated escape sequence in {:?}", text),
+        }
+    }
+
+    Ok(result)
+}
+
+/// Parses a boolean configuration value, which is either `true`, `false` or
+/// an expression
+fn parse_flag(text: &str) -> Result<bool> {
+    match text {
+        "true" => Ok(true),
+        "false" => Ok(false),
+        _ => Ok(expr::eval(text)?.is_true()),
+    }
+}
+
+/// Returns true if the operand of the given opcode is a label
+fn is_label_opcode(opcode: &str) -> bool {
+    opcode.starts_with("jmp") || opcode == "call"
+}
+
+/// Splits a comma separated argument list. An empty list yields no arguments.
+fn split_args(args: &str) -> Vec<String> {
+    if args.trim().is_empty() {
+        Vec::new()
+    } else {
+        split_unquoted(args, ',')
+            .into_iter()
+            .map(|arg| arg.trim().to_owned())
+            .collect()
+    }
+}
+
+/// Returns the byte offsets of all occurrences of `separator` that are not
+/// part of a char or string literal
+fn unquoted_positions(text: &str, separator: char) -> Vec<usize> {
+    let mut positions = Vec::new();
+    let mut quote: Option<char> = None;
+    let mut escaped = false;
+
+    for (index, c) in text.char_indices() {
+        if let Some(open) = quote {
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == open {
+                quote = None;
+            }
+        } else if c == '"' || c == '\'' {
+            quote = Some(c);
+        } else if c == separator {
+            positions.push(index);
+        }
+    }
+
+    positions
+}
+
+/// Splits the given text at every `separator` outside of char and string
+/// literals
+fn split_unquoted(text: &str, separator: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+
+    for position in unquoted_positions(text, separator) {
+        parts.push(&text[start..position]);
+        start = position + separator.len_utf8();
+    }
+
+    parts.push(&text[start..]);
+
+    parts
+}
+
+/// Removes a trailing `;` comment from the given line
+fn strip_comment(line: &str) -> &str {
+    match unquoted_positions(line, ';').first() {
+        Some(&position) => &line[..position],
+        None => line,
+    }
+}
+
+/// Replaces every whole word in `text` for which `lookup` returns a
+/// replacement. Words that are part of a target (`$st`), a label
+/// (`.start`), a signal, a char or a color are never replaced.
+fn substitute_words<F>(text: &str, mut lookup: F) -> String
+where
+    F: FnMut(&str) -> Option<String>,
+{
+    let mut result = String::with_capacity(text.len());
+    let mut chars = text.char_indices().peekable();
+    let mut prev: Option<char> = None;
+
+    while let Some((start, c)) = chars.next() {
+        if !is_word_char(c) {
+            result.push(c);
+            prev = Some(c);
+            continue;
+        }
+
+        let mut end = start + c.len_utf8();
+
+        while let Some(&(index, next)) = chars.peek() {
+            if !is_word_char(next) {
+                break;
+            }
+
+            end = index + next.len_utf8();
+            chars.next();
+        }
+
+        let word = &text[start..end];
+        let prefixed = match prev {
+            Some(prev) => is_word_prefix(prev),
+            None => false,
+        };
+        let replaceable = !c.is_ascii_digit() && !prefixed;
+
+        match if replaceable { lookup(word) } else { None } {
+            Some(replacement) => result.push_str(&replacement),
+            None => result.push_str(word),
+        }
+
+        prev = word.chars().last();
+    }
+
+    result
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+fn is_word_prefix(c: char) -> bool {
+    "$.%'#".contains(c)
+}
+
+/// Returns the directory containing the bundled `std` library, so that
+/// `include! std/index` works from every source file. This is the directory
+/// given by `BAKERVM_LIB_PATH`, or else the closest directory above the
+/// executable, that contains a `std` directory.
+pub fn bundled_library_path() -> Option<PathBuf> {
+    if let Some(path) = env::var_os(LIBRARY_PATH_VAR) {
+        return Some(PathBuf::from(path));
+    }
+
+    let executable = env::current_exe()
+        .and_then(|executable| executable.canonicalize())
+        .ok()?;
+
+    executable
+        .ancestors()
+        .skip(1)
+        .find(|dir| dir.join(LIBRARY_NAME).is_dir())
+        .map(Path::to_path_buf)
+}
+
+fn hash_contents(contents: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    contents.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Compiles the given file, reusing the results of all files that didn't
+/// change since the last compilation with the same cache
+pub fn compile_cached(path: PathBuf, options: &Options, cache: &mut Cache) -> Result<Program> {
+    let mut compiler = BASMCompiler {
+        include_paths: options.include_paths.clone(),
+        cache: mem::take(cache),
+        ..BASMCompiler::default()
+    };
+
+    compiler.cache.reused = 0;
+
+    let result = compile_with(&mut compiler, path, options);
+
+    *cache = mem::take(&mut compiler.cache);
+    cache.files = compiler
+        .read_files
+        .iter()
+        .map(|(path, _)| path.clone())
+        .collect();
+
+    result
+}
+
+fn compile_with(compiler: &mut BASMCompiler, path: PathBuf, options: &Options) -> Result<Program> {
+    for define in &options.defines {
+        compiler.add_symbol(define.name.clone(), define.value.clone())?;
+    }
+
+    let mut program = compiler.compile(path)?;
+
+    if options.debug {
+        let labels = compiler
+            .label_addr_map
+            .iter()
+            .map(|(label, &addr)| (label.clone(), addr))
+            .collect();
+
+        program.debug = Some(DebugInfo { labels });
+    }
+
+    Ok(program)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use core::{BlitSource, ColorMode, Instruction, Target, TilemapSource, Value, Waveform};
 
     #[test]
     fn labeled_mnemonic_regex() {
@@ -327,4 +1427,554 @@ mod tests {
             assert_eq!(captures[1].trim(), "std");
         }
     }
+
+    #[test]
+    fn macro_regex() {
+        let input = "macro std.alloc(N, offset)";
+
+        if !MACRO_RE.is_match(input) {
+            panic!("input doesn't match a macro definition");
+        } else {
+            let captures = MACRO_RE.captures_iter(input).next().unwrap();
+
+            assert_eq!(&captures[1], "std.alloc");
+            assert_eq!(split_args(&captures[2]), vec!["N", "offset"]);
+        }
+
+        let input = "std.alloc!(4)";
+
+        if !MACRO_CALL_RE.is_match(input) {
+            panic!("input doesn't match a macro invocation");
+        } else {
+            let captures = MACRO_CALL_RE.captures_iter(input).next().unwrap();
+
+            assert_eq!(&captures[1], "std.alloc");
+            assert_eq!(split_args(&captures[2]), vec!["4"]);
+        }
+    }
+
+    #[test]
+    fn word_substitution() {
+        let replaced = substitute_words("push $vi(N), @N ; .N $N %N% 'N' N_2", |word| {
+            if word == "N" {
+                Some("12".to_owned())
+            } else {
+                None
+            }
+        });
+
+        assert_eq!(replaced, "push $vi(12), @12 ; .N $N %N% 'N' N_2");
+    }
+
+    #[test]
+    fn macro_expansion() {
+        let source = "
+            macro fill(target, color, count)
+              push $st, @count
+            .%%loop
+              push target, color
+              jmp %%loop
+            endmacro
+
+            fill!($fb, #ff00ff, 3)
+            fill!($st, #000000, 5)
+        ";
+
+        let mut compiler = BASMCompiler::default();
+        compiler.compile_source(source, Path::new("test.basm")).unwrap();
+
+        assert_eq!(compiler.mnemonics.len(), 6);
+        assert_eq!(compiler.label_addr_map.get("fill__1_loop"), Some(&1));
+        assert_eq!(compiler.label_addr_map.get("fill__2_loop"), Some(&4));
+
+        match compiler.mnemonics[2] {
+            Mnemonic::Jmp(ref label) => assert_eq!(label, "fill__1_loop"),
+            ref other => panic!("expected a jump, found {:?}", other),
+        }
+
+        match compiler.mnemonics[3] {
+            Mnemonic::Push(Target::Stack, Value::Address(5)) => {}
+            ref other => panic!("expected a push of @5, found {:?}", other),
+        }
+    }
+
+    #[test]
+    fn macro_errors() {
+        let mut compiler = BASMCompiler::default();
+        assert!(
+            compiler
+                .compile_source("macro twice(a)\nnop\nendmacro\ntwice!(1, 2)", Path::new("test.basm"))
+                .is_err()
+        );
+
+        let mut compiler = BASMCompiler::default();
+        assert!(
+            compiler
+                .compile_source("macro loop()\nloop!()\nendmacro\nloop!()", Path::new("test.basm"))
+                .is_err()
+        );
+
+        let mut compiler = BASMCompiler::default();
+        assert!(compiler.compile_source("endmacro", Path::new("test.basm")).is_err());
+    }
+
+    #[test]
+    fn symbols_and_expressions() {
+        let source = "
+            .equ WIDTH = 160
+            .equ HEIGHT 100
+            .equ AREA = WIDTH * HEIGHT
+            .define SCREEN $fb
+            .local x0 = 20 + 3
+
+            push $st, @WIDTH*HEIGHT
+            push $vi(AREA / 8000 + 18), WIDTH / 2
+            mov x0, $st
+            push SCREEN, #ff00ff
+            push $st, 1.5 * 2
+            push $key('a'), @AREA
+            push $fb(AREA / 8000 + 1), #ff00ff
+            layer AREA / 8000
+            tilemap AREA / 8000, $vi(AREA / 800), @1
+            spritehit AREA / 1000
+            voice AREA / 8000, triangle
+            cmp $btn(start), $btn(AREA / 4000)
+            spritehit 0, AREA / 1000
+        ";
+
+        let mut compiler = BASMCompiler::default();
+        compiler.compile_source(source, Path::new("test.basm")).unwrap();
+
+        match compiler.mnemonics[0] {
+            Mnemonic::Push(Target::Stack, Value::Address(16000)) => {}
+            ref other => panic!("unexpected mnemonic {:?}", other),
+        }
+
+        match compiler.mnemonics[1] {
+            Mnemonic::Push(Target::ValueIndex(20), Value::Integer(80)) => {}
+            ref other => panic!("unexpected mnemonic {:?}", other),
+        }
+
+        match compiler.mnemonics[2] {
+            Mnemonic::Mov(Target::ValueIndex(23), Target::Stack) => {}
+            ref other => panic!("unexpected mnemonic {:?}", other),
+        }
+
+        match compiler.mnemonics[3] {
+            Mnemonic::Push(Target::Framebuffer, Value::Color(255, 0, 255)) => {}
+            ref other => panic!("unexpected mnemonic {:?}", other),
+        }
+
+        match compiler.mnemonics[4] {
+            Mnemonic::Push(Target::Stack, Value::Float(value)) => assert_eq!(value, 3.0),
+            ref other => panic!("unexpected mnemonic {:?}", other),
+        }
+
+        match compiler.mnemonics[5] {
+            Mnemonic::Push(Target::KeyRegister(97), Value::Address(16000)) => {}
+            ref other => panic!("unexpected mnemonic {:?}", other),
+        }
+
+        match compiler.mnemonics[6] {
+            Mnemonic::Push(Target::FramebufferLayer(3), Value::Color(255, 0, 255)) => {}
+            ref other => panic!("unexpected mnemonic {:?}", other),
+        }
+
+        match compiler.mnemonics[7] {
+            Mnemonic::Layer(2) => {}
+            ref other => panic!("unexpected mnemonic {:?}", other),
+        }
+
+        match compiler.mnemonics[8] {
+            Mnemonic::Tilemap(2, TilemapSource::ValueIndex(20), 1) => {}
+            ref other => panic!("unexpected mnemonic {:?}", other),
+        }
+
+        match compiler.mnemonics[9] {
+            Mnemonic::SpriteHit(16, None) => {}
+            ref other => panic!("unexpected mnemonic {:?}", other),
+        }
+
+        match compiler.mnemonics[10] {
+            Mnemonic::Voice(2, Waveform::Triangle) => {}
+            ref other => panic!("unexpected mnemonic {:?}", other),
+        }
+
+        match compiler.mnemonics[11] {
+            Mnemonic::Cmp(Target::ButtonRegister(6), Target::ButtonRegister(4)) => {}
+            ref other => panic!("unexpected mnemonic {:?}", other),
+        }
+
+        match compiler.mnemonics[12] {
+            Mnemonic::SpriteHit(0, Some(16)) => {}
+            ref other => panic!("unexpected mnemonic {:?}", other),
+        }
+    }
+
+    #[test]
+    fn symbol_errors() {
+        let mut compiler = BASMCompiler::default();
+        assert!(
+            compiler
+                .compile_source(".equ A = 1\n.equ A = 2", Path::new("test.basm"))
+                .is_err()
+        );
+
+        let mut compiler = BASMCompiler::default();
+        assert!(
+            compiler
+                .compile_source("push $st, @UNKNOWN * 2", Path::new("test.basm"))
+                .is_err()
+        );
+
+        let mut compiler = BASMCompiler::default();
+        assert!(
+            compiler
+                .compile_source(".define A B\n.define B A\npush $st, A", Path::new("test.basm"))
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn missing_operands() {
+        let sources = [
+            "add $st",
+            "push $st",
+            "read $vi(0)",
+            "blit",
+            "drawtext",
+            "layer",
+            "tilemap 0, @1",
+            "scrollmap",
+            "sprite",
+            "spritehit",
+            "voice 0",
+            "tone",
+        ];
+
+        for source in &sources {
+            let mut compiler = BASMCompiler::default();
+            assert!(compiler.compile_source(source, Path::new("test.basm")).is_err());
+        }
+    }
+
+    #[test]
+    fn conditional_assembly() {
+        let source = "
+            .equ WIDTH = 320
+
+            .ifdef DEBUG
+              push $st, 1
+              .if WIDTH > 160
+                push $st, 2
+              .else
+                push $st, 3
+              .endif
+            .else
+              include! does/not/exist
+              push $st, 4
+            .endif
+
+            .ifndef RELEASE
+              .if UNDEFINED_IN_SKIPPED_BLOCK
+              .endif
+            .else
+              push $st, 5
+            .endif
+        ";
+
+        // Without RELEASE the inner condition is evaluated and refers to an
+        // unknown symbol
+        let mut compiler = BASMCompiler::default();
+        compiler
+            .add_symbol("DEBUG".to_owned(), "1".to_owned())
+            .unwrap();
+        compiler.compile_source(source, Path::new("test.basm")).unwrap_err();
+
+        let mut compiler = BASMCompiler::default();
+        compiler
+            .add_symbol("DEBUG".to_owned(), "1".to_owned())
+            .unwrap();
+        compiler
+            .add_symbol("RELEASE".to_owned(), "1".to_owned())
+            .unwrap();
+        compiler.compile_source(source, Path::new("test.basm")).unwrap();
+
+        let pushed: Vec<Value> = compiler
+            .mnemonics
+            .iter()
+            .map(|mnemonic| match *mnemonic {
+                Mnemonic::Push(Target::Stack, ref value) => value.clone(),
+                ref other => panic!("unexpected mnemonic {:?}", other),
+            })
+            .collect();
+
+        assert_eq!(
+            pushed,
+            vec![Value::Integer(1), Value::Integer(2), Value::Integer(5)]
+        );
+    }
+
+    #[test]
+    fn condition_errors() {
+        let mut compiler = BASMCompiler::default();
+        assert!(compiler.compile_source(".if 1", Path::new("test.basm")).is_err());
+
+        let mut compiler = BASMCompiler::default();
+        assert!(compiler.compile_source(".endif", Path::new("test.basm")).is_err());
+
+        let mut compiler = BASMCompiler::default();
+        assert!(
+            compiler
+                .compile_source(".if 1\n.else\n.else\n.endif", Path::new("test.basm"))
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn define_parsing() {
+        let define: Define = "WIDTH=320".parse().unwrap();
+        assert_eq!((define.name.as_str(), define.value.as_str()), ("WIDTH", "320"));
+
+        let define: Define = "DEBUG".parse().unwrap();
+        assert_eq!((define.name.as_str(), define.value.as_str()), ("DEBUG", "1"));
+
+        assert!("1NVALID=2".parse::<Define>().is_err());
+    }
+
+    fn write_source(dir: &Path, name: &str, source: &str) -> PathBuf {
+        let path = dir.join(name);
+
+        ::std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        File::create(&path)
+            .unwrap()
+            .write_all(source.as_bytes())
+            .unwrap();
+
+        path
+    }
+
+    #[test]
+    fn include_search_paths() {
+        let dir = env::temp_dir().join(format!("basm_include_test_{}", ::std::process::id()));
+        let main = write_source(&dir, "src/main.basm", "include! util\ninclude! shared/lib\nhalt");
+        write_source(&dir, "src/util.basm", "nop");
+        write_source(&dir, "vendor/shared/lib.basm", "include! missing\nnop");
+
+        let options = Options {
+            include_paths: vec![dir.join("vendor")],
+            ..Options::default()
+        };
+
+        let error = compile_cached(main.clone(), &options, &mut Cache::default()).unwrap_err().to_string();
+
+        assert!(error.contains("unable to find \"missing.basm\""));
+        assert!(error.contains(&format!("included from {}:1", dir.join("vendor/shared/lib.basm").display())));
+        assert!(error.contains(&format!("included from {}:2", main.display())));
+
+        write_source(&dir, "vendor/shared/missing.basm", "nop");
+
+        compile_cached(main, &options, &mut Cache::default()).unwrap();
+
+        ::std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn bundled_library() {
+        // Test executables are built below the repository, which holds `std`
+        if env::var_os(LIBRARY_PATH_VAR).is_none() {
+            let library = bundled_library_path().unwrap();
+
+            assert!(library.join(LIBRARY_NAME).join("index.basm").is_file());
+        }
+    }
+
+    #[test]
+    fn compile_cache() {
+        let dir = env::temp_dir().join(format!("basm_cache_test_{}", ::std::process::id()));
+        let main = write_source(
+            &dir,
+            "main.basm",
+            ".equ SIZE = 2\ninclude! sprite\ninclude! util\ncall draw\nhalt",
+        );
+        write_source(&dir, "sprite.basm", "jmp end\n.draw\npush $st, @SIZE\nret\n.end");
+        write_source(&dir, "util.basm", "nop");
+
+        let options = Options::default();
+        let mut cache = Cache::default();
+
+        let assert_unchanged = |cache: &mut Cache| {
+            let cached = compile_cached(main.clone(), &options, cache).unwrap();
+            let fresh = compile_cached(main.clone(), &options, &mut Cache::default()).unwrap();
+
+            assert_eq!(
+                format!("{:?}", cached.instructions),
+                format!("{:?}", fresh.instructions)
+            );
+        };
+
+        assert_unchanged(&mut cache);
+        assert_eq!(cache.reused(), 0);
+        assert_eq!(cache.files().len(), 3);
+
+        // Nothing changed, so the whole program is taken from the cache
+        assert_unchanged(&mut cache);
+        assert_eq!(cache.reused(), 1);
+
+        write_source(&dir, "util.basm", "nop\nnop");
+
+        assert_unchanged(&mut cache);
+        assert_eq!(cache.reused(), 1);
+
+        write_source(
+            &dir,
+            "main.basm",
+            ".equ SIZE = 2\ninclude! sprite\ninclude! util\ncall draw\nnop\nhalt",
+        );
+
+        assert_unchanged(&mut cache);
+        assert_eq!(cache.reused(), 2);
+
+        // The included files are compiled with a different constant
+        write_source(
+            &dir,
+            "main.basm",
+            ".equ SIZE = 3\ninclude! sprite\ninclude! util\ncall draw\nhalt",
+        );
+
+        assert_unchanged(&mut cache);
+        assert_eq!(cache.reused(), 0);
+
+        ::std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn assets() {
+        let dir = env::temp_dir().join(format!("basm_asset_test_{}", ::std::process::id()));
+        let main = write_source(&dir, "main.basm", "include! level
+push $st, @1
+read $st, level");
+        write_source(&dir, "level.basm", ".asset level \"level.bin\"");
+        write_source(&dir, "level.bin", "\u{1}\u{2}\u{3}");
+
+        let options = Options::default();
+        let mut cache = Cache::default();
+
+        let program = compile_cached(main.clone(), &options, &mut cache).unwrap();
+
+        assert_eq!(program.assets.len(), 1);
+        assert_eq!(program.assets[0].name, "level");
+        assert_eq!(program.assets[0].data, vec![1, 2, 3]);
+        assert_eq!(
+            format!("{:?}", program.instructions[1]),
+            format!("{:?}", Instruction::Read(Target::Stack, 0))
+        );
+
+        // Changing the asset invalidates the file embedding it
+        write_source(&dir, "level.bin", "\u{4}");
+
+        let program = compile_cached(main.clone(), &options, &mut cache).unwrap();
+
+        assert_eq!(cache.reused(), 0);
+        assert_eq!(program.assets[0].data, vec![4]);
+
+        let program = compile_cached(main.clone(), &options, &mut cache).unwrap();
+
+        assert_eq!(cache.reused(), 1);
+        assert_eq!(program.assets[0].data, vec![4]);
+
+        write_source(&dir, "main.basm", ".asset level \"missing.bin\"");
+        assert!(compile_cached(main.clone(), &options, &mut cache).is_err());
+
+        write_source(&dir, "main.basm", ".asset level \"level.bin\", sound");
+        assert!(compile_cached(main.clone(), &options, &mut cache).is_err());
+
+        write_source(&dir, "main.basm", ".asset level \"level.bin\"
+blit level, flip_x, #ff00ff
+blit $vi(24)");
+
+        let program = compile_cached(main.clone(), &options, &mut cache).unwrap();
+
+        assert_eq!(
+            format!("{:?}", program.instructions[0]),
+            format!(
+                "{:?}",
+                Instruction::Blit(
+                    BlitSource::Asset(0),
+                    BlitOptions {
+                        flip_x: true,
+                        flip_y: false,
+                        color_key: Some((255, 0, 255)),
+                    }
+                )
+            )
+        );
+        assert_eq!(
+            format!("{:?}", program.instructions[1]),
+            format!(
+                "{:?}",
+                Instruction::Blit(BlitSource::ValueIndex(24), BlitOptions::default())
+            )
+        );
+
+        write_source(&dir, "main.basm", ".asset level \"level.bin\"\nblit level, mirror");
+        assert!(compile_cached(main.clone(), &options, &mut cache).is_err());
+
+        write_source(&dir, "main.basm", ".asset level \"level.bin\"
+drawchar
+drawtext $vi(24), level");
+
+        let program = compile_cached(main.clone(), &options, &mut cache).unwrap();
+
+        assert_eq!(
+            format!("{:?}", &program.instructions[0..2]),
+            format!(
+                "{:?}",
+                [Instruction::DrawChar(None), Instruction::DrawText(24, Some(0))]
+            )
+        );
+
+        write_source(&dir, "main.basm", "drawtext $st");
+        assert!(compile_cached(main.clone(), &options, &mut cache).is_err());
+
+        ::std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn config_directives() {
+        let source = "
+            .equ WIDTH = 320
+            .title \"My \\\"Game\\\"; Part 2\" ; the title may contain semicolons
+            .resolution WIDTH, WIDTH * 5 / 8
+            .scale 2.5
+            .hide_cursor false
+            .color_mode indexed16
+            .input_enabled 1 > 2
+            .map_button start, 13
+            .map_button dpup, 'w'
+
+            push $st, ';'
+        ";
+
+        let mut compiler = BASMCompiler::default();
+        compiler.compile_source(source, Path::new("test.basm")).unwrap();
+
+        assert_eq!(compiler.config.title, "My \"Game\"; Part 2");
+        assert_eq!(compiler.config.display.resolution.width, 320);
+        assert_eq!(compiler.config.display.resolution.height, 200);
+        assert_eq!(compiler.config.display.default_scale, 2.5);
+        assert!(!compiler.config.display.hide_cursor);
+        assert_eq!(compiler.config.display.color_mode, ColorMode::Indexed16);
+        assert!(!compiler.config.input_enabled);
+        assert_eq!(compiler.config.button_map.get(&Button::Start), Some(&13));
+        assert_eq!(compiler.config.button_map.get(&Button::DPadUp), Some(&119));
+
+        match compiler.mnemonics[0] {
+            Mnemonic::Push(Target::Stack, Value::Char(';')) => {}
+            ref other => panic!("unexpected mnemonic {:?}", other),
+        }
+
+        let mut compiler = BASMCompiler::default();
+        assert!(compiler.compile_source(".scale 0.5", Path::new("test.basm")).is_err());
+    }
 }
@@ -1,6 +1,8 @@
+use basm;
+use core::{Config, Program};
 use core::error::*;
-use core::typedef::*;
 use pest::Parser;
+use pest::iterators::Pair;
 use std::fs::File;
 use std::io::Read;
 use std::path::PathBuf;
@@ -11,7 +13,7 @@ const _GRAMMAR: &str = include_str!("../src/beast.pest");
 #[grammar = "beast.pest"]
 pub struct BeastParser;
 
-pub fn compile(path: PathBuf) -> Result<ImageData> {
+pub fn compile(path: PathBuf) -> Result<Program> {
     let mut file = File::open(path).chain_err(|| "unable to open file")?;
 
     let mut buf = String::new();
@@ -19,13 +21,80 @@ pub fn compile(path: PathBuf) -> Result<ImageData> {
     file.read_to_string(&mut buf)
         .chain_err(|| "unable to read file")?;
 
-    let parser_res = BeastParser::parse(Rule::file, &buf);
+    compile_source(&buf)
+}
+
+/// Compiles the given source into a program. Only the `config` form is
+/// compiled so far, so sources containing modules are rejected.
+fn compile_source(source: &str) -> Result<Program> {
+    let file = parse_file(source)?;
+
+    let config = read_config(file.clone())?;
+
+    ensure!(
+        file.into_inner().all(|pair| pair.as_rule() != Rule::module),
+        "Beast compiler is not implemented yet! Only the config form is compiled"
+    );
+
+    Ok(Program {
+        config,
+        ..Program::default()
+    })
+}
+
+fn parse_file(source: &str) -> Result<Pair<'_, Rule>> {
+    match BeastParser::parse(Rule::file, source) {
+        Ok(mut pairs) => pairs.next().ok_or_else(|| "empty Beast file".into()),
+        Err(err) => bail!("\nError parsing file:\n{}\n", err),
+    }
+}
+
+/// Reads the program configuration from the `config` form of the given file.
+/// The fields are applied like the configuration directives of BASM. Returns
+/// the default configuration if there is no `config` form.
+fn read_config(file: Pair<Rule>) -> Result<Config> {
+    let mut config = Config::default();
+
+    for pair in file.into_inner() {
+        if pair.as_rule() != Rule::config {
+            continue;
+        }
 
-    if let Err(err) = parser_res {
-        bail!("\nError parsing file:\n{}\n", err);
+        for field in pair.into_inner() {
+            let rule = field.as_rule();
+            let values: Vec<Pair<Rule>> = field.into_inner().collect();
+
+            let (key, value) = match rule {
+                Rule::title_field => ("title", values[0].as_str().to_owned()),
+                Rule::resolution_field => (
+                    "resolution",
+                    format!(
+                        "{}, {}",
+                        parse_unsigned(values[0].as_str())?,
+                        parse_unsigned(values[1].as_str())?
+                    ),
+                ),
+                Rule::scale_field => ("scale", values[0].as_str().replace('_', "")),
+                Rule::hide_cursor_field => ("hide_cursor", values[0].as_str().to_owned()),
+                Rule::input_enabled_field => ("input_enabled", values[0].as_str().to_owned()),
+                _ => bail!("unexpected config field {:?}", rule),
+            };
+
+            basm::apply_config(&mut config, key, &value)?;
+        }
     }
 
-    bail!("Beast compiler is not implemented yet!")
+    Ok(config)
+}
+
+fn parse_unsigned(text: &str) -> Result<usize> {
+    let text = text.replace('_', "");
+
+    if let Some(digits) = text.strip_prefix("0x") {
+        usize::from_str_radix(digits, 16).chain_err(|| "unable to parse hex number")
+    } else {
+        text.parse().chain_err(|| "unable to parse number")
+    }
 }
 
 #[cfg(test)]
@@ -56,4 +125,33 @@ mod tests {
             include_str!("../examples/beast/simple/else_if.beast"),
         ).unwrap_or_else(|e| panic!("{}", e));
     }
+
+    #[test]
+    fn config() {
+        let source = include_str!("../examples/beast/config/config.beast");
+        let config = read_config(parse_file(source).unwrap()).unwrap();
+
+        assert_eq!(config.title, "Config \"Test\"");
+        assert_eq!(config.display.resolution.width, 320);
+        assert_eq!(config.display.resolution.height, 200);
+        assert_eq!(config.display.default_scale, 2.5);
+        assert!(!config.display.hide_cursor);
+        assert!(config.input_enabled);
+
+        // Modules can't be compiled yet
+        assert!(compile_source(source).is_err());
+
+        let program = compile_source("(config (title \"Only config\") (scale 2))").unwrap();
+
+        assert_eq!(program.config.title, "Only config");
+        assert_eq!(program.config.display.default_scale, 2.0);
+        assert!(program.instructions.is_empty());
+
+        let source = include_str!("../examples/beast/simple/simple.beast");
+        let config = read_config(parse_file(source).unwrap()).unwrap();
+
+        assert_eq!(config.title, Config::default().title);
+
+        assert!(compile_source("(config (scale 0.5))").is_err());
+    }
 }
@@ -0,0 +1,157 @@
+use basm;
+use commands::{compile_cached, pack};
+use core::error::*;
+use manifest::{self, Manifest};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+/// The interval in which watched files are checked for changes
+const POLL_INTERVAL_MS: u64 = 250;
+
+/// Builds the project described by the manifest at the given path. Packs all
+/// declared images first and compiles the entry file afterwards. In watch
+/// mode, the project is rebuilt whenever one of its files changes.
+pub fn build(path: Option<PathBuf>, watch: bool) -> Result<()> {
+    let mut builder = Builder::new(manifest::find(path)?);
+
+    if !watch {
+        return builder.build();
+    }
+
+    loop {
+        match builder.build() {
+            Ok(()) => println!(
+                "BUILD   finished, reused {} cached files",
+                builder.cache.reused()
+            ),
+            Err(ref e) => {
+                println!("error: {}", e);
+
+                for e in e.iter().skip(1) {
+                    println!("caused by: {}", e);
+                }
+            }
+        }
+
+        println!("WATCH   waiting for changes");
+
+        wait_for_change(&builder.watched_files());
+    }
+}
+
+struct Builder {
+    manifest_path: PathBuf,
+    /// All paths inside of the manifest are relative to the manifest itself
+    root: PathBuf,
+    cache: basm::Cache,
+    /// The images packed during the last build and their modification times
+    packed: HashMap<PathBuf, Option<SystemTime>>,
+}
+
+impl Builder {
+    fn new(manifest_path: PathBuf) -> Builder {
+        let root = manifest_path
+            .parent()
+            .map_or_else(|| Path::new(".").to_path_buf(), Path::to_path_buf);
+
+        Builder {
+            manifest_path,
+            root,
+            cache: Default::default(),
+            packed: HashMap::new(),
+        }
+    }
+
+    fn build(&mut self) -> Result<()> {
+        let manifest = Manifest::load(&self.manifest_path).chain_err(|| {
+            format!("unable to load manifest {}", self.manifest_path.display())
+        })?;
+
+        let mut packed = HashMap::new();
+
+        for rule in manifest.pack_rules {
+            let input = self.root.join(&rule.input);
+            let output = rule.output.as_ref().map(|output| self.root.join(output));
+            let modified = modification_time(&input);
+
+            // Images are only packed again if they changed since the last
+            // build or if their output is missing
+            let output_exists = match output {
+                Some(ref output) => output.exists(),
+                None => input.with_extension("basm").exists(),
+            };
+
+            let unchanged = modified.is_some() && self.packed.get(&input) == Some(&modified);
+
+            if !unchanged || !output_exists {
+                pack(rule.packing_type, input.clone(), output)
+                    .chain_err(|| format!("unable to pack {}", rule.input.display()))?;
+            }
+
+            packed.insert(input, modified);
+        }
+
+        self.packed = packed;
+
+        let project = manifest.project;
+
+        let options = basm::Options {
+            defines: project.defines,
+            include_paths: project
+                .include
+                .iter()
+                .map(|path| self.root.join(path))
+                .collect(),
+            debug: project.debug,
+        };
+
+        let output = project.output.map(|output| self.root.join(output));
+
+        compile_cached(
+            project.lang,
+            self.root.join(project.entry),
+            output,
+            options,
+            manifest.config,
+            &mut self.cache,
+        )
+    }
+
+    /// The manifest, the packed images and all source files read during the
+    /// last build
+    fn watched_files(&self) -> Vec<PathBuf> {
+        let mut files = vec![self.manifest_path.clone()];
+
+        files.extend(self.packed.keys().cloned());
+        files.extend(self.cache.files().iter().cloned());
+
+        files
+    }
+}
+
+fn modification_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+/// Blocks until one of the given files is modified, created or removed
+fn wait_for_change(files: &[PathBuf]) {
+    let times: Vec<_> = files.iter().map(|file| modification_time(file)).collect();
+
+    loop {
+        thread::sleep(Duration::from_millis(POLL_INTERVAL_MS));
+
+        let changed = files
+            .iter()
+            .zip(&times)
+            .any(|(file, time)| modification_time(file) != *time);
+
+        if changed {
+            return;
+        }
+    }
+}
@@ -1,16 +1,18 @@
 use basm;
 use beast;
+use core::{ColorMode, Config, DisplayResolution};
 use core::error::*;
-use std::env;
-use std::fs::File;
+use core::typedef::*;
+use std::fs::{self, File};
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
 const BAKERVM_IMAGE_EXTENSION: &str = "img";
+const TEMP_EXTENSION: &str = ".tmp";
 pub const DEFAULT_LANG: Lang = Lang::Beast;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub enum Lang {
     Beast,
     Basm,
@@ -27,7 +29,73 @@ impl FromStr for Lang {
     }
 }
 
-pub fn compile(lang: Option<Lang>, input: PathBuf, output: Option<PathBuf>) -> Result<()> {
+/// Configuration values given on the command line or in the project manifest,
+/// taking precedence over the ones set in the source files
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigOverrides {
+    pub title: Option<String>,
+    #[serde(default, deserialize_with = "::manifest::parse_optional")]
+    pub resolution: Option<DisplayResolution>,
+    pub scale: Option<Float>,
+    pub hide_cursor: Option<bool>,
+    #[serde(default, deserialize_with = "::manifest::parse_optional")]
+    pub color_mode: Option<ColorMode>,
+    pub input_enabled: Option<bool>,
+}
+
+impl ConfigOverrides {
+    pub fn apply(&self, config: &mut Config) -> Result<()> {
+        if let Some(ref title) = self.title {
+            config.title = title.clone();
+        }
+
+        if let Some(ref resolution) = self.resolution {
+            config.display.resolution = resolution.clone();
+        }
+
+        if let Some(scale) = self.scale {
+            ensure!(scale >= 1.0, "display scale can't be less than 1");
+
+            config.display.default_scale = scale;
+        }
+
+        if let Some(hide_cursor) = self.hide_cursor {
+            config.display.hide_cursor = hide_cursor;
+        }
+
+        if let Some(color_mode) = self.color_mode {
+            config.display.color_mode = color_mode;
+        }
+
+        if let Some(input_enabled) = self.input_enabled {
+            config.input_enabled = input_enabled;
+        }
+
+        Ok(())
+    }
+}
+
+pub fn compile(
+    lang: Option<Lang>, input: PathBuf, output: Option<PathBuf>, options: basm::Options,
+    overrides: ConfigOverrides
+) -> Result<()> {
+    compile_cached(
+        lang,
+        input,
+        output,
+        options,
+        overrides,
+        &mut basm::Cache::default(),
+    )
+}
+
+/// Compiles the given file like `compile`, reusing the unchanged files of the
+/// previous compilation with the same cache
+pub fn compile_cached(
+    lang: Option<Lang>, input: PathBuf, output: Option<PathBuf>, options: basm::Options,
+    overrides: ConfigOverrides, cache: &mut basm::Cache
+) -> Result<()> {
     let input = input
         .canonicalize()
         .chain_err(|| "unable to canonicalize input path")?;
@@ -55,22 +123,33 @@ pub fn compile(lang: Option<Lang>, input: PathBuf, output: Option<PathBuf>) -> R
 
     let output = output.unwrap_or(fallback_output);
 
-    let start_dir = env::current_dir().chain_err(|| "unable to get current directory")?;
-
-    let program = match lang {
-        Lang::Basm => basm::compile(input).chain_err(|| "unable to compile basm file")?,
+    let mut program = match lang {
+        Lang::Basm => basm::compile_cached(input, &options, cache)
+            .chain_err(|| "unable to compile basm file")?,
         Lang::Beast => beast::compile(input).chain_err(|| "unable to compile Beast file")?,
     };
 
-    env::set_current_dir(start_dir).chain_err(|| "unable to switch directories")?;
+    overrides.apply(&mut program.config)?;
+
+    let program = program.encode()?;
+
+    write_atomically(&output, &program)
+}
+
+/// Writes the image to a temporary file first and moves it to its destination
+/// afterwards, so a VM watching the image never reads a partial file
+pub fn write_atomically(output: &Path, data: &[u8]) -> Result<()> {
+    let mut temp_output = output.as_os_str().to_owned();
+    temp_output.push(TEMP_EXTENSION);
+    let temp_output = PathBuf::from(temp_output);
 
-    let mut file = File::create(output).chain_err(|| "unable to create file")?;
+    let mut file = File::create(&temp_output).chain_err(|| "unable to create file")?;
 
-    file.write_all(&program[..])
+    file.write_all(data)
         .chain_err(|| "unable to write program data")?;
 
     file.sync_all()
         .chain_err(|| "unable to sync output file to file system")?;
 
-    Ok(())
+    fs::rename(&temp_output, output).chain_err(|| "unable to move image to its destination")
 }
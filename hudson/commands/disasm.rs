@@ -0,0 +1,364 @@
+use asset;
+use commands::read_image;
+use core::{Instruction, Program};
+use core::error::*;
+use core::typedef::*;
+use regex::Regex;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+lazy_static! {
+    static ref ASSET_NAME_RE: Regex = Regex::new(r"^[A-Za-z_]\w*$").unwrap();
+}
+
+/// The column at which the address comments of the instructions start
+const ADDRESS_COLUMN: usize = 40;
+
+/// The suffix of the directory next to a disassembled source holding its
+/// assets
+const ASSET_DIR_SUFFIX: &str = "_assets";
+
+/// Decodes the given image and writes it as BASM source to the given output
+/// or to stdout. The assets are written to a directory next to the output.
+pub fn disasm(input: PathBuf, output: Option<PathBuf>) -> Result<()> {
+    let program = read_image(&input)?;
+
+    if let Some(output) = output {
+        let stem = output.file_stem().map_or("image".into(), |stem| stem.to_string_lossy());
+        let asset_dir = format!("{}{}", stem, ASSET_DIR_SUFFIX);
+
+        let (source, assets) = disassemble(&program, &asset_dir)?;
+
+        let dir = output.parent().unwrap_or_else(|| Path::new(""));
+
+        if !assets.is_empty() {
+            fs::create_dir_all(dir.join(&asset_dir)).chain_err(|| "unable to create asset directory")?;
+        }
+
+        for (path, data) in assets {
+            fs::write(dir.join(&path), data).chain_err(|| format!("unable to write asset {:?}", path))?;
+        }
+
+        let mut file = File::create(output).chain_err(|| "unable to create file")?;
+
+        file.write_all(source.as_bytes())
+            .chain_err(|| "unable to write to file")?;
+    } else {
+        ensure!(
+            program.assets.is_empty(),
+            "the image holds {} asset(s), which are written to files next to the source. \
+             Disassemble it to a file using -o",
+            program.assets.len()
+        );
+
+        print!("{}", disassemble(&program, "")?.0);
+    }
+
+    Ok(())
+}
+
+/// The files of the assets of a disassembled program by their paths
+pub type AssetFiles = Vec<(String, Vec<u8>)>;
+
+/// Formats the program as BASM source that compiles to the same image. Jump
+/// and call targets get the names of the debug section or generated names.
+/// The assets are returned as files in the given directory, that the source
+/// embeds.
+pub fn disassemble(program: &Program, asset_dir: &str) -> Result<(String, AssetFiles)> {
+    let labels = label_names(program);
+
+    let mut source = String::new();
+    let mut assets = Vec::new();
+
+    source += &format!("; preamble: {}\n", program.preamble);
+    source += &format!("; format version: {}\n", program.format_version);
+    source += &format!("; built by version: {}\n", program.version);
+    source += &format!("; instructions: {}\n", program.instructions.len());
+
+    if !program.assets.is_empty() {
+        source += "\n";
+    }
+
+    for asset in &program.assets {
+        ensure!(
+            ASSET_NAME_RE.is_match(&asset.name),
+            "the asset name {:?} is not a valid symbol",
+            asset.name
+        );
+
+        let exported = asset::export(asset)?;
+        let path = format!("{}/{}.{}", asset_dir, asset.name, exported.extension);
+
+        source += &format!(".asset {} {}, {}", asset.name, quote(&path), asset.kind);
+
+        if let Some(option) = exported.option {
+            source += &format!(", {}", option);
+        }
+
+        source += "\n";
+
+        assets.push((path, exported.data));
+    }
+
+    let config = &program.config;
+
+    source += "\n";
+    source += &format!(".title {}\n", quote(&config.title));
+    source += &format!(
+        ".resolution {}, {}\n",
+        config.display.resolution.width,
+        config.display.resolution.height
+    );
+    source += &format!(".scale {:?}\n", config.display.default_scale);
+    source += &format!(".hide_cursor {}\n", config.display.hide_cursor);
+    source += &format!(".color_mode {}\n", config.display.color_mode);
+    source += &format!(".input_enabled {}\n", config.input_enabled);
+
+    for (button, key) in &config.button_map {
+        source += &format!(".map_button {}, {}\n", button, key);
+    }
+
+    source += "\n";
+
+    for (addr, instruction) in program.instructions.iter().enumerate() {
+        push_labels(&mut source, &labels, addr);
+
+        let line = format!("  {}", format_instruction(instruction, &labels));
+
+        source += &format!("{:<width$}; {}\n", line, addr, width = ADDRESS_COLUMN);
+    }
+
+    // Labels may point to the end of the program, e.g. library guards
+    for (&addr, _) in labels.range(program.instructions.len()..) {
+        push_labels(&mut source, &labels, addr);
+    }
+
+    Ok((source, assets))
+}
+
+/// Maps the addresses of all labels to their names. Addresses targeted by a
+/// jump or call without a name in the debug section get a generated one.
+fn label_names(program: &Program) -> BTreeMap<Address, Vec<String>> {
+    let mut labels: BTreeMap<Address, Vec<String>> = BTreeMap::new();
+
+    if let Some(ref debug) = program.debug {
+        for (name, &addr) in &debug.labels {
+            labels.entry(addr).or_default().push(name.clone());
+        }
+    }
+
+    for instruction in &program.instructions {
+        if let Some(addr) = instruction.target_address() {
+            if addr <= program.instructions.len() {
+                labels
+                    .entry(addr)
+                    .or_insert_with(|| vec![format!("label_{}", addr)]);
+            }
+        }
+    }
+
+    labels
+}
+
+fn push_labels(source: &mut String, labels: &BTreeMap<Address, Vec<String>>, addr: Address) {
+    if let Some(names) = labels.get(&addr) {
+        for name in names {
+            *source += &format!(".{}\n", name);
+        }
+    }
+}
+
+fn format_instruction(instruction: &Instruction, labels: &BTreeMap<Address, Vec<String>>)
+    -> String {
+    let mnemonic = instruction.mnemonic();
+
+    if let Some(addr) = instruction.target_address() {
+        return match labels.get(&addr) {
+            Some(names) => format!("{} {}", mnemonic, names[0]),
+            None => format!("; {} @{} (outside of the program)", mnemonic, addr),
+        };
+    }
+
+    match *instruction {
+        Instruction::Add(ref a, ref b) |
+        Instruction::Sub(ref a, ref b) |
+        Instruction::Div(ref a, ref b) |
+        Instruction::Mul(ref a, ref b) |
+        Instruction::Rem(ref a, ref b) |
+        Instruction::Cmp(ref a, ref b) |
+        Instruction::Mov(ref a, ref b) |
+        Instruction::Swp(ref a, ref b) => format!("{} {}, {}", mnemonic, a, b),
+        Instruction::Cast(ref target, ref type_t) => format!("{} {}, {}", mnemonic, target, type_t),
+        Instruction::Push(ref target, ref value) => format!("{} {}, {}", mnemonic, target, value),
+        Instruction::Dup(ref target) => format!("{} {}", mnemonic, target),
+        Instruction::Sig(ref signal) => format!("{} {}", mnemonic, signal),
+        Instruction::Read(ref dest, asset) => format!("{} {}, @{}", mnemonic, dest, asset),
+        Instruction::Blit(ref source, ref options) => format!("{} {}{}", mnemonic, source, options),
+        Instruction::DrawChar(Some(font)) => format!("{} @{}", mnemonic, font),
+        Instruction::DrawText(text, None) => format!("{} $vi({})", mnemonic, text),
+        Instruction::DrawText(text, Some(font)) => format!("{} $vi({}), @{}", mnemonic, text, font),
+        Instruction::Layer(number) |
+        Instruction::Scroll(number) |
+        Instruction::LayerKey(number) |
+        Instruction::SetTile(number) |
+        Instruction::GetTile(number) |
+        Instruction::ScrollMap(number) |
+        Instruction::Sprite(number) |
+        Instruction::SpriteAttr(number) |
+        Instruction::HideSprite(number) |
+        Instruction::SpriteHit(number) |
+        Instruction::BgHit(number) |
+        Instruction::Tone(number) |
+        Instruction::Envelope(number) |
+        Instruction::NoteOff(number) => format!("{} {}", mnemonic, number),
+        Instruction::Voice(channel, waveform) => format!("{} {}, {}", mnemonic, channel, waveform),
+        Instruction::SpriteHitPair(slot, other) => format!("{} {}, {}", mnemonic, slot, other),
+        Instruction::Tilemap(layer, ref source, tileset) => {
+            format!("{} {}, {}, @{}", mnemonic, layer, source, tileset)
+        }
+        _ => mnemonic.to_owned(),
+    }
+}
+
+/// Formats the given text as a BASM string literal
+fn quote(text: &str) -> String {
+    let mut quoted = String::from("\"");
+
+    for c in text.chars() {
+        match c {
+            '"' => quoted += "\\\"",
+            '\\' => quoted += "\\\\",
+            '\n' => quoted += "\\n",
+            '\t' => quoted += "\\t",
+            _ => quoted.push(c),
+        }
+    }
+
+    quoted.push('"');
+
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use basm::{self, Cache, Options};
+    use core::{Asset, GLYPH_COUNT};
+    use std::env;
+    use std::fs;
+
+    const SOURCE: &str = r#"
+        .title "Round \"trip\""
+        .resolution 320, 200
+
+        jmp _guard
+        .draw
+          push $st, #ff00ff
+          push $st, 1.0
+          push $st, inf
+          push $st, -inf
+          push $st, NaN
+          push $vi(21), ';'
+          cast $st, int
+          cmp $st, $key(42)
+          jmplteq draw
+          sig %flush_frame%
+          spritehit 0, 5
+        ret
+        ._guard
+        call draw
+        halt
+    "#;
+
+    fn round_trip(debug: bool) -> (Program, Program, String) {
+        let dir = env::temp_dir().join(format!("disasm_test_{}_{}", ::std::process::id(), debug));
+        fs::create_dir_all(&dir).unwrap();
+
+        let options = Options {
+            debug,
+            ..Options::default()
+        };
+
+        let main = dir.join("main.basm");
+        File::create(&main)
+            .unwrap()
+            .write_all(SOURCE.as_bytes())
+            .unwrap();
+
+        let mut program = basm::compile_cached(main, &options, &mut Cache::default()).unwrap();
+
+        let mut glyphs = vec![0; GLYPH_COUNT * 6];
+        glyphs[7] = 1;
+
+        program.assets = vec![
+            Asset::data("level".into(), vec![1, 2, 3]),
+            Asset::sprite("player".into(), 1, 2, vec![255, 0, 0, 255, 0, 0, 0, 0]),
+            Asset::font("small".into(), 2, 3, glyphs),
+            Asset::tileset("tiles".into(), 1, 1, vec![1, 2, 3, 255, 4, 5, 6, 0]),
+            Asset::tilemap("map".into(), 2, 1, &[0, 2]),
+            Asset::sample("jump".into(), 8000, vec![0, 128, 255]),
+        ];
+
+        let (source, assets) = disassemble(&program, "assets").unwrap();
+
+        fs::create_dir_all(dir.join("assets")).unwrap();
+
+        for (path, data) in assets {
+            fs::write(dir.join(path), data).unwrap();
+        }
+
+        let disassembled = dir.join("disassembled.basm");
+        File::create(&disassembled)
+            .unwrap()
+            .write_all(source.as_bytes())
+            .unwrap();
+
+        let recompiled =
+            basm::compile_cached(disassembled, &options, &mut Cache::default()).unwrap();
+
+        fs::remove_dir_all(dir).unwrap();
+
+        (program, recompiled, source)
+    }
+
+    #[test]
+    fn round_trip_with_generated_labels() {
+        let (program, recompiled, source) = round_trip(false);
+
+        assert!(source.contains("jmplteq label_1"));
+        assert!(source.contains(".title \"Round \\\"trip\\\"\""));
+        assert!(source.contains("push $st, -inf "));
+        assert!(source.contains("push $st, NaN "));
+
+        assert_eq!(
+            format!("{:?}", program.instructions),
+            format!("{:?}", recompiled.instructions)
+        );
+        assert_eq!(recompiled.config.title, "Round \"trip\"");
+        assert_eq!(
+            format!("{:?}", program.assets),
+            format!("{:?}", recompiled.assets)
+        );
+        assert!(source.contains(".asset tiles \"assets/tiles.png\", tileset, 1x1\n"));
+        assert_eq!(recompiled.config.display.resolution.width, 320);
+    }
+
+    #[test]
+    fn round_trip_with_debug_names() {
+        let (program, recompiled, source) = round_trip(true);
+
+        assert!(source.contains("jmplteq draw"));
+        assert!(source.contains("\n._guard\n"));
+
+        assert_eq!(
+            format!("{:?}", program.instructions),
+            format!("{:?}", recompiled.instructions)
+        );
+        assert_eq!(
+            format!("{:?}", program.debug),
+            format!("{:?}", recompiled.debug)
+        );
+    }
+}
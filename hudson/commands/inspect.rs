@@ -0,0 +1,187 @@
+use core::{self, CallGraph, Function, Program, Severity};
+use core::error::*;
+use core::typedef::*;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// The number of functions listed as the largest functions
+const LARGEST_FUNCTIONS: usize = 10;
+
+/// Prints statistics about the given image and validates it. Fails if the
+/// validation found any errors.
+pub fn inspect(input: PathBuf) -> Result<()> {
+    let program = read_image(&input)?;
+
+    print!("{}", report(&program));
+
+    let diagnostics = core::validate(&program);
+
+    for diagnostic in &diagnostics {
+        println!("{}", diagnostic);
+    }
+
+    let errors = diagnostics
+        .iter()
+        .filter(|diagnostic| diagnostic.severity == Severity::Error)
+        .count();
+
+    ensure!(errors == 0, "image validation failed with {} error(s)", errors);
+
+    Ok(())
+}
+
+/// Reads the given image. Unlike after loading it in the VM, the program keeps
+/// the format version the image was written in.
+pub fn read_image(input: &Path) -> Result<Program> {
+    let mut file = File::open(input).chain_err(|| "unable to open image file")?;
+
+    let mut buf = Vec::new();
+
+    file.read_to_end(&mut buf)
+        .chain_err(|| "unable to read image file")?;
+
+    let (mut program, format_version) = Program::decode_versioned(&buf)?;
+    program.format_version = format_version;
+
+    Ok(program)
+}
+
+/// Formats the instruction count, the instruction histogram, the call graph
+/// roots and the largest functions of the program
+fn report(program: &Program) -> String {
+    let names = label_names(program);
+    let name = |function: &Function| match names.get(&function.address) {
+        Some(name) => format!("{} @{}", name, function.address),
+        None => format!("@{}", function.address),
+    };
+
+    let mut report = String::new();
+
+    report += &format!("preamble:     {}\n", program.preamble);
+    report += &format!("format:       {}\n", program.format_version);
+    report += &format!("built by:     {}\n", program.version);
+    report += &format!("instructions: {}\n", program.instructions.len());
+
+    if !program.assets.is_empty() {
+        report += "\nassets:\n";
+
+        for (index, asset) in program.assets.iter().enumerate() {
+            report += &format!(
+                "  @{:<4}{:<8}{:>10} bytes  {}x{}  {}\n",
+                index,
+                asset.kind,
+                asset.data.len(),
+                asset.width,
+                asset.height,
+                asset.name
+            );
+        }
+    }
+
+    let mut histogram: HashMap<&str, usize> = HashMap::new();
+
+    for instruction in &program.instructions {
+        *histogram.entry(instruction.mnemonic()).or_default() += 1;
+    }
+
+    let mut histogram: Vec<(&str, usize)> = histogram.into_iter().collect();
+    histogram.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+
+    report += "\nhistogram:\n";
+
+    for (mnemonic, count) in histogram {
+        report += &format!("  {:<10}{:>8}\n", mnemonic, count);
+    }
+
+    let graph = CallGraph::new(program);
+
+    report += "\nroots:\n";
+
+    for function in graph.roots() {
+        report += &format!("  {}\n", name(function));
+    }
+
+    report += "\nlargest functions:\n";
+
+    for function in graph.largest().into_iter().take(LARGEST_FUNCTIONS) {
+        report += &format!(
+            "  {:>8} instructions {:>4} callees  {}\n",
+            function.size,
+            function.calls.len(),
+            name(function)
+        );
+    }
+
+    report += "\n";
+
+    report
+}
+
+/// Maps addresses to the first label name of the debug section
+fn label_names(program: &Program) -> BTreeMap<Address, String> {
+    let mut names = BTreeMap::new();
+
+    if let Some(ref debug) = program.debug {
+        for (name, &addr) in &debug.labels {
+            names.entry(addr).or_insert_with(|| name.clone());
+        }
+    }
+
+    names
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::{DebugInfo, ImageBuilder, IMAGE_FORMAT_VERSION};
+    use std::{env, fs};
+
+    #[test]
+    fn report_with_debug_names() {
+        let mut builder = ImageBuilder::new();
+        builder.call(3);
+        builder.call(3);
+        builder.halt();
+        builder.nop();
+        builder.ret();
+
+        let mut labels = BTreeMap::new();
+        labels.insert("update".to_owned(), 3);
+
+        let program = Program {
+            debug: Some(DebugInfo { labels }),
+            ..builder.gen_program()
+        };
+
+        let report = report(&program);
+
+        assert!(report.contains("instructions: 5\n"));
+        assert!(report.contains("  call             2\n"));
+        assert!(report.contains("roots:\n  @0\n\n"));
+        assert!(report.contains("  update @3"));
+    }
+
+    #[test]
+    fn older_format_version() {
+        let mut builder = ImageBuilder::new();
+        builder.halt();
+
+        let program = Program {
+            format_version: IMAGE_FORMAT_VERSION - 1,
+            ..builder.gen_program()
+        };
+
+        let path = env::temp_dir().join(format!("inspect_test_{}.img", ::std::process::id()));
+        fs::write(&path, program.encode().unwrap()).unwrap();
+
+        let program = read_image(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert!(report(&program).contains(&format!("format:       {}\n", IMAGE_FORMAT_VERSION - 1)));
+
+        let messages: Vec<String> = core::validate(&program).iter().map(|d| d.to_string()).collect();
+        assert!(messages[0].starts_with("warning: image format version"));
+    }
+}
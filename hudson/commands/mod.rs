@@ -1,5 +1,13 @@
+mod build;
+mod disasm;
+mod inspect;
 mod pack;
 mod compile;
+mod upgrade;
 
+pub use self::build::*;
 pub use self::compile::*;
+pub use self::disasm::*;
+pub use self::inspect::*;
 pub use self::pack::*;
+pub use self::upgrade::*;
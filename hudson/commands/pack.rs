@@ -1,16 +1,28 @@
+use asset;
 use core::error::*;
 use image::{self, DynamicImage, RgbImage, RgbaImage};
-use std::fs::File;
+use std::collections::BTreeSet;
+use std::fs::{self, File};
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use tracker::{ChannelCommand, Module, Song, MAX_VOLUME, PAL_CLOCK};
 
 const BASM_EXTENSION: &str = "basm";
 
-#[derive(Debug)]
+/// The extension of the data asset holding the events of a packed song
+const EVENTS_EXTENSION: &str = "events";
+
+/// The lengths of an offset and an event in the events of a song in bytes
+const OFFSET_LEN: usize = 3;
+const EVENT_LEN: usize = 4;
+
+#[derive(Debug, Clone, Copy)]
 pub enum PackingType {
     Static,
     Dynamic,
+    /// Embeds a WAV sound as a sample or a MOD song as samples and a player
+    Audio,
 }
 
 impl FromStr for PackingType {
@@ -19,7 +31,8 @@ impl FromStr for PackingType {
         match s {
             "static" => Ok(PackingType::Static),
             "dynamic" => Ok(PackingType::Dynamic),
-            _ => bail!("unknown packing type. Packing type must be one of [static, dynamic]"),
+            "audio" => Ok(PackingType::Audio),
+            _ => bail!("unknown packing type. Packing type must be one of [static, dynamic, audio]"),
         }
     }
 }
@@ -46,6 +59,22 @@ pub fn pack(
 
     let output = output.unwrap_or(fallback_output);
 
+    let file_contents = if let PackingType::Audio = pack_type {
+        pack_audio(&input, file_name, &output)?
+    } else {
+        pack_image(pack_type, input, file_name)?
+    };
+
+    let mut file = File::create(output).chain_err(|| "failed to create file")?;
+
+    file.write_all(file_contents.as_bytes())
+        .chain_err(|| "unable to write to file")?;
+
+    Ok(())
+}
+
+/// Writes a function drawing the pixels of an image
+fn pack_image(pack_type: PackingType, input: PathBuf, file_name: &str) -> Result<String> {
     let image_data = image::open(input).chain_err(|| "unable to open image file")?;
 
     let packed_image = if let DynamicImage::ImageRgb8(rgb_image) = image_data {
@@ -103,6 +132,7 @@ pub fn pack(
             file_contents += "\npush $st, @2";
             file_contents += "\nsub $bp, $st";
         }
+        PackingType::Audio => unreachable!(),
     }
 
     file_contents += "\nret";
@@ -110,12 +140,350 @@ pub fn pack(
     file_contents += format!("\n.{}", guard_name.clone()).as_str();
     file_contents += "\n";
 
-    let mut file = File::create(output).chain_err(|| "failed to create file")?;
+    Ok(file_contents)
+}
 
-    file.write_all(file_contents.as_bytes())
-        .chain_err(|| "unable to write to file")?;
+/// Writes the `.asset` directives embedding a WAV sound or the instruments of
+/// a MOD song, together with a macro playing the sound or a function playing
+/// the song
+fn pack_audio(input: &Path, file_name: &str, output: &Path) -> Result<String> {
+    let data = fs::read(input).chain_err(|| "unable to open audio file")?;
 
-    Ok(())
+    let extension = input
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| extension.to_lowercase());
+
+    let input_path = asset_path(input, output)?;
+    let asset_name = symbol_name(file_name);
+
+    match extension.as_deref() {
+        Some("wav") => {
+            let sample = asset::load_sample(&asset_name, input, &data)?;
+
+            println!("Sample of {} Hz with {} samples", sample.width, sample.data.len());
+
+            Ok(pack_sound(&asset_name, &input_path))
+        }
+        Some("mod") => {
+            let module = Module::parse(&data)?;
+
+            println!("Song {:?}", module.title);
+
+            let song = module.song();
+            let (actions, events) = encode_song(&song)?;
+
+            let events_path = output.with_extension(EVENTS_EXTENSION);
+
+            fs::write(&events_path, events).chain_err(|| "unable to write the song events")?;
+
+            let events_path = asset_path(&events_path, output)?;
+
+            Ok(pack_song(&song, &actions, file_name, &asset_name, &input_path, &events_path))
+        }
+        _ => bail!("audio files must be WAV sounds or MOD songs"),
+    }
+}
+
+/// Writes a macro, that plays the sound on the channel given as its argument
+fn pack_sound(asset_name: &str, asset_path: &str) -> String {
+    let mut file_contents = String::new();
+
+    file_contents += &format!("\n.asset {} {}, sample", asset_name, asset_path);
+
+    file_contents += &format!("\n\nmacro play_{}(CHANNEL)", asset_name);
+    file_contents += &format!("\n  voice CHANNEL, {}", asset_name);
+    file_contents += "\n  push $st, 0";
+    file_contents += "\n  push $st, 1.0";
+    file_contents += "\n  tone CHANNEL";
+    file_contents += "\nendmacro\n";
+
+    file_contents
+}
+
+/// An audio instruction the player of a song dispatches to. The channel and
+/// the waveform are operands of the instructions, so every combination used
+/// by the song gets its own branch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Action {
+    Voice(usize, usize),
+    Tone(usize),
+    NoteOff(usize),
+}
+
+/// Encodes the events of a song as a table holding the offset of the first
+/// event of every frame, followed by the events. The table has an entry more
+/// than frames with events, which marks the end of the last frame. Offsets
+/// take 3 bytes and events 4: the index of their action, the volume and the
+/// period of a tone.
+fn encode_song(song: &Song) -> Result<(Vec<Action>, Vec<u8>)> {
+    let actions: BTreeSet<Action> = song.events
+        .values()
+        .flat_map(|commands| commands.iter())
+        .map(|command| match *command {
+            ChannelCommand::Voice(channel, instrument) => Action::Voice(channel, instrument),
+            ChannelCommand::Tone { channel, .. } => Action::Tone(channel),
+            ChannelCommand::NoteOff(channel) => Action::NoteOff(channel),
+        })
+        .collect();
+
+    let actions: Vec<Action> = actions.into_iter().collect();
+
+    ensure!(actions.len() <= 256, "the song uses more than 256 different audio instructions");
+
+    let frames = song.events.keys().next_back().map_or(0, |&frame| frame + 1);
+
+    let mut table = Vec::with_capacity((frames + 1) * OFFSET_LEN);
+    let mut events = Vec::new();
+
+    let event_offset = |events: &Vec<u8>| -> Result<[u8; OFFSET_LEN]> {
+        let offset = (frames + 1) * OFFSET_LEN + events.len();
+
+        ensure!(offset < 1 << 24, "the song has too many events");
+
+        Ok([(offset >> 16) as u8, (offset >> 8) as u8, offset as u8])
+    };
+
+    for frame in 0..frames {
+        table.extend_from_slice(&event_offset(&events)?);
+
+        for command in song.events.get(&frame).into_iter().flatten() {
+            let (action, volume, period) = match *command {
+                ChannelCommand::Voice(channel, instrument) => {
+                    (Action::Voice(channel, instrument), 0, 0)
+                }
+                ChannelCommand::Tone {
+                    channel,
+                    frequency,
+                    volume,
+                } => (
+                    Action::Tone(channel),
+                    (volume * f64::from(MAX_VOLUME)).round() as u8,
+                    (PAL_CLOCK / (2.0 * frequency)).round() as u16,
+                ),
+                ChannelCommand::NoteOff(channel) => (Action::NoteOff(channel), 0, 0),
+            };
+
+            let index = actions.binary_search(&action).unwrap_or_default();
+
+            events.extend_from_slice(&[index as u8, volume, (period >> 8) as u8, period as u8]);
+        }
+    }
+
+    table.extend_from_slice(&event_offset(&events)?);
+    table.extend(events);
+
+    Ok((actions, table))
+}
+
+/// Writes a function, that plays the events of the song in the frame on top
+/// of the stack and pushes whether the song is over. The first frame is @0.
+/// The events are read from a data asset and dispatched to the instructions
+/// by a binary search over their actions.
+fn pack_song(
+    song: &Song, actions: &[Action], file_name: &str, asset_name: &str, asset_path: &str,
+    events_path: &str,
+) -> String {
+    println!("{} frames with {} events", song.frames, song.events.len());
+
+    if song.ignored_effects > 0 {
+        println!("warning: ignored {} unsupported effects", song.ignored_effects);
+    }
+
+    let instruments: BTreeSet<usize> = actions
+        .iter()
+        .filter_map(|action| match *action {
+            Action::Voice(_, instrument) => Some(instrument),
+            _ => None,
+        })
+        .collect();
+
+    let frames = song.events.keys().next_back().map_or(0, |&frame| frame + 1);
+
+    let module_name = format!("assets.music.play_{}", file_name);
+    let guard_name = format!("_{}", module_name.replace('.', "_"));
+    let events_name = format!("{}_events", asset_name);
+
+    let mut file_contents = String::new();
+
+    for instrument in &instruments {
+        file_contents += &format!(
+            "\n.asset {}_{} {}, sample, {}",
+            asset_name,
+            instrument,
+            asset_path,
+            instrument
+        );
+    }
+
+    file_contents += &format!("\n.asset {} {}, data", events_name, events_path);
+
+    file_contents += &format!("\n\njmp {}", guard_name);
+    file_contents += &format!("\n.{}", module_name);
+
+    file_contents += "\npush $st, @5";
+    file_contents += "\nadd $bp, $st";
+    file_contents += "\nmov $vi(20), $st"; // frame
+
+    file_contents += &format!("\npush $st, @{}", frames);
+    file_contents += "\ncmp $st, $vi(20)";
+    file_contents += "\nmov $vi(21), $st";
+    file_contents += &format!("\njmplteq {}__end", module_name);
+
+    file_contents += "\ndup $vi(20)";
+    file_contents += &format!("\npush $st, @{}", OFFSET_LEN);
+    file_contents += "\nmul $st, $st";
+    file_contents += "\nmov $vi(22), $st"; // table entry of the frame
+
+    file_contents += &read_number(&events_name, OFFSET_LEN, OFFSET_LEN);
+    file_contents += "\ncast $st, addr";
+    file_contents += "\nmov $vi(23), $st"; // end of the events
+
+    file_contents += &read_number(&events_name, 0, OFFSET_LEN);
+    file_contents += "\ncast $st, addr";
+    file_contents += "\nmov $vi(22), $st"; // current event
+
+    file_contents += &format!("\n.{}__next", module_name);
+    file_contents += "\ndup $vi(22)";
+    file_contents += "\ncmp $st, $vi(23)";
+    file_contents += "\nmov $vi(21), $st";
+    file_contents += &format!("\njmpgteq {}__end", module_name);
+
+    file_contents += "\ndup $vi(22)";
+    file_contents += &format!("\nread $st, {}", events_name);
+    file_contents += "\nmov $vi(24), $st"; // action
+
+    file_contents += &dispatch(&module_name, 0, actions.len());
+
+    for (index, action) in actions.iter().enumerate() {
+        file_contents += &format!("\n.{}__action_{}", module_name, index);
+
+        match *action {
+            Action::Voice(channel, instrument) => {
+                file_contents += &format!("\nvoice {}, {}_{}", channel, asset_name, instrument);
+            }
+            Action::Tone(channel) => {
+                file_contents += &format!("\npush $st, {:?}", PAL_CLOCK / 2.0);
+                file_contents += &read_number(&events_name, 2, 2);
+                file_contents += "\ncast $st, float";
+                file_contents += "\ndiv $st, $st";
+
+                file_contents += &read_number(&events_name, 1, 1);
+                file_contents += "\ncast $st, float";
+                file_contents += &format!("\npush $st, {:.1}", f64::from(MAX_VOLUME));
+                file_contents += "\ndiv $st, $st";
+
+                file_contents += &format!("\ntone {}", channel);
+            }
+            Action::NoteOff(channel) => {
+                file_contents += &format!("\nnoteoff {}", channel);
+            }
+        }
+
+        file_contents += &format!("\njmp {}__advance", module_name);
+    }
+
+    file_contents += &format!("\n.{}__advance", module_name);
+    file_contents += &format!("\npush $st, @{}", EVENT_LEN);
+    file_contents += "\nadd $vi(22), $st";
+    file_contents += &format!("\njmp {}__next", module_name);
+
+    file_contents += &format!("\n.{}__end", module_name);
+    file_contents += &format!("\npush $st, @{}", song.frames);
+    file_contents += "\ncmp $st, $vi(20)";
+    file_contents += "\nmov $vi(21), $st";
+    file_contents += &format!("\njmplteq {}__over", module_name);
+    file_contents += "\npush $st, false";
+    file_contents += &format!("\njmp {}__return", module_name);
+    file_contents += &format!("\n.{}__over", module_name);
+    file_contents += "\npush $st, true";
+    file_contents += &format!("\n.{}__return", module_name);
+
+    file_contents += "\npush $st, @5";
+    file_contents += "\nsub $bp, $st";
+    file_contents += "\nret";
+
+    file_contents += &format!("\n.{}", guard_name);
+    file_contents += "\n";
+
+    file_contents
+}
+
+/// Writes the instructions pushing the big endian integer of `len` bytes at
+/// `offset` bytes behind the event in `$vi(22)`
+fn read_number(events_name: &str, offset: usize, len: usize) -> String {
+    let mut file_contents = String::from("\npush $st, 0");
+
+    for index in offset..(offset + len) {
+        file_contents += "\npush $st, 256";
+        file_contents += "\nmul $st, $st";
+        file_contents += "\ndup $vi(22)";
+        file_contents += &format!("\npush $st, @{}", index);
+        file_contents += "\nadd $st, $st";
+        file_contents += &format!("\nread $st, {}", events_name);
+        file_contents += "\nadd $st, $st";
+    }
+
+    file_contents
+}
+
+/// Writes a binary search jumping to the action in `$vi(24)`, which is one
+/// of the actions from `start` up to `end`
+fn dispatch(module_name: &str, start: usize, end: usize) -> String {
+    let mut file_contents = format!("\n.{}__dispatch_{}_{}", module_name, start, end);
+
+    if end <= start + 1 {
+        file_contents += &format!("\njmp {}__action_{}", module_name, start);
+
+        return file_contents;
+    }
+
+    let middle = (start + end) / 2;
+
+    file_contents += &format!("\npush $st, {}", middle);
+    file_contents += "\ncmp $st, $vi(24)";
+    file_contents += "\nmov $vi(21), $st";
+    file_contents += &format!("\njmpgt {}__dispatch_{}_{}", module_name, start, middle);
+
+    file_contents += &dispatch(module_name, middle, end);
+    file_contents += &dispatch(module_name, start, middle);
+
+    file_contents
+}
+
+/// Returns the file name of an `.asset` directive in the given output file
+/// referring to the input file. Files in other directories are referred to by
+/// their absolute path.
+fn asset_path(input: &Path, output: &Path) -> Result<String> {
+    let input = fs::canonicalize(input).chain_err(|| "unable to resolve the audio file")?;
+
+    let output_dir = output
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or_else(|| Path::new("."));
+
+    let path = match (fs::canonicalize(output_dir), input.parent(), input.file_name()) {
+        (Ok(ref dir), Some(parent), Some(file_name)) if dir == parent => PathBuf::from(file_name),
+        _ => input.clone(),
+    };
+
+    let path = path.to_str().ok_or("unable to convert file name")?;
+
+    Ok(format!("{:?}", path))
+}
+
+/// Turns a file name into the name of an asset
+fn symbol_name(file_name: &str) -> String {
+    let mut name: String = file_name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+
+    if name.starts_with(|c: char| c.is_ascii_digit()) {
+        name.insert(0, '_');
+    }
+
+    name
 }
 
 fn pack_rgb(image: RgbImage) -> Result<Vec<(usize, usize, (u8, u8, u8))>> {
@@ -146,3 +514,85 @@ fn pack_rgba(image: RgbaImage) -> Result<Vec<(usize, usize, (u8, u8, u8))>> {
 
     Ok(pixels)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use basm::{self, Cache, Options};
+    use core::{AssetKind, Instruction};
+    use std::env;
+    use tracker::tests::build_mod;
+
+    #[test]
+    fn audio() {
+        let dir = env::temp_dir().join(format!("pack_test_{}", ::std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let song = dir.join("song.mod");
+        fs::write(
+            &song,
+            build_mod(&[
+                (0, 0, 1, 428, 0, 0),
+                (0, 1, 1, 214, 0xc, 32),
+                (2, 0, 0, 0, 0xc, 0),
+                (4, 2, 1, 856, 0, 0),
+            ]),
+        ).unwrap();
+
+        let mut wav = b"RIFF\0\0\0\0WAVEfmt ".to_vec();
+        wav.extend_from_slice(&[16, 0, 0, 0, 1, 0, 1, 0, 0x40, 0x1f, 0, 0, 0x40, 0x1f, 0, 0, 1, 0, 8, 0]);
+        wav.extend_from_slice(b"data\x03\0\0\0");
+        wav.extend_from_slice(&[0, 128, 255]);
+
+        let sound = dir.join("jump.wav");
+        fs::write(&sound, wav).unwrap();
+
+        pack(Some(PackingType::Audio), song, None).unwrap();
+        pack(Some(PackingType::Audio), sound, None).unwrap();
+
+        let main = dir.join("main.basm");
+        fs::write(
+            &main,
+            "include! song\ninclude! jump\n\
+             play_jump_wav!(3)\npush $st, @0\ncall assets.music.play_song.mod\nhalt",
+        ).unwrap();
+
+        let program = basm::compile_cached(main, &Options::default(), &mut Cache::default()).unwrap();
+
+        let kinds: Vec<(&str, AssetKind)> = program
+            .assets
+            .iter()
+            .map(|asset| (asset.name.as_str(), asset.kind))
+            .collect();
+
+        assert_eq!(
+            kinds,
+            vec![
+                ("song_mod_1", AssetKind::Sample),
+                ("song_mod_events", AssetKind::Data),
+                ("jump_wav", AssetKind::Sample),
+            ]
+        );
+
+        // Rows last 6 ticks of 1/50 seconds, so the last event is in frame 29.
+        // The table is followed by the voices and tones of the first frame.
+        let events = &program.assets[1].data;
+        let table_len = 31 * OFFSET_LEN;
+
+        assert_eq!(&events[..OFFSET_LEN], &[0, 0, table_len as u8]);
+        assert_eq!(events.len(), table_len + 7 * EVENT_LEN);
+        assert_eq!(&events[table_len + 3 * EVENT_LEN..][..EVENT_LEN], &[4, 32, 0, 214]);
+
+        // The player reads the events instead of comparing the frame with
+        // every frame holding events
+        let jumps = program
+            .instructions
+            .iter()
+            .filter(|instruction| matches!(**instruction, Instruction::JmpEq(..)))
+            .count();
+
+        assert_eq!(jumps, 0);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
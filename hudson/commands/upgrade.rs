@@ -0,0 +1,38 @@
+use commands::write_atomically;
+use core::{Program, IMAGE_FORMAT_VERSION};
+use core::error::*;
+use std::fs::File;
+use std::io::Read;
+use std::path::PathBuf;
+
+/// Rewrites the given image in the current image format. The image is
+/// replaced unless an output is given.
+pub fn upgrade(input: PathBuf, output: Option<PathBuf>) -> Result<()> {
+    let mut file = File::open(&input).chain_err(|| "unable to open image file")?;
+
+    let mut buf = Vec::new();
+
+    file.read_to_end(&mut buf)
+        .chain_err(|| "unable to read image file")?;
+
+    let (program, format_version) = Program::decode_versioned(&buf)?;
+
+    if format_version == IMAGE_FORMAT_VERSION && output.is_none() {
+        println!("CURRENT {:?} already uses format {}", input, format_version);
+
+        return Ok(());
+    }
+
+    let output = output.unwrap_or(input);
+
+    write_atomically(&output, &program.encode()?)?;
+
+    println!(
+        "UPGRADE {:?} from format {} to {}",
+        output,
+        format_version,
+        IMAGE_FORMAT_VERSION
+    );
+
+    Ok(())
+}
@@ -0,0 +1,351 @@
+//! Compile-time expressions used in BASM directives and operands
+
+use core::error::*;
+use core::typedef::*;
+use std::fmt;
+
+/// The result of a compile-time expression
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Number {
+    Integer(Integer),
+    Float(Float),
+}
+
+impl Number {
+    pub fn as_integer(&self) -> Integer {
+        match *self {
+            Number::Integer(integer) => integer,
+            Number::Float(float) => float.round() as Integer,
+        }
+    }
+
+    pub fn as_float(&self) -> Float {
+        match *self {
+            Number::Integer(integer) => integer as Float,
+            Number::Float(float) => float,
+        }
+    }
+
+    pub fn is_true(&self) -> bool {
+        match *self {
+            Number::Integer(integer) => integer != 0,
+            Number::Float(float) => float != 0.0,
+        }
+    }
+
+    fn from_bool(boolean: bool) -> Number {
+        Number::Integer(boolean as Integer)
+    }
+}
+
+impl fmt::Display for Number {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Number::Integer(integer) => write!(f, "{}", integer),
+            Number::Float(float) => {
+                let text = format!("{}", float);
+
+                if text.contains('.') || !float.is_finite() {
+                    write!(f, "{}", text)
+                } else {
+                    write!(f, "{}.0", text)
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(Number),
+    Ident(String),
+    Op(&'static str),
+    OpenParen,
+    CloseParen,
+}
+
+/// Operators sorted so that longer operators are matched first
+const OPERATORS: &[&str] = &[
+    "==", "!=", "<=", ">=", "&&", "||", "<<", ">>", "+", "-", "*", "/", "%", "<", ">", "&", "|",
+    "^", "!", "~",
+];
+
+/// The binary operators, grouped by precedence from the loosest to the
+/// tightest binding
+const BINARY_PRECEDENCE: &[&[&str]] = &[
+    &["||"],
+    &["&&"],
+    &["==", "!=", "<", ">", "<=", ">="],
+    &["|"],
+    &["^"],
+    &["&"],
+    &["<<", ">>"],
+    &["+", "-"],
+    &["*", "/", "%"],
+];
+
+/// Evaluates the given expression. All symbols have to be substituted before,
+/// so any remaining identifier is reported as unknown.
+pub fn eval(input: &str) -> Result<Number> {
+    let tokens = tokenize(input)?;
+
+    ensure!(!tokens.is_empty(), "empty expression");
+
+    let mut parser = Parser { tokens, pos: 0 };
+
+    let result = parser.binary(0)?;
+
+    if let Some(token) = parser.tokens.get(parser.pos) {
+        bail!("unexpected {:?} in expression {:?}", token, input);
+    }
+
+    Ok(result)
+}
+
+/// Returns true if the given text contains any character that may only
+/// appear in an expression
+pub fn has_operator(input: &str) -> bool {
+    input.chars().any(|c| "+-*/%()<>=!&|^~".contains(c))
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>> {
+    let chars: Vec<char> = input.chars().collect();
+    let mut tokens = Vec::new();
+    let mut pos = 0;
+
+    'outer: while pos < chars.len() {
+        let c = chars[pos];
+
+        if c.is_whitespace() {
+            pos += 1;
+        } else if c.is_ascii_digit() {
+            let start = pos;
+
+            if c == '0' && chars.get(pos + 1) == Some(&'x') {
+                pos += 2;
+                while pos < chars.len() && chars[pos].is_ascii_hexdigit() {
+                    pos += 1;
+                }
+
+                let digits: String = chars[(start + 2)..pos].iter().collect();
+                let integer = Integer::from_str_radix(&digits, 16)
+                    .chain_err(|| format!("invalid hex number 0x{}", digits))?;
+
+                tokens.push(Token::Number(Number::Integer(integer)));
+                continue;
+            }
+
+            while pos < chars.len() && (chars[pos].is_ascii_digit() || chars[pos] == '.') {
+                pos += 1;
+            }
+
+            let text: String = chars[start..pos].iter().collect();
+
+            let number = if text.contains('.') {
+                Number::Float(text.parse().chain_err(|| format!("invalid number {}", text))?)
+            } else {
+                Number::Integer(text.parse().chain_err(|| format!("invalid number {}", text))?)
+            };
+
+            tokens.push(Token::Number(number));
+        } else if c.is_alphabetic() || c == '_' {
+            let start = pos;
+
+            while pos < chars.len() && (chars[pos].is_alphanumeric() || chars[pos] == '_') {
+                pos += 1;
+            }
+
+            tokens.push(Token::Ident(chars[start..pos].iter().collect()));
+        } else if c == '\'' {
+            if chars.get(pos + 2) != Some(&'\'') {
+                bail!("invalid char literal in expression {:?}", input);
+            }
+
+            tokens.push(Token::Number(Number::Integer(chars[pos + 1] as Integer)));
+            pos += 3;
+        } else if c == '(' {
+            tokens.push(Token::OpenParen);
+            pos += 1;
+        } else if c == ')' {
+            tokens.push(Token::CloseParen);
+            pos += 1;
+        } else {
+            for op in OPERATORS {
+                let op_chars: Vec<char> = op.chars().collect();
+
+                if chars[pos..].starts_with(&op_chars) {
+                    tokens.push(Token::Op(op));
+                    pos += op_chars.len();
+                    continue 'outer;
+                }
+            }
+
+            bail!("unexpected character {:?} in expression {:?}", c, input);
+        }
+    }
+
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek_op(&self, ops: &[&str]) -> Option<&'static str> {
+        if let Some(&Token::Op(op)) = self.tokens.get(self.pos) {
+            if ops.contains(&op) {
+                return Some(op);
+            }
+        }
+
+        None
+    }
+
+    fn binary(&mut self, level: usize) -> Result<Number> {
+        if level >= BINARY_PRECEDENCE.len() {
+            return self.unary();
+        }
+
+        let mut lhs = self.binary(level + 1)?;
+
+        while let Some(op) = self.peek_op(BINARY_PRECEDENCE[level]) {
+            self.pos += 1;
+            let rhs = self.binary(level + 1)?;
+            lhs = apply(op, lhs, rhs)?;
+        }
+
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Number> {
+        if let Some(op) = self.peek_op(&["-", "!", "~", "+"]) {
+            self.pos += 1;
+            let value = self.unary()?;
+
+            return Ok(match (op, value) {
+                ("-", Number::Integer(integer)) => Number::Integer(integer.wrapping_neg()),
+                ("-", Number::Float(float)) => Number::Float(-float),
+                ("!", value) => Number::from_bool(!value.is_true()),
+                ("~", value) => Number::Integer(!value.as_integer()),
+                (_, value) => value,
+            });
+        }
+
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Number> {
+        let token = if let Some(token) = self.tokens.get(self.pos) {
+            token.clone()
+        } else {
+            bail!("unexpected end of expression");
+        };
+
+        self.pos += 1;
+
+        match token {
+            Token::Number(number) => Ok(number),
+            Token::OpenParen => {
+                let value = self.binary(0)?;
+
+                if self.tokens.get(self.pos) != Some(&Token::CloseParen) {
+                    bail!("missing closing parenthesis");
+                }
+
+                self.pos += 1;
+
+                Ok(value)
+            }
+            Token::Ident(ident) => bail!("unknown symbol {:?}", ident),
+            other => bail!("unexpected {:?} in expression", other),
+        }
+    }
+}
+
+fn apply(op: &str, lhs: Number, rhs: Number) -> Result<Number> {
+    if let (Number::Integer(lhs), Number::Integer(rhs)) = (lhs, rhs) {
+        let result = match op {
+            "+" => lhs.wrapping_add(rhs),
+            "-" => lhs.wrapping_sub(rhs),
+            "*" => lhs.wrapping_mul(rhs),
+            "/" | "%" if rhs == 0 => bail!("division by zero"),
+            "/" => lhs.checked_div(rhs).ok_or("integer overflow in division")?,
+            "%" => lhs.checked_rem(rhs).ok_or("integer overflow in division")?,
+            "<<" => lhs.wrapping_shl(rhs as u32),
+            ">>" => lhs.wrapping_shr(rhs as u32),
+            "&" => lhs & rhs,
+            "|" => lhs | rhs,
+            "^" => lhs ^ rhs,
+            _ => return Ok(compare(op, lhs as Float, rhs as Float)),
+        };
+
+        return Ok(Number::Integer(result));
+    }
+
+    let (lhs, rhs) = (lhs.as_float(), rhs.as_float());
+
+    Ok(match op {
+        "+" => Number::Float(lhs + rhs),
+        "-" => Number::Float(lhs - rhs),
+        "*" => Number::Float(lhs * rhs),
+        "/" => Number::Float(lhs / rhs),
+        "%" => Number::Float(lhs % rhs),
+        "<<" | ">>" | "&" | "|" | "^" => bail!("operator {} requires integer operands", op),
+        _ => compare(op, lhs, rhs),
+    })
+}
+
+fn compare(op: &str, lhs: Float, rhs: Float) -> Number {
+    Number::from_bool(match op {
+        "==" => lhs == rhs,
+        "!=" => lhs != rhs,
+        "<" => lhs < rhs,
+        ">" => lhs > rhs,
+        "<=" => lhs <= rhs,
+        ">=" => lhs >= rhs,
+        "&&" => lhs != 0.0 && rhs != 0.0,
+        _ => lhs != 0.0 || rhs != 0.0,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn arithmetic() {
+        assert_eq!(eval("160*100").unwrap(), Number::Integer(16000));
+        assert_eq!(eval("2 + 3 * 4").unwrap(), Number::Integer(14));
+        assert_eq!(eval("(2 + 3) * 4").unwrap(), Number::Integer(20));
+        assert_eq!(eval("-7 / 2").unwrap(), Number::Integer(-3));
+        assert_eq!(eval("1.5 * 2").unwrap(), Number::Float(3.0));
+        assert_eq!(eval("0x10 | 1 << 2").unwrap(), Number::Integer(20));
+        assert_eq!(eval("'a' + 1").unwrap(), Number::Integer(98));
+    }
+
+    #[test]
+    fn logic() {
+        assert_eq!(eval("1 < 2 && 3 >= 3").unwrap(), Number::Integer(1));
+        assert_eq!(eval("!(2 == 2) || 0").unwrap(), Number::Integer(0));
+    }
+
+    #[test]
+    fn errors() {
+        assert!(eval("WIDTH * 2").is_err());
+        assert!(eval("1 / 0").is_err());
+        assert!(eval("(-9223372036854775807 - 1) / -1").is_err());
+        assert!(eval("(-9223372036854775807 - 1) % -1").is_err());
+        assert!(eval("(1 + 2").is_err());
+        assert!(eval("1 2").is_err());
+        assert!(eval("").is_err());
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(Number::Float(3.0).to_string(), "3.0");
+        assert_eq!(Number::Float(-0.25).to_string(), "-0.25");
+        assert_eq!(Number::Integer(-12).to_string(), "-12");
+    }
+}
@@ -8,15 +8,28 @@ extern crate pest;
 #[macro_use]
 extern crate pest_derive;
 extern crate regex;
+extern crate roxmltree;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+#[macro_use]
+extern crate serde_json;
 #[macro_use]
 extern crate structopt;
+extern crate toml;
 
+mod asset;
 mod commands;
 mod basm;
 mod beast;
+mod expr;
+mod manifest;
 mod mnemonic;
+mod tracker;
 
-use commands::{Lang, PackingType};
+use basm::Define;
+use commands::{ConfigOverrides, Lang, PackingType};
+use core::{ColorMode, DisplayResolution};
 use core::error::*;
 use std::path::PathBuf;
 use structopt::StructOpt;
@@ -49,10 +62,34 @@ enum Opt {
         lang: Option<Lang>,
         #[structopt(short = "o", parse(from_os_str))]
         output: Option<PathBuf>,
+        #[structopt(short = "D", value_name = "NAME=VALUE", number_of_values = 1,
+                    help = "Defines a constant for conditional assembly. The value defaults to 1")]
+        defines: Vec<Define>,
+        #[structopt(short = "I", value_name = "DIR", number_of_values = 1, parse(from_os_str),
+                    help = "Adds a directory to the include search path")]
+        include_paths: Vec<PathBuf>,
+        #[structopt(short = "g", help = "Adds the names of all labels to the image")]
+        debug: bool,
+        #[structopt(long = "title", help = "Overrides the window title of the program")]
+        title: Option<String>,
+        #[structopt(long = "resolution", value_name = "WIDTHxHEIGHT",
+                    help = "Overrides the display resolution of the program")]
+        resolution: Option<DisplayResolution>,
+        #[structopt(long = "scale", help = "Overrides the default display scale of the program")]
+        scale: Option<f64>,
+        #[structopt(long = "color-mode", value_name = "MODE",
+                    help = "Overrides the color mode of the program [direct, indexed16, indexed256]")]
+        color_mode: Option<ColorMode>,
+        #[structopt(long = "hide-cursor", value_name = "BOOL",
+                    help = "Overrides whether the mouse cursor is hidden inside the window")]
+        hide_cursor: Option<bool>,
+        #[structopt(long = "input-enabled", value_name = "BOOL",
+                    help = "Overrides whether input events are sent to the program")]
+        input_enabled: Option<bool>,
         #[structopt(parse(from_os_str))]
         input: PathBuf,
     },
-    #[structopt(name = "pack", about = "Write texture functions from images", alias = "p")]
+    #[structopt(name = "pack", about = "Write texture functions from images and embed sounds and songs", alias = "p")]
     Pack {
         #[structopt(long = "type", short = "t", value_name = "type")]
         packing_type: Option<PackingType>,
@@ -61,6 +98,41 @@ enum Opt {
         #[structopt(parse(from_os_str))]
         input: PathBuf,
     },
+    #[structopt(name = "disasm", about = "prints a bakerVM image as BASM source", alias = "d")]
+    Disasm {
+        #[structopt(short = "o", parse(from_os_str),
+                    help = "Writes the source to the given file instead of stdout and the assets to a directory next to it")]
+        output: Option<PathBuf>,
+        #[structopt(parse(from_os_str))]
+        input: PathBuf,
+    },
+    #[structopt(name = "inspect",
+                about = "prints statistics about a bakerVM image and validates it", alias = "i")]
+    Inspect {
+        #[structopt(parse(from_os_str))]
+        input: PathBuf,
+    },
+    #[structopt(name = "upgrade", about = "rewrites a bakerVM image in the current image format",
+                alias = "u")]
+    Upgrade {
+        #[structopt(short = "o", parse(from_os_str),
+                    help = "Writes the upgraded image to the given file instead of replacing the \
+                            input")]
+        output: Option<PathBuf>,
+        #[structopt(parse(from_os_str))]
+        input: PathBuf,
+    },
+    #[structopt(name = "build", about = "builds the project described by a bakervm.toml manifest",
+                alias = "b")]
+    Build {
+        #[structopt(parse(from_os_str),
+                    help = "The manifest or the directory containing it. Defaults to the \
+                            nearest bakervm.toml in the current directory or its parents")]
+        path: Option<PathBuf>,
+        #[structopt(short = "w", long = "watch",
+                    help = "Rebuilds the project whenever one of its files changes")]
+        watch: bool,
+    },
 }
 
 fn run() -> Result<()> {
@@ -71,12 +143,42 @@ fn run() -> Result<()> {
             lang,
             input,
             output,
-        } => commands::compile(lang, input, output)?,
+            defines,
+            include_paths,
+            debug,
+            title,
+            resolution,
+            scale,
+            color_mode,
+            hide_cursor,
+            input_enabled,
+        } => {
+            let options = basm::Options {
+                defines,
+                include_paths,
+                debug,
+            };
+
+            let overrides = ConfigOverrides {
+                title,
+                resolution,
+                scale,
+                hide_cursor,
+                color_mode,
+                input_enabled,
+            };
+
+            commands::compile(lang, input, output, options, overrides)?
+        }
         Opt::Pack {
             packing_type,
             input,
             output,
         } => commands::pack(packing_type, input, output)?,
+        Opt::Disasm { input, output } => commands::disasm(input, output)?,
+        Opt::Inspect { input } => commands::inspect(input)?,
+        Opt::Upgrade { input, output } => commands::upgrade(input, output)?,
+        Opt::Build { path, watch } => commands::build(path, watch)?,
     }
 
     Ok(())
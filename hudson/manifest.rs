@@ -0,0 +1,213 @@
+//! The `bakervm.toml` project manifest read by `hudson build`
+
+use basm::Define;
+use commands::{ConfigOverrides, Lang, PackingType};
+use core::error::*;
+use serde::de::{self, Deserialize, Deserializer};
+use std::env;
+use std::fmt::Display;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use toml;
+
+pub const MANIFEST_FILE_NAME: &str = "bakervm.toml";
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    pub project: Project,
+    #[serde(default)]
+    pub config: ConfigOverrides,
+    #[serde(default, rename = "pack")]
+    pub pack_rules: Vec<PackRule>,
+}
+
+/// Describes how the image of the project is compiled
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Project {
+    pub entry: PathBuf,
+    #[serde(default, deserialize_with = "parse_optional")]
+    pub lang: Option<Lang>,
+    pub output: Option<PathBuf>,
+    #[serde(default)]
+    pub include: Vec<PathBuf>,
+    #[serde(default, deserialize_with = "parse_all")]
+    pub defines: Vec<Define>,
+    #[serde(default)]
+    pub debug: bool,
+}
+
+/// An image that is packed into a source file before compiling
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct PackRule {
+    pub input: PathBuf,
+    pub output: Option<PathBuf>,
+    #[serde(default, rename = "type", deserialize_with = "parse_optional")]
+    pub packing_type: Option<PackingType>,
+}
+
+impl FromStr for Manifest {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        toml::from_str(s).chain_err(|| "unable to parse manifest")
+    }
+}
+
+impl Manifest {
+    pub fn load(path: &Path) -> Result<Manifest> {
+        let mut file = File::open(path).chain_err(|| "unable to open manifest")?;
+
+        let mut buf = String::new();
+
+        file.read_to_string(&mut buf)
+            .chain_err(|| "unable to read manifest")?;
+
+        buf.parse()
+    }
+}
+
+/// Returns the path of the manifest to use. A given directory is searched for
+/// a manifest. Without a path, the current directory and its ancestors are
+/// searched.
+pub fn find(path: Option<PathBuf>) -> Result<PathBuf> {
+    if let Some(path) = path {
+        if path.is_dir() {
+            let manifest_path = path.join(MANIFEST_FILE_NAME);
+
+            ensure!(
+                manifest_path.is_file(),
+                "no {} found in {}",
+                MANIFEST_FILE_NAME,
+                path.display()
+            );
+
+            return Ok(manifest_path);
+        }
+
+        return Ok(path);
+    }
+
+    let current_dir = env::current_dir().chain_err(|| "unable to get current directory")?;
+
+    for dir in current_dir.ancestors() {
+        let manifest_path = dir.join(MANIFEST_FILE_NAME);
+
+        if manifest_path.is_file() {
+            return Ok(manifest_path);
+        }
+    }
+
+    bail!(
+        "no {} found in {} or any parent directory",
+        MANIFEST_FILE_NAME,
+        current_dir.display()
+    )
+}
+
+/// Parses an optional field from a string using its `FromStr` implementation
+pub fn parse_optional<'de, D, T>(deserializer: D) -> ::std::result::Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: Display,
+{
+    let text = String::deserialize(deserializer)?;
+
+    text.parse().map(Some).map_err(de::Error::custom)
+}
+
+/// Parses each string of a list using its `FromStr` implementation
+fn parse_all<'de, D, T>(deserializer: D) -> ::std::result::Result<Vec<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: Display,
+{
+    let texts: Vec<String> = Vec::deserialize(deserializer)?;
+
+    texts
+        .iter()
+        .map(|text| text.parse().map_err(de::Error::custom))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn manifest() {
+        let manifest: Manifest = r#"
+            [project]
+            entry = "src/main.basm"
+            output = "build/game.img"
+            include = ["lib"]
+            defines = ["DEBUG", "LEVEL=2"]
+
+            [config]
+            title = "My Game"
+            resolution = "320x200"
+            scale = 2.0
+
+            [[pack]]
+            input = "assets/player.png"
+            type = "dynamic"
+
+            [[pack]]
+            input = "assets/title.png"
+            output = "src/title.basm"
+        "#.parse()
+            .unwrap();
+
+        assert_eq!(manifest.project.entry, Path::new("src/main.basm"));
+        assert!(manifest.project.lang.is_none());
+        assert_eq!(manifest.project.include, vec![PathBuf::from("lib")]);
+        assert_eq!(manifest.project.defines.len(), 2);
+        assert_eq!(manifest.project.defines[1].name, "LEVEL");
+        assert_eq!(manifest.project.defines[1].value, "2");
+
+        assert_eq!(manifest.config.title, Some("My Game".into()));
+        assert_eq!(manifest.config.resolution.as_ref().unwrap().width, 320);
+        assert_eq!(manifest.config.scale, Some(2.0));
+        assert!(manifest.config.hide_cursor.is_none());
+
+        assert_eq!(manifest.pack_rules.len(), 2);
+
+        match manifest.pack_rules[0].packing_type {
+            Some(PackingType::Dynamic) => {}
+            ref other => panic!("unexpected packing type {:?}", other),
+        }
+
+        assert!(manifest.pack_rules[1].packing_type.is_none());
+        assert_eq!(
+            manifest.pack_rules[1].output,
+            Some(PathBuf::from("src/title.basm"))
+        );
+    }
+
+    #[test]
+    fn invalid_manifests() {
+        assert!("".parse::<Manifest>().is_err());
+        assert!("[project]\nentry = 1".parse::<Manifest>().is_err());
+        assert!(
+            "[project]\nentry = \"a.basm\"\nlang = \"cobol\""
+                .parse::<Manifest>()
+                .is_err()
+        );
+        assert!(
+            "[project]\nentry = \"a.basm\"\n[config]\nresolution = \"320\""
+                .parse::<Manifest>()
+                .is_err()
+        );
+        assert!(
+            "[project]\nentry = \"a.basm\"\nunknown = true"
+                .parse::<Manifest>()
+                .is_err()
+        );
+    }
+}
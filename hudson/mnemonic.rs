@@ -1,4 +1,5 @@
-use core::{Signal, Target, Type, Value};
+use core::{BlitOptions, BlitSource, Signal, Target, TilemapSource, Type, Value, Waveform};
+use core::typedef::*;
 #[derive(Clone, Debug)]
 pub enum Mnemonic {
     Add(Target, Target),
@@ -29,4 +30,39 @@ pub enum Mnemonic {
     Pause,
     Nop,
     Sig(Signal),
+
+    Read(Target, Address),
+    Blit(BlitSource, BlitOptions),
+
+    Clear,
+    Rect,
+    Line,
+    HSpan,
+    VSpan,
+
+    DrawChar(Option<Address>),
+    DrawText(Address, Option<Address>),
+
+    SetPalette,
+    RotatePalette,
+
+    Layer(Address),
+    Scroll(Address),
+    LayerKey(Address),
+
+    Tilemap(Address, TilemapSource, Address),
+    SetTile(Address),
+    GetTile(Address),
+    ScrollMap(Address),
+
+    Sprite(Address),
+    SpriteAttr(Address),
+    HideSprite(Address),
+    SpriteHit(Address, Option<Address>),
+    BgHit(Address),
+
+    Voice(Address, Waveform),
+    Tone(Address),
+    Envelope(Address),
+    NoteOff(Address),
 }
@@ -0,0 +1,366 @@
+//! Reading songs of the MOD tracker format and converting them into the
+//! commands of the audio channels
+
+use core::CHANNEL_COUNT;
+use core::error::*;
+use std::collections::BTreeMap;
+
+/// The number of instruments of a MOD file
+pub const INSTRUMENT_COUNT: usize = 31;
+
+/// The rate a MOD instrument plays at for the note C-2 in samples per second
+pub const INSTRUMENT_RATE: usize = 8363;
+
+/// The signatures of 31 instrument MOD files with 4 channels
+const SIGNATURES: &[&[u8]] = &[b"M.K.", b"M!K!", b"4CHN", b"FLT4"];
+
+const INSTRUMENT_HEADER_LEN: usize = 30;
+const ORDER_COUNT: usize = 128;
+const SONG_LENGTH_OFFSET: usize = 950;
+const ORDERS_OFFSET: usize = 952;
+const SIGNATURE_OFFSET: usize = 1080;
+const PATTERNS_OFFSET: usize = 1084;
+const PATTERN_ROWS: usize = 64;
+const CELL_LEN: usize = 4;
+const PATTERN_LEN: usize = PATTERN_ROWS * CHANNEL_COUNT * CELL_LEN;
+
+/// The clock of the Amiga the periods of a MOD file refer to
+pub const PAL_CLOCK: f64 = 7_093_789.2;
+
+/// The highest volume of a MOD instrument
+pub const MAX_VOLUME: u8 = 64;
+
+/// The ticks per row and beats per minute a song starts with
+const DEFAULT_SPEED: u32 = 6;
+const DEFAULT_TEMPO: u32 = 125;
+
+/// The frames per second the events of a song are timed in
+const FRAME_RATE: f64 = 60.0;
+
+/// An instrument of a MOD file
+#[derive(Debug, Clone, PartialEq)]
+pub struct Instrument {
+    pub name: String,
+    /// The volume between 0 and 64
+    pub volume: u8,
+    /// Signed 8 bit samples
+    pub data: Vec<i8>,
+}
+
+/// A command for an audio channel
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChannelCommand {
+    /// Plays an instrument, whose number starts at 1
+    Voice(usize, usize),
+    Tone {
+        channel: usize,
+        frequency: f64,
+        volume: f64,
+    },
+    NoteOff(usize),
+}
+
+/// A song of a MOD file
+#[derive(Debug, Clone, PartialEq)]
+pub struct Module {
+    pub title: String,
+    /// The instruments numbered from 1
+    pub instruments: Vec<Instrument>,
+    orders: Vec<usize>,
+    patterns: Vec<Vec<u8>>,
+}
+
+/// The channel commands of a song keyed by the frame they happen in and the
+/// length of the song in frames
+#[derive(Debug, Clone, PartialEq)]
+pub struct Song {
+    pub events: BTreeMap<usize, Vec<ChannelCommand>>,
+    pub frames: usize,
+    /// The number of effects that were skipped, as they are not supported
+    pub ignored_effects: usize,
+}
+
+impl Module {
+    /// Reads a MOD file with 31 instruments and 4 channels
+    pub fn parse(data: &[u8]) -> Result<Module> {
+        ensure!(
+            data.len() >= PATTERNS_OFFSET
+                && SIGNATURES.contains(&&data[SIGNATURE_OFFSET..PATTERNS_OFFSET]),
+            "only MOD files with 31 instruments and 4 channels are supported"
+        );
+
+        let song_length = data[SONG_LENGTH_OFFSET] as usize;
+        ensure!(
+            song_length > 0 && song_length <= ORDER_COUNT,
+            "the song length {} of the MOD file is invalid",
+            song_length
+        );
+
+        let all_orders = &data[ORDERS_OFFSET..ORDERS_OFFSET + ORDER_COUNT];
+        let pattern_count = all_orders.iter().cloned().max().unwrap_or(0) as usize + 1;
+
+        let mut offset = PATTERNS_OFFSET;
+        let mut patterns = Vec::with_capacity(pattern_count);
+
+        for _ in 0..pattern_count {
+            ensure!(
+                data.len() >= offset + PATTERN_LEN,
+                "the patterns of the MOD file are truncated"
+            );
+
+            patterns.push(data[offset..offset + PATTERN_LEN].to_vec());
+            offset += PATTERN_LEN;
+        }
+
+        let mut instruments = Vec::with_capacity(INSTRUMENT_COUNT);
+
+        for index in 0..INSTRUMENT_COUNT {
+            let header = &data[20 + index * INSTRUMENT_HEADER_LEN..];
+            let len = read_u16(&header[22..]) as usize * 2;
+
+            // Some files end in the middle of the last sample
+            let end = (offset + len).min(data.len());
+            let samples = data[offset.min(end)..end].iter().map(|&byte| byte as i8).collect();
+            offset = end;
+
+            instruments.push(Instrument {
+                name: read_text(&header[..22]),
+                volume: header[25].min(MAX_VOLUME),
+                data: samples,
+            });
+        }
+
+        Ok(Module {
+            title: read_text(&data[..20]),
+            instruments,
+            orders: all_orders[..song_length].iter().map(|&order| order as usize).collect(),
+            patterns,
+        })
+    }
+
+    /// Returns the instrument with the given number, which starts at 1
+    pub fn instrument(&self, number: usize) -> Result<&Instrument> {
+        if let Some(instrument) = number.checked_sub(1).and_then(|index| self.instruments.get(index)) {
+            Ok(instrument)
+        } else {
+            bail!(
+                "a MOD file has the instruments 1 to {}. Found {}",
+                INSTRUMENT_COUNT,
+                number
+            );
+        }
+    }
+
+    /// Plays the song through once, converting its notes into channel
+    /// commands. Supported are the effects `Bxx` (position jump, where jumps
+    /// backwards end the song), `Cxx` (set volume), `Dxx` (pattern break) and
+    /// `Fxx` (set speed and tempo). Looping instruments are played once.
+    pub fn song(&self) -> Song {
+        let mut events: BTreeMap<usize, Vec<ChannelCommand>> = BTreeMap::new();
+        let mut ignored_effects = 0;
+
+        let mut speed = DEFAULT_SPEED;
+        let mut tempo = DEFAULT_TEMPO;
+        let mut seconds = 0.0;
+
+        let mut instruments = [0; CHANNEL_COUNT];
+        let mut voices = [0; CHANNEL_COUNT];
+
+        let mut position = 0;
+        let mut start_row = 0;
+
+        'song: while position < self.orders.len() {
+            let pattern = &self.patterns[self.orders[position]];
+            let mut next = (position + 1, 0);
+
+            for row in start_row..PATTERN_ROWS {
+                let frame = (seconds * FRAME_RATE).round() as usize;
+                let mut jumped = false;
+                let mut stopped = false;
+
+                for channel in 0..CHANNEL_COUNT {
+                    let cell = &pattern[(row * CHANNEL_COUNT + channel) * CELL_LEN..];
+
+                    let instrument = (cell[0] & 0xf0 | cell[2] >> 4) as usize;
+                    let period = u32::from(cell[0] & 0x0f) << 8 | u32::from(cell[1]);
+                    let (effect, param) = (cell[2] & 0x0f, cell[3]);
+
+                    if instrument != 0 {
+                        instruments[channel] = instrument;
+                    }
+
+                    let mut volume = self.instruments
+                        .get(instruments[channel].wrapping_sub(1))
+                        .map_or(0, |instrument| instrument.volume);
+
+                    match (effect, param) {
+                        (0x0, 0) => {}
+                        (0xb, target) => {
+                            jumped = true;
+
+                            if target as usize > position {
+                                next = (target as usize, 0);
+                            } else {
+                                stopped = true;
+                            }
+                        }
+                        (0xc, level) => volume = level.min(MAX_VOLUME),
+                        (0xd, target) => {
+                            jumped = true;
+                            next = (position + 1, (target >> 4) as usize * 10 + (target & 0x0f) as usize);
+                        }
+                        (0xf, 0) => stopped = true,
+                        (0xf, ticks) if ticks < 32 => speed = u32::from(ticks),
+                        (0xf, bpm) => tempo = u32::from(bpm),
+                        _ => ignored_effects += 1,
+                    }
+
+                    let commands = events.entry(frame).or_default();
+
+                    let playable = self.instruments
+                        .get(instruments[channel].wrapping_sub(1))
+                        .is_some_and(|instrument| !instrument.data.is_empty());
+
+                    if period != 0 && playable {
+                        if voices[channel] != instruments[channel] {
+                            voices[channel] = instruments[channel];
+                            commands.push(ChannelCommand::Voice(channel, instruments[channel]));
+                        }
+
+                        commands.push(ChannelCommand::Tone {
+                            channel,
+                            frequency: PAL_CLOCK / f64::from(period * 2),
+                            volume: f64::from(volume) / f64::from(MAX_VOLUME),
+                        });
+                    } else if effect == 0xc && param == 0 {
+                        commands.push(ChannelCommand::NoteOff(channel));
+                    }
+                }
+
+                // One tick lasts 2.5 / tempo seconds, which is 1/50 of a
+                // second by default
+                seconds += f64::from(speed) * 2.5 / f64::from(tempo);
+
+                if stopped {
+                    break 'song;
+                }
+
+                if jumped {
+                    break;
+                }
+            }
+
+            position = next.0;
+            start_row = next.1.min(PATTERN_ROWS - 1);
+        }
+
+        events.retain(|_, commands| !commands.is_empty());
+
+        Song {
+            events,
+            frames: (seconds * FRAME_RATE).round() as usize,
+            ignored_effects,
+        }
+    }
+}
+
+fn read_u16(bytes: &[u8]) -> u16 {
+    u16::from(bytes[0]) << 8 | u16::from(bytes[1])
+}
+
+/// Reads a text padded with zeros
+fn read_text(bytes: &[u8]) -> String {
+    let len = bytes.iter().position(|&byte| byte == 0).unwrap_or(bytes.len());
+
+    String::from_utf8_lossy(&bytes[..len]).trim().to_owned()
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    /// Builds a MOD file with a single pattern, whose cells are given as
+    /// (row, channel, instrument, period, effect, param), and an instrument
+    /// holding 4 samples
+    pub fn build_mod(cells: &[(usize, usize, u8, u16, u8, u8)]) -> Vec<u8> {
+        let mut data = vec![0; PATTERNS_OFFSET + PATTERN_LEN];
+        data[..4].copy_from_slice(b"song");
+
+        let header = &mut data[20..20 + INSTRUMENT_HEADER_LEN];
+        header[..4].copy_from_slice(b"lead");
+        header[23] = 2;
+        header[25] = 32;
+
+        data[SONG_LENGTH_OFFSET] = 1;
+        data[SIGNATURE_OFFSET..PATTERNS_OFFSET].copy_from_slice(b"M.K.");
+
+        for &(row, channel, instrument, period, effect, param) in cells {
+            let offset = PATTERNS_OFFSET + (row * CHANNEL_COUNT + channel) * CELL_LEN;
+            data[offset] = instrument & 0xf0 | (period >> 8) as u8;
+            data[offset + 1] = period as u8;
+            data[offset + 2] = instrument << 4 | effect;
+            data[offset + 3] = param;
+        }
+
+        data.extend_from_slice(&[0, 64, 0x80, 0xff]);
+        data
+    }
+
+    #[test]
+    fn parse() {
+        let module = Module::parse(&build_mod(&[])).unwrap();
+
+        assert_eq!(module.title, "song");
+        assert_eq!(module.instruments.len(), INSTRUMENT_COUNT);
+        assert_eq!(
+            *module.instrument(1).unwrap(),
+            Instrument {
+                name: "lead".into(),
+                volume: 32,
+                data: vec![0, 64, -128, -1],
+            }
+        );
+        assert!(module.instrument(2).unwrap().data.is_empty());
+        assert!(module.instrument(0).is_err());
+
+        let mut data = build_mod(&[]);
+        data[SIGNATURE_OFFSET] = b'X';
+        assert!(Module::parse(&data).is_err());
+
+        data = build_mod(&[]);
+        data.truncate(PATTERNS_OFFSET + 10);
+        assert!(Module::parse(&data).is_err());
+    }
+
+    #[test]
+    fn song() {
+        let module = Module::parse(&build_mod(&[
+            (0, 0, 1, 428, 0x0, 0),
+            (0, 1, 2, 428, 0x0, 0),
+            (1, 0, 0, 214, 0xc, 64),
+            (1, 2, 0, 0, 0x8, 0x80),
+            (2, 0, 0, 0, 0xc, 0),
+            (2, 3, 0, 0, 0xf, 3),
+            (4, 0, 0, 0, 0xd, 0),
+        ])).unwrap();
+
+        let song = module.song();
+
+        let tone = |channel, frequency: f64, volume| ChannelCommand::Tone {
+            channel,
+            frequency,
+            volume,
+        };
+
+        let mut events = BTreeMap::new();
+        events.insert(0, vec![ChannelCommand::Voice(0, 1), tone(0, PAL_CLOCK / 856.0, 0.5)]);
+        events.insert(7, vec![tone(0, PAL_CLOCK / 428.0, 1.0)]);
+        events.insert(14, vec![ChannelCommand::NoteOff(0)]);
+
+        // The speed drops to 3 ticks per row after the third row and the
+        // pattern breaks after the fifth
+        assert_eq!(song.events, events);
+        assert_eq!(song.frames, 25);
+        assert_eq!(song.ignored_effects, 1);
+    }
+}